@@ -3,16 +3,23 @@
 use codec::{Encode, Decode};
 use frame_support::{
 	decl_module, decl_storage, decl_error, decl_event, ensure,
-	StorageValue, StorageMap,
+	StorageValue, StorageMap, StorageDoubleMap, IterableStorageMap, IterableStorageDoubleMap,
+	traits::{BalanceStatus, Get}, weights::Weight,
 };
 use frame_system::ensure_signed;
 use sp_runtime::{
-	traits::{One, Zero},
-	DispatchError, DispatchResult, RuntimeDebug,
+	traits::{AccountIdConversion, One, Zero, Saturating, SaturatedConversion},
+	DispatchError, DispatchResult, ModuleId, RuntimeDebug,
 };
 use sp_std::vec::Vec;
 use primitives::{CurrencyId};
 
+#[cfg(test)]
+mod mock;
+
+#[cfg(test)]
+mod tests;
+
 
 /// Currency info
 #[derive(Encode, Decode, Clone, Eq, PartialEq, RuntimeDebug)]
@@ -25,9 +32,66 @@ pub struct CurrencyInfo<AccountId, TokenId> {
 	pub total_supply: u128,
 }
 
+/// What an account may do with a currency.
+#[derive(Encode, Decode, Clone, Copy, Eq, PartialEq, RuntimeDebug)]
+pub enum CurrencyRole {
+	/// Accountable for the currency in circulation.
+	Issuer,
+	/// May mint new units.
+	Minter,
+	/// May grant and revoke roles and renounce minting.
+	Admin,
+}
+
 /// The pallet's configuration trait.
 pub trait Trait: frame_system::Trait + token::Trait {
 	type Event: From<Event<Self>> + Into<<Self as frame_system::Trait>::Event>;
+	/// Holds reserved balances.
+	type ModuleId: Get<ModuleId>;
+}
+
+/// Balances of several currencies, after `orml_traits::MultiCurrency`.
+pub trait MultiCurrency<AccountId> {
+	type CurrencyId;
+	type Balance;
+
+	/// The smallest balance an account may hold.
+	fn minimum_balance(currency_id: Self::CurrencyId) -> Self::Balance;
+	/// The amount of the currency in circulation.
+	fn total_issuance(currency_id: Self::CurrencyId) -> Self::Balance;
+	/// The free and reserved balance of `who`.
+	fn total_balance(currency_id: Self::CurrencyId, who: &AccountId) -> Self::Balance;
+	/// The balance of `who` that can be spent.
+	fn free_balance(currency_id: Self::CurrencyId, who: &AccountId) -> Self::Balance;
+	fn ensure_can_withdraw(currency_id: Self::CurrencyId, who: &AccountId, amount: Self::Balance) -> DispatchResult;
+	fn transfer(currency_id: Self::CurrencyId, from: &AccountId, to: &AccountId, amount: Self::Balance) -> DispatchResult;
+	/// Add `amount` to the balance of `who`, increasing issuance.
+	fn deposit(currency_id: Self::CurrencyId, who: &AccountId, amount: Self::Balance) -> DispatchResult;
+	/// Remove `amount` from the balance of `who`, decreasing issuance.
+	fn withdraw(currency_id: Self::CurrencyId, who: &AccountId, amount: Self::Balance) -> DispatchResult;
+	fn can_slash(currency_id: Self::CurrencyId, who: &AccountId, value: Self::Balance) -> bool;
+	/// Burn up to `amount` of the free balance of `who`, returning what could not be slashed.
+	fn slash(currency_id: Self::CurrencyId, who: &AccountId, amount: Self::Balance) -> Self::Balance;
+}
+
+/// Reservable balances of several currencies, after `orml_traits::MultiReservableCurrency`.
+pub trait MultiReservableCurrency<AccountId>: MultiCurrency<AccountId> {
+	fn can_reserve(currency_id: Self::CurrencyId, who: &AccountId, value: Self::Balance) -> bool;
+	/// Burn up to `value` of the reserved balance of `who`, returning what could not be slashed.
+	fn slash_reserved(currency_id: Self::CurrencyId, who: &AccountId, value: Self::Balance) -> Self::Balance;
+	fn reserved_balance(currency_id: Self::CurrencyId, who: &AccountId) -> Self::Balance;
+	/// Move `value` from the free to the reserved balance of `who`.
+	fn reserve(currency_id: Self::CurrencyId, who: &AccountId, value: Self::Balance) -> DispatchResult;
+	/// Move up to `value` back to the free balance of `who`, returning what could not be unreserved.
+	fn unreserve(currency_id: Self::CurrencyId, who: &AccountId, value: Self::Balance) -> Self::Balance;
+	/// Move up to `value` of the reserved balance of `slashed` to `beneficiary`, returning what could not be moved.
+	fn repatriate_reserved(
+		currency_id: Self::CurrencyId,
+		slashed: &AccountId,
+		beneficiary: &AccountId,
+		value: Self::Balance,
+		status: BalanceStatus,
+	) -> Result<Self::Balance, DispatchError>;
 }
 
 pub type CurrencyInfoOf<T> =
@@ -38,6 +102,26 @@ decl_storage! {
 	trait Store for Module<T: Trait> as TemplateModule {
 		pub Currencies get(fn currencies): map hasher(twox_64_concat) CurrencyId => Option<CurrencyInfoOf<T>>;
 		pub NextCurrencyId get(fn next_currency_id): CurrencyId;
+
+		/// Accounts holding each role of a currency.
+		pub Roles get(fn has_role):
+			double_map hasher(twox_64_concat) (CurrencyId, CurrencyRole), hasher(blake2_128_concat) T::AccountId => bool;
+		/// Currencies whose supply can never grow again.
+		pub MintingRenounced get(fn minting_renounced): map hasher(twox_64_concat) CurrencyId => bool;
+		/// Whether currencies created before roles existed have been given to their creators.
+		RolesMigrated: bool;
+		/// Whether `total_supply` of currencies created before it was tracked has been recounted.
+		SupplyMigrated: bool;
+		/// Whether tokens of currencies created as non-fungible have been reclassified.
+		FungibilityMigrated: bool;
+
+		/// How much of a holder's balance of a currency an issuer may still burn.
+		pub BurnAllowances get(fn burn_allowance):
+			double_map hasher(blake2_128_concat) (CurrencyId, T::AccountId), hasher(blake2_128_concat) T::AccountId => T::TokenBalance;
+
+		/// Reserved balances, held by the pallet account.
+		pub Reserves get(fn reserves):
+			double_map hasher(twox_64_concat) CurrencyId, hasher(blake2_128_concat) T::AccountId => T::TokenBalance;
 	}
 }
 
@@ -46,10 +130,21 @@ decl_event!(
 	pub enum Event<T> where
 		AccountId = <T as frame_system::Trait>::AccountId,
 		CurrencyId = CurrencyId,
+		CurrencyRole = CurrencyRole,
 		TokenBalance = <T as token::Trait>::TokenBalance,
 	{
 		Created(CurrencyId, AccountId),
 		Mint(CurrencyId, TokenBalance, AccountId),
+		Burn(CurrencyId, TokenBalance, AccountId),
+		Transferred(CurrencyId, AccountId, AccountId, TokenBalance),
+		RoleGranted(CurrencyId, CurrencyRole, AccountId),
+		RoleRevoked(CurrencyId, CurrencyRole, AccountId),
+		MintingRenounced(CurrencyId),
+		/// A holder let an account burn up to an amount of its balance.
+		BurnApproved(CurrencyId, AccountId, AccountId, TokenBalance),
+		/// The token of a currency could not be made fungible, the migration runs again on the
+		/// next upgrade.
+		FungibilityNotMigrated(CurrencyId),
 	}
 );
 
@@ -58,6 +153,16 @@ decl_error! {
 	pub enum Error for Module<T: Trait> {
 		NoneValue,
 		InvalidCurrencyId,
+		/// The sender lacks the role the call requires.
+		MissingRole,
+		/// Minting was renounced for the currency.
+		MintingRenounced,
+		/// The currency's total supply would overflow.
+		SupplyOverflow,
+		/// The free balance is too low.
+		BalanceTooLow,
+		/// The holder has not approved burning that much of its balance.
+		BurnNotApproved,
 	}
 }
 
@@ -68,13 +173,20 @@ decl_module! {
 
 		fn deposit_event() = default;
 
+		fn on_runtime_upgrade() -> Weight {
+			Self::migrate_roles()
+				.saturating_add(Self::migrate_supply())
+				.saturating_add(Self::migrate_fungibility())
+		}
+
+		/// Create a currency, the creator holding all its roles.
 		#[weight = 0]
 		pub fn create(origin, token_uri: Vec<u8>) -> DispatchResult {
 			let who = ensure_signed(origin)?;
 
 			let currency_id = Self::next_currency_id();
 	
-			let token_id = token::Module::<T>::create_token(&who, true, &token_uri)?;
+			let token_id = token::Module::<T>::create_token(&who, false, &token_uri)?;
 	
 			let new_currency_info = CurrencyInfo {
 				creator: who.clone(),
@@ -84,20 +196,117 @@ decl_module! {
 
 			Currencies::<T>::insert(currency_id, new_currency_info);
 			NextCurrencyId::mutate(|id| *id += <u64 as One>::one());
+			Self::grant_all_roles(currency_id, &who);
 
 			Self::deposit_event(RawEvent::Created(currency_id, who));
 			Ok(())
 		}
 
+		/// Mint `amount` of a currency to `to`. Minters only.
 		#[weight = 0]
 		pub fn mint(origin, currency_id: CurrencyId, amount: T::TokenBalance, to: T::AccountId) -> DispatchResult {
 			let who = ensure_signed(origin)?;
 
-			let currency = Self::currencies(currency_id).ok_or(Error::<T>::InvalidCurrencyId)?;
+			ensure!(Currencies::<T>::contains_key(currency_id), Error::<T>::InvalidCurrencyId);
+			ensure!(!Self::minting_renounced(currency_id), Error::<T>::MintingRenounced);
+			ensure!(Self::has_role((currency_id, CurrencyRole::Minter), &who), Error::<T>::MissingRole);
 
-			token::Module::<T>::mint(&to, &currency.token, amount)?;
+			Self::do_mint(&to, currency_id, amount)
+		}
 
-			Self::deposit_event(RawEvent::Mint(currency_id, amount, to));
+		/// Burn `amount` of the sender's own balance of a currency.
+		#[weight = 0]
+		pub fn burn(origin, currency_id: CurrencyId, amount: T::TokenBalance) -> DispatchResult {
+			let who = ensure_signed(origin)?;
+
+			Self::do_burn(&who, currency_id, amount)
+		}
+
+		/// Burn `amount` of a currency held by `from`, out of what `from` approved. Issuers only.
+		///
+		/// Pallet accounts, such as the reserve account and exchange vaults, never sign and so
+		/// can never be burnt from.
+		#[weight = 0]
+		pub fn burn_from(origin, currency_id: CurrencyId, from: T::AccountId, amount: T::TokenBalance) -> DispatchResult {
+			let who = ensure_signed(origin)?;
+			Self::ensure_role(currency_id, CurrencyRole::Issuer, &who)?;
+
+			let allowance = Self::burn_allowance((currency_id, &from), &who);
+			ensure!(allowance >= amount, Error::<T>::BurnNotApproved);
+
+			Self::do_burn(&from, currency_id, amount)?;
+			BurnAllowances::<T>::insert((currency_id, &from), &who, allowance - amount);
+
+			Ok(())
+		}
+
+		/// Let `burner` burn up to `amount` of the sender's balance of a currency, replacing
+		/// any earlier approval.
+		#[weight = 0]
+		pub fn approve_burn(origin, currency_id: CurrencyId, burner: T::AccountId, amount: T::TokenBalance) -> DispatchResult {
+			let who = ensure_signed(origin)?;
+			ensure!(Currencies::<T>::contains_key(currency_id), Error::<T>::InvalidCurrencyId);
+
+			BurnAllowances::<T>::insert((currency_id, &who), &burner, amount);
+
+			Self::deposit_event(RawEvent::BurnApproved(currency_id, who, burner, amount));
+
+			Ok(())
+		}
+
+		/// Transfer `amount` of a currency from the sender to `to`.
+		#[weight = 0]
+		pub fn transfer(origin, currency_id: CurrencyId, to: T::AccountId, amount: T::TokenBalance) -> DispatchResult {
+			let who = ensure_signed(origin)?;
+
+			<Self as MultiCurrency<T::AccountId>>::transfer(currency_id, &who, &to, amount)?;
+
+			Self::deposit_event(RawEvent::Transferred(currency_id, who, to, amount));
+
+			Ok(())
+		}
+
+		/// Give `who` a role on a currency. Admins only.
+		#[weight = 0]
+		pub fn grant_role(origin, currency_id: CurrencyId, role: CurrencyRole, who: T::AccountId) -> DispatchResult {
+			let sender = ensure_signed(origin)?;
+			Self::ensure_role(currency_id, CurrencyRole::Admin, &sender)?;
+			ensure!(
+				role != CurrencyRole::Minter || !Self::minting_renounced(currency_id),
+				Error::<T>::MintingRenounced
+			);
+
+			Roles::<T>::insert((currency_id, role), &who, true);
+
+			Self::deposit_event(RawEvent::RoleGranted(currency_id, role, who));
+
+			Ok(())
+		}
+
+		/// Take a role on a currency from `who`. Admins only, and may leave a currency without admins.
+		#[weight = 0]
+		pub fn revoke_role(origin, currency_id: CurrencyId, role: CurrencyRole, who: T::AccountId) -> DispatchResult {
+			let sender = ensure_signed(origin)?;
+			Self::ensure_role(currency_id, CurrencyRole::Admin, &sender)?;
+
+			Roles::<T>::remove((currency_id, role), &who);
+
+			Self::deposit_event(RawEvent::RoleRevoked(currency_id, role, who));
+
+			Ok(())
+		}
+
+		/// Stop all minting of a currency for good, revoking every minter. Admins only.
+		#[weight = 0]
+		pub fn renounce_minting(origin, currency_id: CurrencyId) -> DispatchResult {
+			let sender = ensure_signed(origin)?;
+			Self::ensure_role(currency_id, CurrencyRole::Admin, &sender)?;
+			ensure!(!Self::minting_renounced(currency_id), Error::<T>::MintingRenounced);
+
+			MintingRenounced::insert(currency_id, true);
+			Roles::<T>::remove_prefix((currency_id, CurrencyRole::Minter));
+
+			Self::deposit_event(RawEvent::MintingRenounced(currency_id));
 
 			Ok(())
 		}
@@ -106,6 +315,120 @@ decl_module! {
 }
 
 impl<T: Trait> Module<T> {
+	fn ensure_role(currency_id: CurrencyId, role: CurrencyRole, who: &T::AccountId) -> DispatchResult {
+		ensure!(Currencies::<T>::contains_key(currency_id), Error::<T>::InvalidCurrencyId);
+		ensure!(Self::has_role((currency_id, role), who), Error::<T>::MissingRole);
+		Ok(())
+	}
+
+	fn grant_all_roles(currency_id: CurrencyId, who: &T::AccountId) {
+		for role in &[CurrencyRole::Issuer, CurrencyRole::Minter, CurrencyRole::Admin] {
+			Roles::<T>::insert((currency_id, *role), who, true);
+		}
+	}
+
+	/// Gives currencies created before roles existed to their creators, once.
+	fn migrate_roles() -> Weight {
+		if RolesMigrated::get() {
+			return 0;
+		}
+
+		let mut count: Weight = 0;
+		for (currency_id, currency) in Currencies::<T>::iter() {
+			count += 1;
+			let has_roles = [CurrencyRole::Issuer, CurrencyRole::Minter, CurrencyRole::Admin]
+				.iter()
+				.any(|role| Roles::<T>::iter_prefix((currency_id, *role)).next().is_some());
+			if !has_roles {
+				Self::grant_all_roles(currency_id, &currency.creator);
+			}
+		}
+		RolesMigrated::put(true);
+
+		T::DbWeight::get().reads_writes(count * 4 + 1, count * 3 + 1)
+	}
+
+	/// Recounts the supply of every currency from its holders' balances, once.
+	fn migrate_supply() -> Weight {
+		if SupplyMigrated::get() {
+			return 0;
+		}
+
+		let mut reads: Weight = 0;
+		let mut writes: Weight = 1;
+		for (currency_id, mut currency) in Currencies::<T>::iter() {
+			reads += 1;
+			let mut total_supply: u128 = 0;
+			for balance in token::Balances::<T>::iter_prefix_values(currency.token) {
+				reads += 1;
+				total_supply = total_supply.saturating_add(balance.saturated_into::<u128>());
+			}
+			if currency.total_supply != total_supply {
+				currency.total_supply = total_supply;
+				Currencies::<T>::insert(currency_id, currency);
+				writes += 1;
+			}
+		}
+		SupplyMigrated::put(true);
+
+		T::DbWeight::get().reads_writes(reads + 1, writes)
+	}
+
+	/// Marks the tokens of currencies created before they were fungible as fungible, once
+	/// every one of them succeeded.
+	fn migrate_fungibility() -> Weight {
+		if FungibilityMigrated::get() {
+			return 0;
+		}
+
+		let mut count: Weight = 0;
+		let mut failed: Weight = 0;
+		for (currency_id, currency) in Currencies::<T>::iter() {
+			count += 1;
+			if token::Module::<T>::set_fungible(&currency.token, true).is_err() {
+				failed += 1;
+				Self::deposit_event(RawEvent::FungibilityNotMigrated(currency_id));
+			}
+		}
+		if failed == 0 {
+			FungibilityMigrated::put(true);
+		}
+
+		T::DbWeight::get().reads_writes(count * 2 + 1, count + 1)
+	}
+
+	/// The account holding reserved balances.
+	pub fn account_id() -> T::AccountId {
+		T::ModuleId::get().into_account()
+	}
+
+	fn do_mint(to: &T::AccountId, currency_id: CurrencyId, amount: T::TokenBalance) -> DispatchResult {
+		let mut currency = Self::currencies(currency_id).ok_or(Error::<T>::InvalidCurrencyId)?;
+
+		currency.total_supply = currency.total_supply
+			.checked_add(amount.saturated_into::<u128>())
+			.ok_or(Error::<T>::SupplyOverflow)?;
+
+		token::Module::<T>::mint(to, &currency.token, amount)?;
+		Currencies::<T>::insert(currency_id, currency);
+
+		Self::deposit_event(RawEvent::Mint(currency_id, amount, to.clone()));
+
+		Ok(())
+	}
+
+	fn do_burn(from: &T::AccountId, currency_id: CurrencyId, amount: T::TokenBalance) -> DispatchResult {
+		let mut currency = Self::currencies(currency_id).ok_or(Error::<T>::InvalidCurrencyId)?;
+
+		token::Module::<T>::burn(from, &currency.token, amount)?;
+		currency.total_supply = currency.total_supply.saturating_sub(amount.saturated_into::<u128>());
+		Currencies::<T>::insert(currency_id, currency);
+
+		Self::deposit_event(RawEvent::Burn(currency_id, amount, from.clone()));
+
+		Ok(())
+	}
+
 	pub fn get_currency_token(currency_id: &CurrencyId) -> Result<T::TokenId, DispatchError> {
 		let currency = Self::currencies(currency_id).ok_or(Error::<T>::InvalidCurrencyId)?;
 		Ok(currency.token)
@@ -124,3 +447,117 @@ impl<T: Trait> Module<T> {
 		Ok(())
 	}
 }
+
+impl<T: Trait> MultiCurrency<T::AccountId> for Module<T> {
+	type CurrencyId = CurrencyId;
+	type Balance = T::TokenBalance;
+
+	fn minimum_balance(_currency_id: CurrencyId) -> T::TokenBalance {
+		Zero::zero()
+	}
+
+	fn total_issuance(currency_id: CurrencyId) -> T::TokenBalance {
+		Self::currencies(currency_id).map_or(Zero::zero(), |currency| currency.total_supply.into())
+	}
+
+	fn total_balance(currency_id: CurrencyId, who: &T::AccountId) -> T::TokenBalance {
+		Self::free_balance(currency_id, who).saturating_add(Self::reserves(currency_id, who))
+	}
+
+	fn free_balance(currency_id: CurrencyId, who: &T::AccountId) -> T::TokenBalance {
+		Self::currencies(currency_id)
+			.map_or(Zero::zero(), |currency| token::Module::<T>::balance_of(who, &currency.token))
+	}
+
+	fn ensure_can_withdraw(currency_id: CurrencyId, who: &T::AccountId, amount: T::TokenBalance) -> DispatchResult {
+		ensure!(Currencies::<T>::contains_key(currency_id), Error::<T>::InvalidCurrencyId);
+		ensure!(Self::free_balance(currency_id, who) >= amount, Error::<T>::BalanceTooLow);
+		Ok(())
+	}
+
+	fn transfer(currency_id: CurrencyId, from: &T::AccountId, to: &T::AccountId, amount: T::TokenBalance) -> DispatchResult {
+		Self::ensure_can_withdraw(currency_id, from, amount)?;
+		Self::do_transfer_from(from, to, &currency_id, amount)
+	}
+
+	fn deposit(currency_id: CurrencyId, who: &T::AccountId, amount: T::TokenBalance) -> DispatchResult {
+		Self::do_mint(who, currency_id, amount)
+	}
+
+	fn withdraw(currency_id: CurrencyId, who: &T::AccountId, amount: T::TokenBalance) -> DispatchResult {
+		Self::ensure_can_withdraw(currency_id, who, amount)?;
+		Self::do_burn(who, currency_id, amount)
+	}
+
+	fn can_slash(currency_id: CurrencyId, who: &T::AccountId, value: T::TokenBalance) -> bool {
+		Self::free_balance(currency_id, who) >= value
+	}
+
+	fn slash(currency_id: CurrencyId, who: &T::AccountId, amount: T::TokenBalance) -> T::TokenBalance {
+		let actual = Self::free_balance(currency_id, who).min(amount);
+		if Self::do_burn(who, currency_id, actual).is_err() {
+			return amount;
+		}
+		amount - actual
+	}
+}
+
+impl<T: Trait> MultiReservableCurrency<T::AccountId> for Module<T> {
+	fn can_reserve(currency_id: CurrencyId, who: &T::AccountId, value: T::TokenBalance) -> bool {
+		Self::free_balance(currency_id, who) >= value
+	}
+
+	fn slash_reserved(currency_id: CurrencyId, who: &T::AccountId, value: T::TokenBalance) -> T::TokenBalance {
+		let actual = Self::reserves(currency_id, who).min(value);
+		if Self::do_burn(&Self::account_id(), currency_id, actual).is_err() {
+			return value;
+		}
+		Reserves::<T>::mutate(currency_id, who, |reserved| *reserved -= actual);
+		value - actual
+	}
+
+	fn reserved_balance(currency_id: CurrencyId, who: &T::AccountId) -> T::TokenBalance {
+		Self::reserves(currency_id, who)
+	}
+
+	fn reserve(currency_id: CurrencyId, who: &T::AccountId, value: T::TokenBalance) -> DispatchResult {
+		Self::ensure_can_withdraw(currency_id, who, value)?;
+		Self::do_transfer_from(who, &Self::account_id(), &currency_id, value)?;
+		Reserves::<T>::mutate(currency_id, who, |reserved| *reserved = reserved.saturating_add(value));
+		Ok(())
+	}
+
+	fn unreserve(currency_id: CurrencyId, who: &T::AccountId, value: T::TokenBalance) -> T::TokenBalance {
+		let actual = Self::reserves(currency_id, who).min(value);
+		if Self::do_transfer_from(&Self::account_id(), who, &currency_id, actual).is_err() {
+			return value;
+		}
+		Reserves::<T>::mutate(currency_id, who, |reserved| *reserved -= actual);
+		value - actual
+	}
+
+	fn repatriate_reserved(
+		currency_id: CurrencyId,
+		slashed: &T::AccountId,
+		beneficiary: &T::AccountId,
+		value: T::TokenBalance,
+		status: BalanceStatus,
+	) -> Result<T::TokenBalance, DispatchError> {
+		ensure!(Currencies::<T>::contains_key(currency_id), Error::<T>::InvalidCurrencyId);
+		if slashed == beneficiary {
+			return match status {
+				BalanceStatus::Free => Ok(Self::unreserve(currency_id, slashed, value)),
+				BalanceStatus::Reserved => Ok(value.saturating_sub(Self::reserves(currency_id, slashed))),
+			};
+		}
+
+		let actual = Self::reserves(currency_id, slashed).min(value);
+		match status {
+			BalanceStatus::Free => Self::do_transfer_from(&Self::account_id(), beneficiary, &currency_id, actual)?,
+			BalanceStatus::Reserved => Reserves::<T>::mutate(currency_id, beneficiary, |reserved| *reserved = reserved.saturating_add(actual)),
+		}
+		Reserves::<T>::mutate(currency_id, slashed, |reserved| *reserved -= actual);
+
+		Ok(value - actual)
+	}
+}
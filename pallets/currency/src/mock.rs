@@ -0,0 +1,88 @@
+use crate::{Module, Trait};
+
+use sp_core::H256;
+use frame_support::{impl_outer_origin, impl_outer_event, parameter_types, weights::Weight};
+use sp_runtime::{
+    traits::{BlakeTwo256, IdentityLookup}, testing::Header, Perbill, ModuleId,
+};
+use frame_system as system;
+
+impl_outer_origin! {
+    pub enum Origin for Test where system = frame_system {}
+}
+
+mod currency {
+    pub use super::super::*;
+}
+
+impl_outer_event! {
+    pub enum TestEvent for Test {
+        system<T>,
+        token<T>,
+        currency<T>,
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Test;
+parameter_types! {
+    pub const BlockHashCount: u64 = 250;
+    pub const MaximumBlockWeight: Weight = 1024;
+    pub const MaximumBlockLength: u32 = 2 * 1024;
+    pub const AvailableBlockRatio: Perbill = Perbill::from_percent(75);
+}
+impl system::Trait for Test {
+    type Origin = Origin;
+    type Call = ();
+    type Index = u64;
+    type BlockNumber = u64;
+    type Hash = H256;
+    type Hashing = BlakeTwo256;
+    type AccountId = u64;
+    type Lookup = IdentityLookup<Self::AccountId>;
+    type Header = Header;
+    type Event = TestEvent;
+    type BlockHashCount = BlockHashCount;
+    type MaximumBlockWeight = MaximumBlockWeight;
+    type DbWeight = ();
+    type BlockExecutionWeight = ();
+    type ExtrinsicBaseWeight = ();
+    type MaximumExtrinsicWeight = MaximumBlockWeight;
+    type MaximumBlockLength = MaximumBlockLength;
+    type AvailableBlockRatio = AvailableBlockRatio;
+    type Version = ();
+    type SystemWeightInfo = ();
+    type PalletInfo = ();
+    type AccountData = ();
+    type OnNewAccount = ();
+    type OnKilledAccount = ();
+    type BaseCallFilter = ();
+}
+
+impl token::Trait for Test {
+    type Event = TestEvent;
+    type TokenBalance = u128;
+    type TokenId = u64;
+}
+
+parameter_types! {
+    pub const CurrencyModuleId: ModuleId = ModuleId(*b"spr/curr");
+}
+
+impl Trait for Test {
+    type Event = TestEvent;
+    type ModuleId = CurrencyModuleId;
+}
+
+pub type Currency = Module<Test>;
+pub type Token = token::Module<Test>;
+pub type System = frame_system::Module<Test>;
+
+pub fn new_test_ext() -> sp_io::TestExternalities {
+    let t = system::GenesisConfig::default()
+        .build_storage::<Test>()
+        .unwrap();
+    let mut ext: sp_io::TestExternalities = t.into();
+    ext.execute_with(|| System::set_block_number(1));
+    ext
+}
@@ -0,0 +1,250 @@
+use crate::mock::*;
+use super::*;
+use frame_support::{assert_ok, assert_noop, traits::OnRuntimeUpgrade};
+
+fn supply_of_balances(currency_id: CurrencyId) -> u128 {
+	let token = Currency::get_currency_token(&currency_id).unwrap();
+	token::Balances::<Test>::iter_prefix_values(token).sum()
+}
+
+#[test]
+fn roles_gate_minting() {
+	new_test_ext().execute_with(|| {
+		assert_ok!(Currency::create(Origin::signed(1), [0].to_vec()));
+		assert!(Currency::has_role((0, CurrencyRole::Issuer), &1));
+		assert!(Currency::has_role((0, CurrencyRole::Minter), &1));
+		assert!(Currency::has_role((0, CurrencyRole::Admin), &1));
+
+		assert_noop!(Currency::mint(Origin::signed(2), 0, 100, 2), Error::<Test>::MissingRole);
+		assert_noop!(
+			Currency::grant_role(Origin::signed(2), 0, CurrencyRole::Minter, 2),
+			Error::<Test>::MissingRole
+		);
+		assert_noop!(
+			Currency::grant_role(Origin::signed(1), 1, CurrencyRole::Minter, 2),
+			Error::<Test>::InvalidCurrencyId
+		);
+		assert_ok!(Currency::grant_role(Origin::signed(1), 0, CurrencyRole::Minter, 2));
+		assert_ok!(Currency::mint(Origin::signed(2), 0, 100, 3));
+		assert_eq!(Token::balance_of(&3, &0), 100);
+
+		assert_noop!(
+			Currency::revoke_role(Origin::signed(2), 0, CurrencyRole::Admin, 1),
+			Error::<Test>::MissingRole
+		);
+		assert_ok!(Currency::revoke_role(Origin::signed(1), 0, CurrencyRole::Minter, 2));
+		assert_noop!(Currency::mint(Origin::signed(2), 0, 100, 3), Error::<Test>::MissingRole);
+
+		assert_noop!(Currency::renounce_minting(Origin::signed(2), 0), Error::<Test>::MissingRole);
+		assert_ok!(Currency::renounce_minting(Origin::signed(1), 0));
+		assert!(!Currency::has_role((0, CurrencyRole::Minter), &1));
+		assert_noop!(Currency::mint(Origin::signed(1), 0, 1, 1), Error::<Test>::MintingRenounced);
+		assert_noop!(
+			Currency::grant_role(Origin::signed(1), 0, CurrencyRole::Minter, 4),
+			Error::<Test>::MintingRenounced
+		);
+		assert_noop!(Currency::renounce_minting(Origin::signed(1), 0), Error::<Test>::MintingRenounced);
+	});
+}
+
+#[test]
+fn migration_gives_roles_to_creators() {
+	new_test_ext().execute_with(|| {
+		assert_ok!(Currency::create(Origin::signed(1), [0].to_vec()));
+		for role in &[CurrencyRole::Issuer, CurrencyRole::Minter, CurrencyRole::Admin] {
+			Roles::<Test>::remove((0, *role), &1);
+		}
+		assert_noop!(Currency::mint(Origin::signed(1), 0, 100, 2), Error::<Test>::MissingRole);
+
+		Currency::on_runtime_upgrade();
+		assert!(Currency::has_role((0, CurrencyRole::Admin), &1));
+		assert_ok!(Currency::mint(Origin::signed(1), 0, 100, 2));
+	});
+}
+
+#[test]
+fn burning_needs_issuer_role_for_other_accounts() {
+	new_test_ext().execute_with(|| {
+		assert_ok!(Currency::create(Origin::signed(1), [0].to_vec()));
+		assert_ok!(Currency::mint(Origin::signed(1), 0, 100, 3));
+		assert_eq!(Currency::currencies(0).unwrap().total_supply, 100);
+
+		assert_noop!(Currency::burn_from(Origin::signed(2), 0, 3, 10), Error::<Test>::MissingRole);
+		assert_noop!(Currency::burn_from(Origin::signed(1), 0, 3, 10), Error::<Test>::BurnNotApproved);
+		assert_ok!(Currency::approve_burn(Origin::signed(3), 0, 1, 15));
+		assert_ok!(Currency::burn_from(Origin::signed(1), 0, 3, 10));
+		assert_noop!(Currency::burn_from(Origin::signed(1), 0, 3, 6), Error::<Test>::BurnNotApproved);
+		assert_eq!(Currency::burn_allowance((0, 3), 1), 5);
+		assert_ok!(Currency::burn(Origin::signed(3), 0, 20));
+		assert_noop!(Currency::burn(Origin::signed(3), 0, 71), token::Error::<Test>::NumOverflow);
+		assert_eq!(Token::balance_of(&3, &0), 70);
+		assert_eq!(Currency::currencies(0).unwrap().total_supply, 70);
+	});
+}
+
+#[test]
+fn currencies_are_fungible() {
+	new_test_ext().execute_with(|| {
+		assert_ok!(Currency::create(Origin::signed(1), [0].to_vec()));
+		assert!(Token::is_fungible(&0));
+		assert_ok!(Currency::mint(Origin::signed(1), 0, 100, 2));
+
+		// Currencies created before were flagged non-fungible.
+		assert_ok!(Token::set_fungible(&0, false));
+		assert_noop!(Currency::mint(Origin::signed(1), 0, 1, 2), token::Error::<Test>::NonFungibleAmount);
+
+		Currency::on_runtime_upgrade();
+		assert!(Token::is_fungible(&0));
+		assert_eq!(Currency::currencies(0).unwrap().total_supply, 100);
+		assert_ok!(Currency::mint(Origin::signed(1), 0, 100, 3));
+	});
+}
+
+#[test]
+fn fungibility_migration_retries_currencies_it_could_not_fix() {
+	new_test_ext().execute_with(|| {
+		assert_ok!(Currency::create(Origin::signed(1), [0].to_vec()));
+		let token = Currency::get_currency_token(&0).unwrap();
+		token::Tokens::<Test>::remove(token);
+
+		let not_migrated = TestEvent::currency(RawEvent::FungibilityNotMigrated(0));
+		Currency::on_runtime_upgrade();
+		assert!(System::events().into_iter().any(|r| r.event == not_migrated));
+		assert!(!FungibilityMigrated::get());
+
+		System::reset_events();
+		Currency::on_runtime_upgrade();
+		assert!(System::events().into_iter().any(|r| r.event == not_migrated));
+	});
+}
+
+#[test]
+fn refused_transfers_leave_the_sender_whole() {
+	new_test_ext().execute_with(|| {
+		// A token spread over several holders before it was made non-fungible.
+		let nft = Token::create_token(&1, false, &vec![]).unwrap();
+		assert_ok!(Token::mint(&1, &nft, 1));
+		assert_ok!(Token::mint(&2, &nft, 1));
+		assert_ok!(Token::set_fungible(&nft, false));
+		assert_noop!(Token::transfer_from(&1, &2, &nft, 1), token::Error::<Test>::NonFungibleAmount);
+		assert_eq!(Token::balance_of(&1, &nft), 1);
+
+		assert_ok!(Currency::create(Origin::signed(1), [0].to_vec()));
+		assert_ok!(Currency::mint(Origin::signed(1), 0, 100, 1));
+		let token = Currency::get_currency_token(&0).unwrap();
+		assert_noop!(
+			Token::batch_transfer_from(&1, &2, &vec![token, nft], vec![50, 1]),
+			token::Error::<Test>::NonFungibleAmount,
+		);
+		assert_eq!(Token::balance_of(&1, &token), 100);
+	});
+}
+
+#[test]
+fn non_fungible_tokens_are_minted_once() {
+	new_test_ext().execute_with(|| {
+		let nft = Token::create_token(&1, true, &vec![]).unwrap();
+		assert_noop!(Token::mint(&1, &nft, 2), token::Error::<Test>::NonFungibleAmount);
+		assert_ok!(Token::mint(&1, &nft, 1));
+		assert_noop!(Token::mint(&2, &nft, 1), token::Error::<Test>::NonFungibleAmount);
+		assert_noop!(Token::batch_mint(&2, &vec![nft], vec![1]), token::Error::<Test>::NonFungibleAmount);
+		assert_eq!(Token::total_supply(nft), 1);
+
+		// Once burnt it may be minted again.
+		assert_ok!(Token::burn(&1, &nft, 1));
+		assert_ok!(Token::mint(&2, &nft, 1));
+		assert_eq!(Token::total_supply(nft), 1);
+	});
+}
+
+#[test]
+fn transfer_and_reserve_through_multi_currency() {
+	new_test_ext().execute_with(|| {
+		assert_ok!(Currency::create(Origin::signed(1), [0].to_vec()));
+		assert_ok!(Currency::mint(Origin::signed(1), 0, 100, 2));
+
+		assert_ok!(Currency::transfer(Origin::signed(2), 0, 3, 40));
+		assert_noop!(Currency::transfer(Origin::signed(3), 0, 2, 41), Error::<Test>::BalanceTooLow);
+		assert_eq!(<Currency as MultiCurrency<u64>>::free_balance(0, &3), 40);
+
+		assert_ok!(<Currency as MultiReservableCurrency<u64>>::reserve(0, &2, 50));
+		assert_noop!(<Currency as MultiReservableCurrency<u64>>::reserve(0, &2, 11), Error::<Test>::BalanceTooLow);
+		assert_eq!(<Currency as MultiCurrency<u64>>::free_balance(0, &2), 10);
+		assert_eq!(<Currency as MultiCurrency<u64>>::total_balance(0, &2), 60);
+		assert_noop!(Currency::transfer(Origin::signed(2), 0, 3, 11), Error::<Test>::BalanceTooLow);
+
+		assert_eq!(<Currency as MultiReservableCurrency<u64>>::unreserve(0, &2, 20), 0);
+		assert_eq!(
+			<Currency as MultiReservableCurrency<u64>>::repatriate_reserved(0, &2, &3, 40, BalanceStatus::Reserved),
+			Ok(10)
+		);
+		assert_eq!(<Currency as MultiReservableCurrency<u64>>::reserved_balance(0, &3), 30);
+		assert_eq!(<Currency as MultiReservableCurrency<u64>>::slash_reserved(0, &3, 50), 20);
+
+		assert_eq!(<Currency as MultiCurrency<u64>>::total_issuance(0), 70);
+		assert_eq!(Currency::currencies(0).unwrap().total_supply, supply_of_balances(0));
+	});
+}
+
+#[test]
+fn issuers_cannot_burn_reserved_balances() {
+	new_test_ext().execute_with(|| {
+		assert_ok!(Currency::create(Origin::signed(1), [0].to_vec()));
+		assert_ok!(Currency::mint(Origin::signed(1), 0, 100, 2));
+		assert_ok!(<Currency as MultiReservableCurrency<u64>>::reserve(0, &2, 50));
+
+		let reserve = Currency::account_id();
+		assert_noop!(Currency::burn_from(Origin::signed(1), 0, reserve, 50), Error::<Test>::BurnNotApproved);
+		assert_eq!(Token::balance_of(&reserve, &0), 50);
+		assert_eq!(<Currency as MultiReservableCurrency<u64>>::unreserve(0, &2, 50), 0);
+	});
+}
+
+mod fuzz {
+	use super::*;
+	use proptest::prelude::*;
+
+	#[derive(Clone, Debug)]
+	enum Op {
+		Mint(u64, u128),
+		Burn(u64, u128),
+		BurnFrom(u64, u128),
+		Transfer(u64, u64, u128),
+	}
+
+	fn amount() -> impl Strategy<Value = u128> {
+		prop_oneof![0u128..1_000, any::<u128>()]
+	}
+
+	fn op() -> impl Strategy<Value = Op> {
+		let who = 1u64..4;
+		prop_oneof![
+			(who.clone(), amount()).prop_map(|(w, a)| Op::Mint(w, a)),
+			(who.clone(), amount()).prop_map(|(w, a)| Op::Burn(w, a)),
+			(who.clone(), amount()).prop_map(|(w, a)| Op::BurnFrom(w, a)),
+			(who.clone(), who, amount()).prop_map(|(f, t, a)| Op::Transfer(f, t, a)),
+		]
+	}
+
+	proptest! {
+		#![proptest_config(ProptestConfig::with_cases(64))]
+
+		#[test]
+		fn total_supply_equals_sum_of_balances(ops in prop::collection::vec(op(), 1..32)) {
+			new_test_ext().execute_with(|| {
+				assert_ok!(Currency::create(Origin::signed(1), [0].to_vec()));
+
+				for op in &ops {
+					let _ = match *op {
+						Op::Mint(to, a) => Currency::mint(Origin::signed(1), 0, a, to),
+						Op::Burn(who, a) => Currency::burn(Origin::signed(who), 0, a),
+						Op::BurnFrom(from, a) => Currency::approve_burn(Origin::signed(from), 0, 1, a)
+							.and_then(|_| Currency::burn_from(Origin::signed(1), 0, from, a)),
+						Op::Transfer(from, to, a) => Currency::do_transfer_from(&from, &to, &0, a),
+					};
+					assert_eq!(Currency::currencies(0).unwrap().total_supply, supply_of_balances(0));
+				}
+			});
+		}
+	}
+}
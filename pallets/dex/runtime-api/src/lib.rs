@@ -0,0 +1,50 @@
+//! Runtime API definition for the DEX pallet.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+use codec::Codec;
+use primitives::{BlockNumber, CurrencyId, ExchangeId};
+use sp_std::vec::Vec;
+
+sp_api::decl_runtime_apis! {
+	pub trait DexApi<AccountId, TokenId, TokenBalance> where
+		AccountId: Codec,
+		TokenId: Codec,
+		TokenBalance: Codec,
+	{
+		/// The exchange of `currency_id` and the tokens with liquidity in it.
+		fn exchange_for_currency(currency_id: CurrencyId) -> Option<(ExchangeId, Vec<TokenId>)>;
+
+		/// Output of selling `amount_in` of `path[0]`, trading `path[i]` for `path[i + 1]` in
+		/// `markets[i]`. `None` if the route cannot trade it.
+		fn quote_exact_in(
+			markets: Vec<(ExchangeId, CurrencyId)>,
+			path: Vec<TokenId>,
+			amount_in: TokenBalance,
+		) -> Option<TokenBalance>;
+
+		/// Input needed to buy `amount_out` of the last token of `path` along the same kind of route.
+		fn quote_exact_out(
+			markets: Vec<(ExchangeId, CurrencyId)>,
+			path: Vec<TokenId>,
+			amount_out: TokenBalance,
+		) -> Option<TokenBalance>;
+
+		/// Swap fees paid to `referrer` so far, by the token they were paid in.
+		fn referral_earnings(referrer: AccountId) -> Vec<(TokenId, TokenBalance)>;
+
+		/// Volume and fees of a pool over the last `window` blocks, in the exchange currency.
+		fn pool_volume(exchange_id: ExchangeId, token_id: TokenId, window: BlockNumber) -> Option<(TokenBalance, TokenBalance)>;
+
+		/// Yearly return of a pool's fees over the last `window` blocks, in basis points of its reserves.
+		fn fee_apr(exchange_id: ExchangeId, token_id: TokenId, window: BlockNumber) -> Option<u32>;
+
+		/// Value of a liquidity share against holding since the pool was at `entry`, its
+		/// `(currency_reserve, token_reserve, total_liquidity)`, in basis points.
+		fn impermanent_loss(
+			exchange_id: ExchangeId,
+			token_id: TokenId,
+			entry: (TokenBalance, TokenBalance, TokenBalance),
+		) -> Option<i32>;
+	}
+}
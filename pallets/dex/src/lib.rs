@@ -2,19 +2,25 @@
 
 use codec::{Encode, Decode};
 use frame_support::{
-	decl_module, decl_storage, decl_error, decl_event, ensure, StorageValue, StorageMap, Parameter,
-	traits::Get,
+	decl_module, decl_storage, decl_error, decl_event, ensure, StorageValue, StorageMap, StorageDoubleMap,
+	IterableStorageMap, IterableStorageDoubleMap, Parameter,
+	storage::{migration::{take_storage_value, StorageIterator}, with_transaction, TransactionOutcome},
+	traits::{Get, ReservableCurrency}, transactional,
+	weights::{GetDispatchInfo, Weight},
 };
-use frame_system::{self as system, ensure_signed};
+use frame_system::{self as system, ensure_root, ensure_signed};
+use sp_core::U256;
 use sp_runtime::{
-	ModuleId,
+	ModuleId, FixedPointNumber, FixedU128,
 	traits::{
-		AccountIdConversion, One, Zero,
+		AccountIdConversion, Bounded, CheckedAdd, CheckedDiv, CheckedSub, Dispatchable, Hash as HashT, One, Saturating,
+		UniqueSaturatedFrom, UniqueSaturatedInto, Zero,
 	},
 	DispatchError, DispatchResult, RuntimeDebug,
 };
-use primitives::{BlockNumber, CurrencyId};
-use sp_std::prelude::*;
+pub use primitives::{BlockNumber, CurrencyId, ExchangeId};
+use nft::{AssetId, CollectionId, NftAssetData};
+use sp_std::{collections::btree_set::BTreeSet, prelude::*};
 
 #[cfg(test)]
 mod mock;
@@ -23,35 +29,295 @@ mod mock;
 mod tests;
 
 
-pub type ExchangeId = u64;
+pub type OrderId = u64;
+
+type BalanceOf<T> =
+	<<T as Trait>::DepositCurrency as frame_support::traits::Currency<<T as system::Trait>::AccountId>>::Balance;
+
+/// Largest StableSwap amplification coefficient a pool may use.
+pub const MAX_AMPLIFICATION: u32 = 1_000_000;
 
 /// The pallet's configuration trait.
-pub trait Trait: system::Trait + pallet_timestamp::Trait + currency::Trait {
+pub trait Trait: system::Trait + pallet_timestamp::Trait + currency::Trait + nft::Trait {
 	type Event: From<Event<Self>> + Into<<Self as system::Trait>::Event>;
 	type ModuleId: Get<ModuleId>;
+	/// Number of price and volume observations kept per pool for TWAP and volume queries.
+	type MaxPriceObservations: Get<u32>;
+	/// The call a flash swap dispatches while the borrowed funds are out.
+	type Call: Parameter + Dispatchable<Origin = <Self as system::Trait>::Origin> + GetDispatchInfo;
+	/// Maximum number of resting orders on each side of a pool's order book.
+	type MaxOrdersPerPool: Get<u32>;
+	/// Deposit reserved for every resting order until it is filled or cancelled, so that the
+	/// `MaxOrdersPerPool` slots cannot be taken for free.
+	type OrderDeposit: Get<BalanceOf<Self>>;
+	/// Maximum number of swaps queued on a pool in batch auction mode within a block.
+	type MaxBatchSwapsPerPool: Get<u32>;
+	/// Maximum number of swaps queued on all pools within a block, bounding the batches
+	/// cleared in `on_finalize`.
+	type MaxBatchSwapsPerBlock: Get<u32>;
+	/// Liquidity shares locked for good when a pool is first funded.
+	type MinimumLiquidity: Get<Self::TokenBalance>;
+	/// Currency a pool must be funded with, for exchanges without their own minimum.
+	type DefaultMinPoolSize: Get<Self::TokenBalance>;
+	/// Blocks after a swap commitment during which it can be revealed, after which it is refunded.
+	type RevealWindow: Get<Self::BlockNumber>;
+	/// Currency order deposits are reserved in.
+	type DepositCurrency: ReservableCurrency<Self::AccountId>;
+	/// Part of the swap fee paid to the referrer of a swap, in basis points.
+	type ReferralFeeShare: Get<u32>;
 	// type PairId: Parameter + Member + AtLeast32Bit + Default + Copy
 	// 	+ MaybeSerializeDeserialize;
 
 }
 
+/// One router hop, trading `token_in` for `token_out` through the currency of a single exchange.
+struct RouteLeg<T: Trait> {
+	exchange_id: ExchangeId,
+	exchange: ExchangeInfo<T::AccountId>,
+	currency_token: T::TokenId,
+	token_in: T::TokenId,
+	token_out: T::TokenId,
+}
+
 /// Exchange info
 #[derive(Encode, Decode, Clone, Eq, PartialEq, RuntimeDebug)]
 pub struct ExchangeInfo<AccountId> {
-	/// Class owner
+	/// Exchange owner, allowed to pause and close it
 	pub creator: AccountId,
 	/// Token id
 	pub currency: CurrencyId,
 	pub vault: AccountId,
 }
 
+/// Input escrowed for a swap whose other parameters stay hidden until revealed.
+#[derive(Encode, Decode, Clone, Eq, PartialEq, RuntimeDebug)]
+pub struct SwapCommitment<TokenId, TokenBalance, BlockNumber> {
+	/// The exchange currency token or a pool token.
+	pub token_in: TokenId,
+	pub amount_in: TokenBalance,
+	pub committed_at: BlockNumber,
+}
+
+/// A swap queued on an exchange in batch auction mode. Buys spend currency, sells spend tokens.
+#[derive(Encode, Decode, Clone, Eq, PartialEq, RuntimeDebug)]
+pub struct BatchSwap<AccountId, TokenBalance> {
+	pub owner: AccountId,
+	pub to: AccountId,
+	pub side: OrderSide,
+	pub amount_in: TokenBalance,
+	pub min_amount_out: TokenBalance,
+}
+
+/// Liquidity shares held by the DEX on behalf of the owner of a position NFT.
+#[derive(Encode, Decode, Clone, Eq, PartialEq, RuntimeDebug)]
+pub struct LpPosition<TokenId, TokenBalance> {
+	pub exchange_id: ExchangeId,
+	pub token_id: TokenId,
+	pub shares: TokenBalance,
+	/// Currency per token and token per currency when the position was opened.
+	pub entry_prices: (FixedU128, FixedU128),
+}
+
+/// State of a pool after an operation, reported in events.
+#[derive(Encode, Decode, Clone, Eq, PartialEq, RuntimeDebug)]
+pub struct PoolState<TokenBalance> {
+	pub token_reserve: TokenBalance,
+	pub currency_reserve: TokenBalance,
+	/// Liquidity tokens in circulation.
+	pub total_liquidity: TokenBalance,
+}
+
+/// Limits how far a pool's price may move within a window of blocks.
+#[derive(Encode, Decode, Clone, Eq, PartialEq, RuntimeDebug)]
+pub struct CircuitBreaker<BlockNumber> {
+	/// Largest move allowed from the price at the start of the window, in basis points.
+	pub max_move: u32,
+	pub window: BlockNumber,
+}
+
+/// Pool reserves at the start of the current circuit breaker window.
+#[derive(Encode, Decode, Clone, Eq, PartialEq, RuntimeDebug)]
+pub struct PriceReference<BlockNumber, TokenBalance> {
+	pub start: BlockNumber,
+	pub currency_reserve: TokenBalance,
+	pub token_reserve: TokenBalance,
+}
+
+/// Operations currently allowed on an exchange.
+#[derive(Encode, Decode, Clone, Copy, Default, Eq, PartialEq, RuntimeDebug)]
+pub struct ExchangeStatus {
+	/// Swaps, flash swaps and order book trades are rejected.
+	pub trading_paused: bool,
+	/// `add_liquidity` is rejected.
+	pub deposits_paused: bool,
+	/// Only `remove_liquidity` and `cancel_order` are allowed, the exchange is deleted once empty.
+	pub closed: bool,
+}
+
+/// Invariant a pool prices its swaps with.
+#[derive(Encode, Decode, Clone, Copy, Eq, PartialEq, RuntimeDebug)]
+pub enum PoolCurve {
+	/// `x * y = k`.
+	ConstantProduct,
+	/// Curve's two-coin StableSwap invariant, for pairs meant to trade near one to one. The
+	/// higher the amplification, the flatter the price around balanced reserves.
+	StableSwap { amplification: u32 },
+}
+
+impl Default for PoolCurve {
+	fn default() -> Self {
+		PoolCurve::ConstantProduct
+	}
+}
+
+#[derive(Encode, Decode, Clone, Copy, Eq, PartialEq, RuntimeDebug)]
+pub enum OrderSide {
+	Buy,
+	Sell,
+}
+
+/// A resting limit order, with what is left to trade held in escrow.
+#[derive(Encode, Decode, Clone, Eq, PartialEq, RuntimeDebug)]
+pub struct LimitOrder<AccountId, TokenId, TokenBalance, Balance> {
+	pub owner: AccountId,
+	pub exchange_id: ExchangeId,
+	pub token_id: TokenId,
+	pub side: OrderSide,
+	/// Currency paid per unit of token, which can be a fraction of a currency unit.
+	pub price: FixedU128,
+	/// Tokens left to trade.
+	pub amount: TokenBalance,
+	/// Currency left in escrow for a buy order, zero for a sell order.
+	pub currency_escrowed: TokenBalance,
+	/// Reserved from the owner until the order leaves the book.
+	pub deposit: Balance,
+}
+
+/// Uniswap v2 style price accumulators of a pool at a given block.
+///
+/// Each accumulator is the running sum of the spot price times the number of blocks it
+/// held, stored as the inner value of a `FixedU128` and allowed to wrap around.
+#[derive(Encode, Decode, Clone, Default, Eq, PartialEq, RuntimeDebug)]
+pub struct PriceObservation<BlockNumber> {
+	/// Cumulative price of one token in the exchange currency.
+	pub currency_per_token: u128,
+	/// Cumulative price of one unit of the exchange currency in the token.
+	pub token_per_currency: u128,
+	pub block_number: BlockNumber,
+}
+
+/// Cumulative volume and fees of a pool, both in the exchange currency, as of the last
+/// trade in a block.
+#[derive(Encode, Decode, Clone, Default, Eq, PartialEq, RuntimeDebug)]
+pub struct VolumeObservation<BlockNumber, TokenBalance> {
+	pub block_number: BlockNumber,
+	pub volume: TokenBalance,
+	/// Swap fees charged, valued at the currency side of each trade.
+	pub fees: TokenBalance,
+}
+
+/// Manipulation resistant prices sourced from the exchange pools.
+pub trait PriceOracle<TokenId, BlockNumber> {
+	/// Time weighted average prices of the `(exchange_id, token_id)` pool over the last
+	/// `window` blocks, as `(currency_per_token, token_per_currency)`.
+	///
+	/// Returns `None` if the pool has no observation old enough to cover the window.
+	fn twap(exchange_id: ExchangeId, token_id: TokenId, window: BlockNumber) -> Option<(FixedU128, FixedU128)>;
+}
+
 // This pallet's storage items.
 decl_storage! {
 	trait Store for Module<T: Trait> as DexModule {
 		pub Exchanges get(fn exchanges): map hasher(blake2_128_concat) ExchangeId => Option<ExchangeInfo<T::AccountId>>;
 		pub NextExchangeId get(fn next_exchange_id): ExchangeId;
-
-		pub TotalSupplies get(fn total_supplies): map hasher(blake2_128_concat) T::TokenId => T::TokenBalance;
-		pub CurrencyReserves get(fn currency_reserves): map hasher(blake2_128_concat) T::TokenId => T::TokenBalance;
+		/// The only exchange trading against each currency.
+		pub ExchangeByCurrency get(fn exchange_by_currency): map hasher(twox_64_concat) CurrencyId => Option<ExchangeId>;
+		/// Whether exchanges created before `ExchangeByCurrency` existed have been indexed.
+		ExchangeIndexMigrated: bool;
+		pub ExchangeStatuses get(fn exchange_status): map hasher(blake2_128_concat) ExchangeId => ExchangeStatus;
+
+		pub TotalSupplies get(fn total_supplies):
+			double_map hasher(blake2_128_concat) ExchangeId, hasher(blake2_128_concat) T::TokenId => T::TokenBalance;
+		pub CurrencyReserves get(fn currency_reserves):
+			double_map hasher(blake2_128_concat) ExchangeId, hasher(blake2_128_concat) T::TokenId => T::TokenBalance;
+		/// Token minted to liquidity providers as their share of a pool.
+		pub LiquidityTokens get(fn liquidity_tokens):
+			double_map hasher(blake2_128_concat) ExchangeId, hasher(blake2_128_concat) T::TokenId => Option<T::TokenId>;
+		/// Shares of pools migrated from the layout keyed by token alone, not yet converted to
+		/// liquidity tokens.
+		pub LegacyLiquidity get(fn legacy_liquidity):
+			double_map hasher(blake2_128_concat) ExchangeId, hasher(blake2_128_concat) T::TokenId => T::TokenBalance;
+		/// Whether pools of the layout keyed by token alone have been moved to their exchanges.
+		LegacyPoolsMigrated: bool;
+
+		/// Swap fees paid to each referrer, by the token they were paid in.
+		pub ReferralEarnings get(fn referral_earnings):
+			double_map hasher(blake2_128_concat) T::AccountId, hasher(blake2_128_concat) T::TokenId => T::TokenBalance;
+
+		/// Pools priced with another curve than the constant product.
+		pub PoolCurves get(fn pool_curve):
+			double_map hasher(blake2_128_concat) ExchangeId, hasher(blake2_128_concat) T::TokenId => PoolCurve;
+
+		/// Per exchange override of `DefaultMinPoolSize`.
+		pub MinPoolSizes get(fn min_pool_sizes): map hasher(blake2_128_concat) ExchangeId => Option<T::TokenBalance>;
+
+		pub CircuitBreakers get(fn circuit_breakers): map hasher(blake2_128_concat) ExchangeId => Option<CircuitBreaker<T::BlockNumber>>;
+		pub PriceReferences get(fn price_references):
+			double_map hasher(blake2_128_concat) ExchangeId, hasher(blake2_128_concat) T::TokenId
+			=> Option<PriceReference<T::BlockNumber, T::TokenBalance>>;
+
+		/// Exchanges with a flash swap in progress, closed to any other operation.
+		pub FlashSwapLocks get(fn flash_swap_locks): map hasher(blake2_128_concat) ExchangeId => bool;
+
+		pub Orders get(fn orders): map hasher(blake2_128_concat) OrderId => Option<LimitOrder<T::AccountId, T::TokenId, T::TokenBalance, BalanceOf<T>>>;
+		pub NextOrderId get(fn next_order_id): OrderId;
+
+		/// Pending swap commitments by owner and hash, so that copying a commitment cannot block
+		/// its owner's.
+		pub SwapCommitments get(fn swap_commitments):
+			double_map hasher(blake2_128_concat) T::AccountId, hasher(identity) T::Hash
+			=> Option<SwapCommitment<T::TokenId, T::TokenBalance, T::BlockNumber>>;
+		/// Commitments refunded at the end of each block unless revealed.
+		pub CommitmentExpiries get(fn commitment_expiries):
+			map hasher(twox_64_concat) T::BlockNumber => Vec<(T::AccountId, T::Hash)>;
+
+		/// Markets trading an exchange's tokens against its other quote currencies.
+		///
+		/// A market is an exchange of its own with a separate vault, so pool state stays keyed by
+		/// exchange and token, and each pool is priced in a single currency. Its pools hold their
+		/// own liquidity. Pausing, closing, minimum pool size, circuit breaker and batch auction
+		/// mode set on the parent apply to its markets.
+		pub QuoteMarkets get(fn quote_markets):
+			double_map hasher(twox_64_concat) ExchangeId, hasher(twox_64_concat) CurrencyId => Option<ExchangeId>;
+		/// The exchange each quote market belongs to.
+		pub MarketParents get(fn market_parents): map hasher(twox_64_concat) ExchangeId => Option<ExchangeId>;
+
+		/// Exchanges whose pools only trade through per-block batch auctions.
+		pub BatchAuctions get(fn batch_auctions): map hasher(blake2_128_concat) ExchangeId => bool;
+		/// Swaps queued during the current block, cleared in its `on_finalize`.
+		pub BatchSwaps get(fn batch_swaps):
+			double_map hasher(blake2_128_concat) ExchangeId, hasher(blake2_128_concat) T::TokenId
+			=> Vec<BatchSwap<T::AccountId, T::TokenBalance>>;
+		/// Swaps queued on all pools during the current block.
+		pub BatchSwapsQueued get(fn batch_swaps_queued): u32;
+
+		/// `pallet_nft` collection of LP position NFTs, created with the first position.
+		pub PositionCollection get(fn position_collection): Option<CollectionId>;
+		pub Positions get(fn positions): map hasher(twox_64_concat) AssetId => Option<LpPosition<T::TokenId, T::TokenBalance>>;
+		/// Resting buy orders per pool as `(price, order_id)`, highest price first, oldest first at equal prices.
+		pub Bids get(fn bids):
+			double_map hasher(blake2_128_concat) ExchangeId, hasher(blake2_128_concat) T::TokenId => Vec<(FixedU128, OrderId)>;
+		/// Resting sell orders per pool as `(price, order_id)`, lowest price first, oldest first at equal prices.
+		pub Asks get(fn asks):
+			double_map hasher(blake2_128_concat) ExchangeId, hasher(blake2_128_concat) T::TokenId => Vec<(FixedU128, OrderId)>;
+
+		/// Price accumulators per pool, oldest first, one entry per block with trading activity.
+		pub PriceObservations get(fn price_observations):
+			double_map hasher(blake2_128_concat) ExchangeId, hasher(blake2_128_concat) T::TokenId => Vec<PriceObservation<T::BlockNumber>>;
+		/// Volume counters per pool, oldest first, one entry per block with trading activity.
+		pub VolumeObservations get(fn volume_observations):
+			double_map hasher(blake2_128_concat) ExchangeId, hasher(blake2_128_concat) T::TokenId
+			=> Vec<VolumeObservation<T::BlockNumber, T::TokenBalance>>;
 	}
 }
 
@@ -61,12 +327,87 @@ decl_event!(
 		AccountId = <T as system::Trait>::AccountId,
 		TokenId = <T as token::Trait>::TokenId,
 		TokenBalance = <T as token::Trait>::TokenBalance,
+		BlockNumber = <T as system::Trait>::BlockNumber,
+		Hash = <T as system::Trait>::Hash,
 	{
 		ExchangeCreated(ExchangeId, AccountId),
-		CurrencyToToken(ExchangeId, AccountId, AccountId, Vec<TokenId>, Vec<TokenBalance>, Vec<TokenBalance>),
-		TokenToCurrency(ExchangeId, AccountId, AccountId, Vec<TokenId>, Vec<TokenBalance>, Vec<TokenBalance>),
-		LiquidityAdded(AccountId, AccountId, Vec<TokenId>, Vec<TokenBalance>, Vec<TokenBalance>),
-		LiquidityRemoved(AccountId, AccountId, Vec<TokenId>, Vec<TokenBalance>, Vec<TokenBalance>),
+		TradingPaused(ExchangeId, bool),
+		DepositsPaused(ExchangeId, bool),
+		/// Exchange, new owner.
+		OwnershipTransferred(ExchangeId, AccountId),
+		ExchangeClosed(ExchangeId),
+		CircuitBreakerSet(ExchangeId, Option<CircuitBreaker<BlockNumber>>),
+		MinPoolSizeSet(ExchangeId, Option<TokenBalance>),
+		PoolCurveSet(ExchangeId, TokenId, PoolCurve),
+		/// Referrer, token paid in, amount.
+		ReferralPaid(AccountId, TokenId, TokenBalance),
+		/// A closed exchange was emptied and deleted.
+		ExchangeRemoved(ExchangeId),
+		/// Exchange, token, tokens and currency backing the pool's locked liquidity, moved to the
+		/// locked liquidity account as the exchange was removed.
+		LockedLiquiditySwept(ExchangeId, TokenId, TokenBalance, TokenBalance),
+		/// Exchange, sender, recipient, tokens, tokens bought, currency paid, pool fees in currency,
+		/// pools after the trade.
+		CurrencyToToken(
+			ExchangeId, AccountId, AccountId, Vec<TokenId>, Vec<TokenBalance>, Vec<TokenBalance>,
+			Vec<TokenBalance>, Vec<PoolState<TokenBalance>>,
+		),
+		/// Exchange, sender, recipient, tokens, tokens sold, currency received, pool fees in tokens,
+		/// pools after the trade.
+		TokenToCurrency(
+			ExchangeId, AccountId, AccountId, Vec<TokenId>, Vec<TokenBalance>, Vec<TokenBalance>,
+			Vec<TokenBalance>, Vec<PoolState<TokenBalance>>,
+		),
+		/// Sender, recipient, exchanges, path, amount in, amount out, every pool traded through
+		/// after the trade.
+		TokenToToken(
+			AccountId, AccountId, Vec<ExchangeId>, Vec<TokenId>, TokenBalance, TokenBalance,
+			Vec<(ExchangeId, TokenId, PoolState<TokenBalance>)>,
+		),
+		/// Exchange, borrower, token, tokens out, currency out, tokens repaid, currency repaid.
+		FlashSwap(ExchangeId, AccountId, TokenId, TokenBalance, TokenBalance, TokenBalance, TokenBalance),
+		/// Exchange, token, liquidity token. Emitted when a pool is funded for the first time.
+		PoolCreated(ExchangeId, TokenId, TokenId),
+		/// Exchange, token, holder, old shares converted to liquidity tokens.
+		LegacyLiquidityConverted(ExchangeId, TokenId, AccountId, TokenBalance),
+		/// Exchange, sender, recipient, tokens, tokens deposited, currency deposited, liquidity
+		/// minted, pools after the deposit.
+		LiquidityAdded(
+			ExchangeId, AccountId, AccountId, Vec<TokenId>, Vec<TokenBalance>, Vec<TokenBalance>,
+			Vec<TokenBalance>, Vec<PoolState<TokenBalance>>,
+		),
+		/// Exchange, sender, recipient, tokens, tokens withdrawn, currency withdrawn, liquidity
+		/// burned, pools after the withdrawal.
+		LiquidityRemoved(
+			ExchangeId, AccountId, AccountId, Vec<TokenId>, Vec<TokenBalance>, Vec<TokenBalance>,
+			Vec<TokenBalance>, Vec<PoolState<TokenBalance>>,
+		),
+		/// Order, owner, exchange, token, side, price, amount.
+		OrderPlaced(OrderId, AccountId, ExchangeId, TokenId, OrderSide, FixedU128, TokenBalance),
+		/// Order, taker, tokens traded, currency traded.
+		OrderFilled(OrderId, AccountId, TokenBalance, TokenBalance),
+		OrderCancelled(OrderId, AccountId),
+		/// Position NFT, owner, exchange, token, liquidity shares.
+		PositionOpened(AssetId, AccountId, ExchangeId, TokenId, TokenBalance),
+		/// Position NFT, owner.
+		PositionClosed(AssetId, AccountId),
+		/// Commitment, owner, token escrowed, amount escrowed.
+		SwapCommitted(Hash, AccountId, TokenId, TokenBalance),
+		/// Commitment, owner. Followed by the event of the swap.
+		SwapRevealed(Hash, AccountId),
+		/// Commitment, owner. The escrowed input was refunded.
+		SwapCommitmentExpired(Hash, AccountId),
+		BatchAuctionSet(ExchangeId, bool),
+		/// Exchange, quote currency, market trading against it.
+		QuoteCurrencyAdded(ExchangeId, CurrencyId, ExchangeId),
+		/// Exchange, token, owner, side, amount in.
+		BatchSwapSubmitted(ExchangeId, TokenId, AccountId, OrderSide, TokenBalance),
+		/// Exchange, token, owner, recipient, side, amount in, amount out.
+		BatchSwapFilled(ExchangeId, TokenId, AccountId, AccountId, OrderSide, TokenBalance, TokenBalance),
+		/// Exchange, token, owner. The swap missed its minimum output or the batch failed.
+		BatchSwapRefunded(ExchangeId, TokenId, AccountId),
+		/// Exchange, token, clearing price in currency per token, pool after the batch.
+		BatchCleared(ExchangeId, TokenId, FixedU128, PoolState<TokenBalance>),
 	}
 );
 
@@ -84,6 +425,58 @@ decl_error! {
 		InsufficientLiquidity,
 		InsufficientOutputAmount,
 		InsufficientInputAmount,
+		MaxTokenAmountExceeded,
+		DeadlineExpired,
+		InvalidArrayLength,
+		DuplicateTokenId,
+		InvalidPath,
+		InsufficientLiquidityMinted,
+		ExchangeLocked,
+		FlashSwapNotRepaid,
+		InvalidOrderId,
+		NotOrderOwner,
+		InvalidPrice,
+		OrderBookFull,
+		NotExchangeOwner,
+		TradingPaused,
+		DepositsPaused,
+		ExchangeClosed,
+		ExchangeAlreadyExists,
+		/// The position NFT does not exist or belongs to another exchange.
+		InvalidPosition,
+		/// No pending commitment with this hash for the sender.
+		InvalidCommitment,
+		CommitmentAlreadyExists,
+		/// The revealed parameters do not hash to the commitment.
+		InvalidReveal,
+		/// Commitments can be revealed from the block after they are made.
+		RevealTooEarly,
+		RevealWindowExpired,
+		/// The exchange only trades through batch auctions.
+		BatchAuctionOnly,
+		NotBatchAuction,
+		/// The pool already has `MaxBatchSwapsPerPool` swaps queued for the next clearing, or all
+		/// pools together `MaxBatchSwapsPerBlock`.
+		BatchQueueFull,
+		/// The exchange does not trade against this currency.
+		InvalidQuoteCurrency,
+		QuoteCurrencyAlreadyAdded,
+		/// Traders cannot refer their own swaps.
+		InvalidReferrer,
+		/// The pool curve can only change while the pool has no liquidity.
+		PoolNotEmpty,
+		/// The amplification is zero or above `MAX_AMPLIFICATION`.
+		InvalidAmplification,
+		/// Batch auctions only clear constant product pools.
+		CurveNotSupported,
+		/// The trade moves a pool's price by more than `max_price_impact`.
+		PriceImpactTooHigh,
+		/// The trade moves a pool's price outside the exchange's circuit breaker band.
+		CircuitBreakerTripped,
+		/// More old shares converted than the pool has outstanding.
+		LegacyLiquidityExceeded,
+		/// Non-fungible tokens have no pools or order books.
+		NonFungibleToken,
 	}
 }
 
@@ -94,13 +487,26 @@ decl_module! {
 
 		fn deposit_event() = default;
 
+		fn on_runtime_upgrade() -> Weight {
+			Self::migrate_legacy_pools().saturating_add(Self::migrate_exchange_index())
+		}
+
+		fn on_finalize(now: T::BlockNumber) {
+			Self::clear_batch_auctions();
+			Self::refund_expired_commitments(now);
+		}
+
+		/// Create the exchange of `currency_id`. Each currency has at most one exchange.
 		#[weight = 0]
 		pub fn create_exchange(origin, currency_id: CurrencyId) -> DispatchResult {
 			let sender = ensure_signed(origin)?;
 
+			currency::Module::<T>::get_currency_token(&currency_id)?;
+			ensure!(!ExchangeByCurrency::contains_key(currency_id), Error::<T>::ExchangeAlreadyExists);
+
 			let exchange_id = Self::next_exchange_id();
 
-			let account = T::ModuleId::get().into_sub_account(exchange_id);
+			let account = <T as Trait>::ModuleId::get().into_sub_account(exchange_id);
 
 			let new_exchange_info = ExchangeInfo {
 				creator: sender.clone(),
@@ -109,6 +515,7 @@ decl_module! {
 			};
 
 			Exchanges::<T>::insert(exchange_id, new_exchange_info);
+			ExchangeByCurrency::insert(currency_id, exchange_id);
 			NextExchangeId::mutate(|id| *id += <ExchangeId as One>::one());
 
 			Self::deposit_event(RawEvent::ExchangeCreated(exchange_id, sender));
@@ -116,55 +523,326 @@ decl_module! {
 			Ok(())
 		}
 
+		/// Let the tokens of an exchange also trade against `currency_id`. Creator or root only.
+		///
+		/// Pools against the new quote currency live in a market of their own, addressed by the
+		/// exchange and the currency in every pool call, which is why those calls take a
+		/// `currency_id` after the exchange id. The market is not indexed in `ExchangeByCurrency`,
+		/// it is only reached through its parent and follows the parent's settings.
+		///
+		/// Pools stay keyed by exchange and token rather than by token and currency, so pools in
+		/// the primary currency keep their storage, vault and liquidity token and need no
+		/// migration. Clients written before quote currencies pass the exchange's primary
+		/// currency as `currency_id` to keep their behaviour, `currency_to_token` and
+		/// `token_to_currency` still take their old arguments.
 		#[weight = 0]
-		pub fn currency_to_token(
+		pub fn add_quote_currency(origin, exchange_id: ExchangeId, currency_id: CurrencyId) -> DispatchResult {
+			Self::ensure_owner_or_root(origin, exchange_id)?;
+
+			let exchange = Self::get_exchange(exchange_id)?;
+			ensure!(!MarketParents::contains_key(exchange_id), Error::<T>::InvalidExchangeId);
+			ensure!(!Self::exchange_status(exchange_id).closed, Error::<T>::ExchangeClosed);
+			currency::Module::<T>::get_currency_token(&currency_id)?;
+			ensure!(
+				currency_id != exchange.currency && !QuoteMarkets::contains_key(exchange_id, currency_id),
+				Error::<T>::QuoteCurrencyAlreadyAdded
+			);
+
+			let market_id = Self::next_exchange_id();
+			Exchanges::<T>::insert(market_id, ExchangeInfo {
+				creator: exchange.creator,
+				currency: currency_id,
+				vault: <T as Trait>::ModuleId::get().into_sub_account(market_id),
+			});
+			QuoteMarkets::insert(exchange_id, currency_id, market_id);
+			MarketParents::insert(market_id, exchange_id);
+			NextExchangeId::mutate(|id| *id += <ExchangeId as One>::one());
+
+			Self::deposit_event(RawEvent::QuoteCurrencyAdded(exchange_id, currency_id, market_id));
+
+			Ok(())
+		}
+
+		/// Pause or resume trading on an exchange. Creator or root only.
+		#[weight = 0]
+		pub fn set_trading_paused(origin, exchange_id: ExchangeId, paused: bool) -> DispatchResult {
+			Self::ensure_owner_or_root(origin, exchange_id)?;
+
+			ExchangeStatuses::try_mutate(exchange_id, |status| -> DispatchResult {
+				ensure!(!status.closed, Error::<T>::ExchangeClosed);
+				status.trading_paused = paused;
+				Ok(())
+			})?;
+
+			Self::deposit_event(RawEvent::TradingPaused(exchange_id, paused));
+
+			Ok(())
+		}
+
+		/// Pause or resume liquidity deposits on an exchange. Creator or root only.
+		#[weight = 0]
+		pub fn set_deposits_paused(origin, exchange_id: ExchangeId, paused: bool) -> DispatchResult {
+			Self::ensure_owner_or_root(origin, exchange_id)?;
+
+			ExchangeStatuses::try_mutate(exchange_id, |status| -> DispatchResult {
+				ensure!(!status.closed, Error::<T>::ExchangeClosed);
+				status.deposits_paused = paused;
+				Ok(())
+			})?;
+
+			Self::deposit_event(RawEvent::DepositsPaused(exchange_id, paused));
+
+			Ok(())
+		}
+
+		/// Hand an exchange over to `new_owner`. Creator or root only.
+		#[weight = 0]
+		pub fn transfer_ownership(origin, exchange_id: ExchangeId, new_owner: T::AccountId) -> DispatchResult {
+			Self::ensure_owner_or_root(origin, exchange_id)?;
+
+			Exchanges::<T>::try_mutate(exchange_id, |exchange| -> DispatchResult {
+				let exchange = exchange.as_mut().ok_or(Error::<T>::InvalidExchangeId)?;
+				exchange.creator = new_owner.clone();
+				Ok(())
+			})?;
+			for market_id in QuoteMarkets::iter_prefix_values(exchange_id) {
+				Exchanges::<T>::mutate(market_id, |market| {
+					if let Some(market) = market {
+						market.creator = new_owner.clone();
+					}
+				});
+			}
+
+			Self::deposit_event(RawEvent::OwnershipTransferred(exchange_id, new_owner));
+
+			Ok(())
+		}
+
+		/// Set or clear the currency a pool of an exchange must be funded with. Creator or root only.
+		///
+		/// Applies to the quote markets of the exchange without a minimum of their own.
+		#[weight = 0]
+		pub fn set_min_pool_size(origin, exchange_id: ExchangeId, min_pool_size: Option<T::TokenBalance>) -> DispatchResult {
+			Self::ensure_owner_or_root(origin, exchange_id)?;
+
+			MinPoolSizes::<T>::mutate_exists(exchange_id, |size| *size = min_pool_size);
+
+			Self::deposit_event(RawEvent::MinPoolSizeSet(exchange_id, min_pool_size));
+
+			Ok(())
+		}
+
+		/// Convert old shares of a migrated pool into its liquidity token. Root only.
+		///
+		/// The old layout minted shares as the pool token itself, so `holders` and their shares
+		/// come from the `LiquidityAdded`/`LiquidityRemoved` history rather than balances. The
+		/// shares are burned from the holder's pool token balance.
+		#[weight = 0]
+		#[transactional]
+		pub fn convert_legacy_liquidity(
 			origin,
 			exchange_id: ExchangeId,
-			token_ids: Vec<T::TokenId>,
-			token_amounts_out: Vec<T::TokenBalance>,
-			max_currency: T::TokenBalance,
-			to: T::AccountId,
-			deadline: BlockNumber,
+			token_id: T::TokenId,
+			holders: Vec<(T::AccountId, T::TokenBalance)>,
 		) -> DispatchResult {
-			let sender = ensure_signed(origin)?;
+			ensure_root(origin)?;
 
-			let exchange = Self::exchanges(exchange_id).ok_or(Error::<T>::InvalidExchangeId)?;
+			let liquidity_token = Self::liquidity_tokens(exchange_id, token_id).ok_or(Error::<T>::InvalidExchangeId)?;
 
-			let n = token_ids.len();
+			for (holder, shares) in holders {
+				LegacyLiquidity::<T>::try_mutate_exists(exchange_id, token_id, |outstanding| -> DispatchResult {
+					let left = outstanding
+						.unwrap_or_else(Zero::zero)
+						.checked_sub(&shares)
+						.ok_or(Error::<T>::LegacyLiquidityExceeded)?;
+					*outstanding = if left.is_zero() { None } else { Some(left) };
+					Ok(())
+				})?;
 
-			let mut total_refund_currency: T::TokenBalance = max_currency;
+				token::Module::<T>::burn(&holder, &token_id, shares)?;
+				token::Module::<T>::mint(&holder, &liquidity_token, shares)?;
 
-			let mut amounts_in = vec![T::TokenBalance::from(0u32); n];
-			// let mut token_reserves = vec![0 as T::TokenBalance; n];
+				Self::deposit_event(RawEvent::LegacyLiquidityConverted(exchange_id, token_id, holder, shares));
+			}
 
-			let token_reserves = Self::get_token_reserves(&exchange.vault, &token_ids);
+			Ok(())
+		}
 
-			for i in 0..n {
-				let id = token_ids[i];
-				let amount_out = token_amounts_out[i];
-				let token_reserve = token_reserves[i];
+		/// Choose the curve the `token_id` pool prices swaps with. Creator or root only.
+		///
+		/// Only possible while the pool has no liquidity, so providers always know the curve
+		/// they deposit into.
+		#[weight = 0]
+		pub fn set_pool_curve(
+			origin,
+			exchange_id: ExchangeId,
+			currency_id: CurrencyId,
+			token_id: T::TokenId,
+			curve: PoolCurve,
+		) -> DispatchResult {
+			let exchange_id = Self::market_id(exchange_id, currency_id)?;
+			Self::ensure_owner_or_root(origin, exchange_id)?;
+			ensure!(!Self::market_status(exchange_id).closed, Error::<T>::ExchangeClosed);
+			ensure!(Self::total_supplies(exchange_id, token_id).is_zero(), Error::<T>::PoolNotEmpty);
+
+			match curve {
+				PoolCurve::ConstantProduct => PoolCurves::<T>::remove(exchange_id, token_id),
+				PoolCurve::StableSwap { amplification } => {
+					ensure!(amplification > 0 && amplification <= MAX_AMPLIFICATION, Error::<T>::InvalidAmplification);
+					ensure!(!Self::market_batch_auction(exchange_id), Error::<T>::CurveNotSupported);
+					PoolCurves::<T>::insert(exchange_id, token_id, curve);
+				}
+			}
+
+			Self::deposit_event(RawEvent::PoolCurveSet(exchange_id, token_id, curve));
+
+			Ok(())
+		}
+
+		/// Set or clear the circuit breaker of an exchange. Creator or root only.
+		///
+		/// Trades that would move a pool's price by more than `max_move` basis points from its
+		/// price at the start of the current `window` blocks are rejected. Applies to the quote
+		/// markets of the exchange without a breaker of their own.
+		#[weight = 0]
+		pub fn set_circuit_breaker(
+			origin,
+			exchange_id: ExchangeId,
+			breaker: Option<CircuitBreaker<T::BlockNumber>>,
+		) -> DispatchResult {
+			Self::ensure_owner_or_root(origin, exchange_id)?;
 
-				let currency_reserve = Self::currency_reserves(id);
-				let currency_amount = Self::get_amount_in(amount_out, currency_reserve, token_reserve)?;
+			CircuitBreakers::<T>::mutate_exists(exchange_id, |b| *b = breaker.clone());
+			PriceReferences::<T>::remove_prefix(exchange_id);
+			for market_id in QuoteMarkets::iter_prefix_values(exchange_id) {
+				PriceReferences::<T>::remove_prefix(market_id);
+			}
 
-				total_refund_currency -= currency_amount;
+			Self::deposit_event(RawEvent::CircuitBreakerSet(exchange_id, breaker));
 
-				amounts_in[i] = currency_amount;
+			Ok(())
+		}
 
-				CurrencyReserves::<T>::mutate(id, |currency_reserve| *currency_reserve += currency_amount);
+		/// Close an exchange for good, with its quote markets. Creator or root only.
+		///
+		/// Resting orders are refunded and liquidity providers can only withdraw. The exchange
+		/// is deleted as soon as all its liquidity is withdrawn.
+		#[weight = 0]
+		#[transactional]
+		pub fn close_exchange(origin, exchange_id: ExchangeId) -> DispatchResult {
+			Self::ensure_owner_or_root(origin, exchange_id)?;
+
+			let markets: Vec<ExchangeId> = QuoteMarkets::iter_prefix_values(exchange_id).collect();
+			for market_id in markets {
+				if !Self::exchange_status(market_id).closed {
+					Self::do_close_exchange(market_id)?;
+				}
 			}
 
-			if total_refund_currency > Zero::zero()  {
-				currency::Module::<T>::do_transfer_from(&exchange.vault, &to, &exchange.currency, total_refund_currency)?;
+			Self::do_close_exchange(exchange_id)
+		}
+
+		/// Switch an exchange to or from batch auction mode. Creator or root only.
+		///
+		/// In batch auction mode the pools only trade through `submit_batch_swap`, every swap
+		/// queued for a pool during a block clearing at the same price at the end of the block.
+		/// Enabling it on an exchange puts its quote markets in batch auction mode too.
+		#[weight = 0]
+		pub fn set_batch_auction(origin, exchange_id: ExchangeId, enabled: bool) -> DispatchResult {
+			Self::ensure_owner_or_root(origin, exchange_id)?;
+			ensure!(!Self::exchange_status(exchange_id).closed, Error::<T>::ExchangeClosed);
+
+			if enabled {
+				let markets: Vec<ExchangeId> = QuoteMarkets::iter_prefix_values(exchange_id).collect();
+				ensure!(
+					sp_std::iter::once(exchange_id).chain(markets).all(|id| {
+						PoolCurves::<T>::iter_prefix_values(id).all(|curve| curve == PoolCurve::ConstantProduct)
+					}),
+					Error::<T>::CurveNotSupported
+				);
+				BatchAuctions::insert(exchange_id, true);
+			} else {
+				BatchAuctions::remove(exchange_id);
 			}
 
-			token::Module::<T>::batch_transfer_from(&exchange.vault, &to, &token_ids, token_amounts_out.clone())?;
+			Self::deposit_event(RawEvent::BatchAuctionSet(exchange_id, enabled));
+
+			Ok(())
+		}
+
+		/// Queue a swap of `amount_in` on an exchange in batch auction mode.
+		///
+		/// A buy spends currency on `token_id`, a sell spends `token_id` for currency. Swaps the
+		/// clearing price gives less than `min_amount_out` are refunded, and so is the whole batch
+		/// if the exchange is paused or closed by then or the clearing trips its circuit breaker.
+		///
+		/// Weighs the swap's part in clearing its pool, which `on_finalize` cannot report.
+		#[weight = Module::<T>::clear_batch_weight(1)]
+		#[transactional]
+		pub fn submit_batch_swap(
+			origin,
+			exchange_id: ExchangeId,
+			currency_id: CurrencyId,
+			token_id: T::TokenId,
+			side: OrderSide,
+			amount_in: T::TokenBalance,
+			min_amount_out: T::TokenBalance,
+			to: T::AccountId,
+		) -> DispatchResult {
+			let sender = ensure_signed(origin)?;
+			let exchange_id = Self::market_id(exchange_id, currency_id)?;
+
+			let exchange = Self::get_exchange(exchange_id)?;
+			let status = Self::market_status(exchange_id);
+			ensure!(!status.closed, Error::<T>::ExchangeClosed);
+			ensure!(!status.trading_paused, Error::<T>::TradingPaused);
+			ensure!(Self::market_batch_auction(exchange_id), Error::<T>::NotBatchAuction);
+			ensure!(Self::total_supplies(exchange_id, token_id) > Zero::zero(), Error::<T>::InsufficientLiquidity);
+			ensure!(amount_in > Zero::zero(), Error::<T>::InsufficientInputAmount);
+
+			// Bounds the work of clearing the batches in `on_finalize`.
+			let queued = Self::batch_swaps_queued();
+			ensure!(queued < T::MaxBatchSwapsPerBlock::get(), Error::<T>::BatchQueueFull);
+			BatchSwapsQueued::put(queued + 1);
+			BatchSwaps::<T>::try_mutate(exchange_id, token_id, |swaps| -> DispatchResult {
+				ensure!(swaps.len() < T::MaxBatchSwapsPerPool::get() as usize, Error::<T>::BatchQueueFull);
+				swaps.push(BatchSwap { owner: sender.clone(), to, side, amount_in, min_amount_out });
+				Ok(())
+			})?;
+
+			let escrow = Self::batch_escrow();
+			match side {
+				OrderSide::Buy => currency::Module::<T>::do_transfer_from(&sender, &escrow, &exchange.currency, amount_in)?,
+				OrderSide::Sell => token::Module::<T>::transfer_from(&sender, &escrow, &token_id, amount_in)?,
+			}
 
-			Self::deposit_event(RawEvent::CurrencyToToken(exchange_id, sender, to, token_ids, token_amounts_out, amounts_in));
+			Self::deposit_event(RawEvent::BatchSwapSubmitted(exchange_id, token_id, sender, side, amount_in));
 
 			Ok(())
 		}
 
+		/// Buy exact amounts of tokens, spending at most `max_currency`.
+		///
+		/// Kept for existing clients, same as `swap_currency_for_exact_tokens` in the exchange's
+		/// primary currency.
+		#[weight = 0]
+		pub fn currency_to_token(
+			origin,
+			exchange_id: ExchangeId,
+			token_ids: Vec<T::TokenId>,
+			token_amounts_out: Vec<T::TokenBalance>,
+			max_currency: T::TokenBalance,
+			to: T::AccountId,
+			deadline: BlockNumber,
+		) -> DispatchResult {
+			let currency_id = Self::get_exchange(exchange_id)?.currency;
+			Self::swap_currency_for_exact_tokens(origin, exchange_id, currency_id, token_ids, token_amounts_out, max_currency, to, deadline, None, None)
+		}
+
+		/// Sell exact amounts of tokens, receiving at least `min_currency`.
+		///
+		/// Kept for existing clients, same as `swap_exact_tokens_for_currency` in the exchange's
+		/// primary currency.
 		#[weight = 0]
 		pub fn token_to_currency(
 			origin,
@@ -174,192 +852,1979 @@ decl_module! {
 			min_currency: T::TokenBalance,
 			to: T::AccountId,
 			deadline: BlockNumber,
+		) -> DispatchResult {
+			let currency_id = Self::get_exchange(exchange_id)?.currency;
+			Self::swap_exact_tokens_for_currency(origin, exchange_id, currency_id, token_ids, token_amounts_in, min_currency, to, deadline, None, None)
+		}
+
+		/// Spend exact currency amounts, one per token, receiving at least `min_tokens_out`.
+		///
+		/// Each amount first crosses resting sell orders priced below the pool, the rest is
+		/// swapped in the pool. The same goes for the other single exchange swaps.
+		///
+		/// Every swap takes an optional `max_price_impact` in basis points, failing if the
+		/// trade would move the spot price of any pool it goes through by more than that.
+		///
+		/// An optional `referrer` is paid `ReferralFeeShare` of the pool fee, in the asset the
+		/// swap pays in. Routes pay it on the fee of their first pool.
+		///
+		/// Pool calls name the quote currency after the exchange, either its primary currency
+		/// or one added with `add_quote_currency`.
+		#[weight = 0]
+		#[transactional]
+		pub fn swap_exact_currency_for_tokens(
+			origin,
+			exchange_id: ExchangeId,
+			currency_id: CurrencyId,
+			token_ids: Vec<T::TokenId>,
+			currency_amounts_in: Vec<T::TokenBalance>,
+			min_tokens_out: Vec<T::TokenBalance>,
+			to: T::AccountId,
+			deadline: BlockNumber,
+			max_price_impact: Option<u32>,
+			referrer: Option<T::AccountId>,
 		) -> DispatchResult {
 			let sender = ensure_signed(origin)?;
+			ensure!(referrer.as_ref() != Some(&sender), Error::<T>::InvalidReferrer);
+			let exchange_id = Self::market_id(exchange_id, currency_id)?;
+			Self::ensure_deadline(deadline)?;
 
-			let exchange = Self::exchanges(exchange_id).ok_or(Error::<T>::InvalidExchangeId)?;
+			let exchange = Self::get_trading_exchange(exchange_id)?;
+			Self::ensure_token_ids(&token_ids, &[&currency_amounts_in, &min_tokens_out])?;
 
 			let n = token_ids.len();
-			let mut total_currency = T::TokenBalance::from(0u32);
-			let mut amounts_out = vec![T::TokenBalance::from(0u32); n];
-			// let mut token_reserves = vec![0 as T::TokenBalance; n];
-
-			let token_reserves = Self::get_token_reserves(&exchange.vault, &token_ids);
+			let mut token_amounts_out = Vec::with_capacity(n);
+			let mut pool_tokens_out = Vec::with_capacity(n);
+			let mut pool_currency = T::TokenBalance::from(0u32);
+			let mut fees = Vec::with_capacity(n);
 
 			for i in 0..n {
 				let id = token_ids[i];
-				let amount_in = token_amounts_in[i];
-				let token_reserve = token_reserves[i];
+				let (book_tokens, book_currency) = Self::cross_orders(
+					exchange_id, &exchange, id, OrderSide::Buy, &sender, &to, T::TokenBalance::max_value(), currency_amounts_in[i],
+				)?;
 
-				let currency_reserve = Self::currency_reserves(id);
-				let currency_amount = Self::get_amount_out(amount_in, token_reserve - amount_in, currency_reserve)?;
+				let currency_amount = currency_amounts_in[i].checked_sub(&book_currency).ok_or(Error::<T>::Overflow)?;
+				let token_amount = if currency_amount.is_zero() {
+					Zero::zero()
+				} else {
+					Self::do_exact_currency_for_tokens(exchange_id, &exchange, id, currency_amount, max_price_impact, referrer.is_some())?
+				};
 
-				total_currency += currency_amount;
-				amounts_out[i] = currency_amount;
+				let total = book_tokens.checked_add(&token_amount).ok_or(Error::<T>::Overflow)?;
+				ensure!(total > Zero::zero(), Error::<T>::InsufficientOutputAmount);
+				ensure!(total >= min_tokens_out[i], Error::<T>::InsufficientOutputAmount);
 
-				CurrencyReserves::<T>::mutate(id, |currency_reserve| *currency_reserve -= currency_amount);
+				token_amounts_out.push(total);
+				pool_tokens_out.push(token_amount);
+				pool_currency = pool_currency.checked_add(&currency_amount).ok_or(Error::<T>::Overflow)?;
+				fees.push(Self::swap_fee(currency_amount)?);
 			}
 
-			ensure!(total_currency >= min_currency, Error::<T>::InsufficientCurrencyAmount);
-
-			currency::Module::<T>::do_transfer_from(&exchange.vault, &to, &exchange.currency, total_currency)?;
+			currency::Module::<T>::do_transfer_from(&sender, &exchange.vault, &exchange.currency, pool_currency)?;
+			token::Module::<T>::batch_transfer_from(&exchange.vault, &to, &token_ids, pool_tokens_out)?;
+			if let Some(referrer) = &referrer {
+				for (id, fee) in token_ids.iter().zip(&fees) {
+					Self::pay_referral(referrer, exchange_id, &exchange, *id, OrderSide::Buy, *fee)?;
+				}
+			}
 
-			Self::deposit_event(RawEvent::TokenToCurrency(exchange_id, sender, to, token_ids, token_amounts_in, amounts_out));
+			let pools = Self::pool_states(exchange_id, &exchange.vault, &token_ids);
+			Self::deposit_event(RawEvent::CurrencyToToken(
+				exchange_id, sender, to, token_ids, token_amounts_out, currency_amounts_in, fees, pools,
+			));
 
 			Ok(())
 		}
 
+		/// Buy exact token amounts, spending at most `max_currency` in total.
 		#[weight = 0]
-		pub fn add_liquidity(
+		#[transactional]
+		pub fn swap_currency_for_exact_tokens(
 			origin,
 			exchange_id: ExchangeId,
-			to: T::AccountId,
+			currency_id: CurrencyId,
 			token_ids: Vec<T::TokenId>,
-			token_amounts: Vec<T::TokenBalance>,
-			max_currencys: Vec<T::TokenBalance>,
+			token_amounts_out: Vec<T::TokenBalance>,
+			max_currency: T::TokenBalance,
+			to: T::AccountId,
 			deadline: BlockNumber,
+			max_price_impact: Option<u32>,
+			referrer: Option<T::AccountId>,
 		) -> DispatchResult {
 			let sender = ensure_signed(origin)?;
+			ensure!(referrer.as_ref() != Some(&sender), Error::<T>::InvalidReferrer);
+			let exchange_id = Self::market_id(exchange_id, currency_id)?;
+			Self::ensure_deadline(deadline)?;
 
-			let exchange = Self::exchanges(exchange_id).ok_or(Error::<T>::InvalidExchangeId)?;
-			let currency_token = currency::Module::<T>::get_currency_token(&exchange.currency)?;
+			let exchange = Self::get_trading_exchange(exchange_id)?;
+			Self::ensure_token_ids(&token_ids, &[&token_amounts_out])?;
 
 			let n = token_ids.len();
-			let mut total_currency = T::TokenBalance::from(0u32);
-
-			let mut liquidities_to_mint = vec![T::TokenBalance::from(0u32); n];
-			let mut currency_amounts = vec![T::TokenBalance::from(0u32); n];
-			// let mut token_reserves = [0 as T::TokenBalance; n];
-
-			let token_reserves = Self::get_token_reserves(&exchange.vault, &token_ids);
+			let mut currency_amounts_in = Vec::with_capacity(n);
+			let mut pool_tokens_out = Vec::with_capacity(n);
+			let mut pool_currency = T::TokenBalance::from(0u32);
+			let mut fees = Vec::with_capacity(n);
 
 			for i in 0..n {
 				let id = token_ids[i];
-				let amount = token_amounts[i];
+				let (book_tokens, book_currency) = Self::cross_orders(
+					exchange_id, &exchange, id, OrderSide::Buy, &sender, &to, token_amounts_out[i], T::TokenBalance::max_value(),
+				)?;
 
-				ensure!(max_currencys[i] > Zero::zero() , Error::<T>::InvalidMaxCurrency);
-				ensure!(amount > Zero::zero() , Error::<T>::InsufficientTokenAmount);
+				let token_amount = token_amounts_out[i].checked_sub(&book_tokens).ok_or(Error::<T>::Overflow)?;
+				let currency_amount = if token_amount.is_zero() {
+					Zero::zero()
+				} else {
+					Self::do_currency_for_exact_tokens(exchange_id, &exchange, id, token_amount, max_price_impact, referrer.is_some())?
+				};
 
-				ensure!(currency_token != id, Error::<T>::SameCurrencyAndToken);
+				currency_amounts_in.push(book_currency.checked_add(&currency_amount).ok_or(Error::<T>::Overflow)?);
+				pool_tokens_out.push(token_amount);
+				pool_currency = pool_currency.checked_add(&currency_amount).ok_or(Error::<T>::Overflow)?;
+				fees.push(Self::swap_fee(currency_amount)?);
+			}
 
-				let total_liquidity = Self::total_supplies(id);
+			let total_currency = Self::sum(&currency_amounts_in)?;
+			ensure!(total_currency <= max_currency, Error::<T>::MaxCurrencyAmountExceeded);
 
-				if total_liquidity > Zero::zero()  {
-					let currency_reserve = Self::currency_reserves(id);
-					let token_reserve = token_reserves[i];
+			currency::Module::<T>::do_transfer_from(&sender, &exchange.vault, &exchange.currency, pool_currency)?;
+			token::Module::<T>::batch_transfer_from(&exchange.vault, &to, &token_ids, pool_tokens_out)?;
+			if let Some(referrer) = &referrer {
+				for (id, fee) in token_ids.iter().zip(&fees) {
+					Self::pay_referral(referrer, exchange_id, &exchange, *id, OrderSide::Buy, *fee)?;
+				}
+			}
 
-					let (currency_amount, rounded) = Self::div_round(amount * currency_reserve, token_reserve - amount);
-					ensure!(max_currencys[i] >= currency_amount, Error::<T>::MaxCurrencyAmountExceeded);
+			let pools = Self::pool_states(exchange_id, &exchange.vault, &token_ids);
+			Self::deposit_event(RawEvent::CurrencyToToken(
+				exchange_id, sender, to, token_ids, token_amounts_out, currency_amounts_in, fees, pools,
+			));
 
-					total_currency = total_currency + currency_amount;
+			Ok(())
+		}
 
-					let fixed_currency_amount = if rounded { currency_amount - 1u32.into() } else { currency_amount };
-					liquidities_to_mint[i] = (fixed_currency_amount * total_liquidity) / currency_reserve;
-					currency_amounts[i] = currency_amount;
+		/// Sell exact token amounts, receiving at least `min_currency` in total.
+		#[weight = 0]
+		#[transactional]
+		pub fn swap_exact_tokens_for_currency(
+			origin,
+			exchange_id: ExchangeId,
+			currency_id: CurrencyId,
+			token_ids: Vec<T::TokenId>,
+			token_amounts_in: Vec<T::TokenBalance>,
+			min_currency: T::TokenBalance,
+			to: T::AccountId,
+			deadline: BlockNumber,
+			max_price_impact: Option<u32>,
+			referrer: Option<T::AccountId>,
+		) -> DispatchResult {
+			let sender = ensure_signed(origin)?;
+			ensure!(referrer.as_ref() != Some(&sender), Error::<T>::InvalidReferrer);
+			let exchange_id = Self::market_id(exchange_id, currency_id)?;
+			Self::ensure_deadline(deadline)?;
+
+			let exchange = Self::get_trading_exchange(exchange_id)?;
+			Self::ensure_token_ids(&token_ids, &[&token_amounts_in])?;
+
+			let n = token_ids.len();
+			let mut currency_amounts_out = Vec::with_capacity(n);
+			let mut pool_tokens_in = Vec::with_capacity(n);
+			let mut pool_currency = T::TokenBalance::from(0u32);
+			let mut fees = Vec::with_capacity(n);
 
-					CurrencyReserves::<T>::mutate(id, |currency_reserve| *currency_reserve += currency_amount);
-					TotalSupplies::<T>::mutate(id, |total_supply| *total_supply = total_liquidity + liquidities_to_mint[i]);
+			for i in 0..n {
+				let id = token_ids[i];
+				let (book_tokens, book_currency) = Self::cross_orders(
+					exchange_id, &exchange, id, OrderSide::Sell, &sender, &to, token_amounts_in[i], T::TokenBalance::max_value(),
+				)?;
+
+				let token_amount = token_amounts_in[i].checked_sub(&book_tokens).ok_or(Error::<T>::Overflow)?;
+				let currency_amount = if token_amount.is_zero() {
+					Zero::zero()
 				} else {
-					let max_currency = max_currencys[i];
-					// ensure!(max_currency >= 1000000000u32.into(), Error::<T>::InvalidCurrencyAmount);
-					ensure!(max_currency >= 1000u32.into(), Error::<T>::InvalidCurrencyAmount);
+					Self::do_exact_tokens_for_currency(exchange_id, &exchange, id, token_amount, max_price_impact, referrer.is_some())?
+				};
 
-					total_currency = total_currency + max_currency;
-					liquidities_to_mint[i] = max_currency;
-					currency_amounts[i] = max_currency;
+				let total = book_currency.checked_add(&currency_amount).ok_or(Error::<T>::Overflow)?;
+				ensure!(total > Zero::zero(), Error::<T>::InsufficientOutputAmount);
 
-					CurrencyReserves::<T>::mutate(id, |currency_reserve| *currency_reserve = max_currency);
-					TotalSupplies::<T>::mutate(id, |total_supply| *total_supply = max_currency);
-				}
+				currency_amounts_out.push(total);
+				pool_tokens_in.push(token_amount);
+				pool_currency = pool_currency.checked_add(&currency_amount).ok_or(Error::<T>::Overflow)?;
+				fees.push(Self::swap_fee(token_amount)?);
 			}
 
-			token::Module::<T>::batch_mint(&to, &token_ids, liquidities_to_mint)?;
+			let total_currency = Self::sum(&currency_amounts_out)?;
+			ensure!(total_currency >= min_currency, Error::<T>::InsufficientCurrencyAmount);
 
-			currency::Module::<T>::do_transfer_from(&sender, &exchange.vault, &exchange.currency, total_currency)?;
+			token::Module::<T>::batch_transfer_from(&sender, &exchange.vault, &token_ids, pool_tokens_in)?;
+			currency::Module::<T>::do_transfer_from(&exchange.vault, &to, &exchange.currency, pool_currency)?;
+			if let Some(referrer) = &referrer {
+				for (id, fee) in token_ids.iter().zip(&fees) {
+					Self::pay_referral(referrer, exchange_id, &exchange, *id, OrderSide::Sell, *fee)?;
+				}
+			}
 
-			Self::deposit_event(RawEvent::LiquidityAdded(sender, to, token_ids, token_amounts, currency_amounts));
+			let pools = Self::pool_states(exchange_id, &exchange.vault, &token_ids);
+			Self::deposit_event(RawEvent::TokenToCurrency(
+				exchange_id, sender, to, token_ids, token_amounts_in, currency_amounts_out, fees, pools,
+			));
 
 			Ok(())
 		}
 
+		/// Receive exact currency amounts, one per token, selling at most `max_tokens_in`.
 		#[weight = 0]
-		pub fn remove_liquidity(
+		#[transactional]
+		pub fn swap_tokens_for_exact_currency(
 			origin,
 			exchange_id: ExchangeId,
-			to: T::AccountId,
+			currency_id: CurrencyId,
 			token_ids: Vec<T::TokenId>,
-			liquidities: Vec<T::TokenBalance>,
-			min_currencys: Vec<T::TokenBalance>,
-			min_tokens: Vec<T::TokenBalance>,
+			currency_amounts_out: Vec<T::TokenBalance>,
+			max_tokens_in: Vec<T::TokenBalance>,
+			to: T::AccountId,
 			deadline: BlockNumber,
+			max_price_impact: Option<u32>,
+			referrer: Option<T::AccountId>,
 		) -> DispatchResult {
 			let sender = ensure_signed(origin)?;
+			ensure!(referrer.as_ref() != Some(&sender), Error::<T>::InvalidReferrer);
+			let exchange_id = Self::market_id(exchange_id, currency_id)?;
+			Self::ensure_deadline(deadline)?;
 
-			let exchange = Self::exchanges(exchange_id).ok_or(Error::<T>::InvalidExchangeId)?;
+			let exchange = Self::get_trading_exchange(exchange_id)?;
+			Self::ensure_token_ids(&token_ids, &[&currency_amounts_out, &max_tokens_in])?;
 
 			let n = token_ids.len();
-			let mut total_currency = T::TokenBalance::from(0u32);
-
-			let mut token_amounts = vec![T::TokenBalance::from(0u32); n];
-			let mut currency_amounts = vec![T::TokenBalance::from(0u32); n];
-			// let mut token_reserves = [0 as T::TokenBalance; n];
-
-			let token_reserves = Self::get_token_reserves(&exchange.vault, &token_ids);
+			let mut token_amounts_in = Vec::with_capacity(n);
+			let mut pool_tokens_in = Vec::with_capacity(n);
+			let mut pool_currency = T::TokenBalance::from(0u32);
+			let mut fees = Vec::with_capacity(n);
 
 			for i in 0..n {
 				let id = token_ids[i];
-				let liquidity = liquidities[i];
-				let token_reserve = token_reserves[i];
-
-				let total_liquidity = Self::total_supplies(id);
-				ensure!(total_liquidity > Zero::zero() , Error::<T>::InsufficientLiquidity);
-
-				let currency_reserve = Self::currency_reserves(id);
+				let (book_tokens, book_currency) = Self::cross_orders(
+					exchange_id, &exchange, id, OrderSide::Sell, &sender, &to, T::TokenBalance::max_value(), currency_amounts_out[i],
+				)?;
 
-				let currency_amount = liquidity * currency_reserve / total_liquidity;
-				let token_amount = liquidity * token_reserve / total_liquidity;
-
-				ensure!(currency_amount >= min_currencys[i], Error::<T>::InsufficientCurrencyAmount);
-				ensure!(token_amount >= min_tokens[i], Error::<T>::InsufficientTokenAmount);
+				let currency_amount = currency_amounts_out[i].checked_sub(&book_currency).ok_or(Error::<T>::Overflow)?;
+				let token_amount = if currency_amount.is_zero() {
+					Zero::zero()
+				} else {
+					Self::do_tokens_for_exact_currency(exchange_id, &exchange, id, currency_amount, max_price_impact, referrer.is_some())?
+				};
 
-				total_currency += currency_amount;
-				token_amounts[i] = token_amount;
-				currency_amounts[i] = currency_amount;
+				let total = book_tokens.checked_add(&token_amount).ok_or(Error::<T>::Overflow)?;
+				ensure!(total <= max_tokens_in[i], Error::<T>::MaxTokenAmountExceeded);
 
-				CurrencyReserves::<T>::mutate(id, |currency_reserve| *currency_reserve -= currency_amount);
-				TotalSupplies::<T>::mutate(id, |total_supply| *total_supply = total_liquidity - liquidity);
+				token_amounts_in.push(total);
+				pool_tokens_in.push(token_amount);
+				pool_currency = pool_currency.checked_add(&currency_amount).ok_or(Error::<T>::Overflow)?;
+				fees.push(Self::swap_fee(token_amount)?);
 			}
 
-			token::Module::<T>::batch_burn(&exchange.vault, &token_ids, liquidities)?;
-
-			currency::Module::<T>::do_transfer_from(&exchange.vault, &to, &exchange.currency, total_currency)?;
-			token::Module::<T>::batch_transfer_from(&exchange.vault, &to, &token_ids, token_amounts.clone())?;
+			token::Module::<T>::batch_transfer_from(&sender, &exchange.vault, &token_ids, pool_tokens_in)?;
+			currency::Module::<T>::do_transfer_from(&exchange.vault, &to, &exchange.currency, pool_currency)?;
+			if let Some(referrer) = &referrer {
+				for (id, fee) in token_ids.iter().zip(&fees) {
+					Self::pay_referral(referrer, exchange_id, &exchange, *id, OrderSide::Sell, *fee)?;
+				}
+			}
 
-			Self::deposit_event(RawEvent::LiquidityRemoved(sender, to, token_ids, token_amounts, currency_amounts));
+			let pools = Self::pool_states(exchange_id, &exchange.vault, &token_ids);
+			Self::deposit_event(RawEvent::TokenToCurrency(
+				exchange_id, sender, to, token_ids, token_amounts_in, currency_amounts_out, fees, pools,
+			));
 
 			Ok(())
 		}
 
-	}
-}
+		/// Sell exactly `amount_in` of `path[0]` for at least `min_amount_out` of the last token
+		/// in `path`, trading `path[i]` for `path[i + 1]` in `markets[i]`, an exchange and the
+		/// quote currency to trade through.
+		#[weight = 0]
+		#[transactional]
+		pub fn swap_exact_tokens_for_tokens(
+			origin,
+			markets: Vec<(ExchangeId, CurrencyId)>,
+			path: Vec<T::TokenId>,
+			amount_in: T::TokenBalance,
+			min_amount_out: T::TokenBalance,
+			to: T::AccountId,
+			deadline: BlockNumber,
+			max_price_impact: Option<u32>,
+			referrer: Option<T::AccountId>,
+		) -> DispatchResult {
+			let sender = ensure_signed(origin)?;
+			ensure!(referrer.as_ref() != Some(&sender), Error::<T>::InvalidReferrer);
+			Self::ensure_deadline(deadline)?;
 
-impl<T: Trait> Module<T> {
-	fn get_amount_in(
-		amount_out: T::TokenBalance,
-		reserve_in: T::TokenBalance,
-		reserve_out: T::TokenBalance,
-	) -> Result<T::TokenBalance, DispatchError> {
-		ensure!(amount_out > Zero::zero() , Error::<T>::InsufficientOutputAmount);
-		ensure!(reserve_in > Zero::zero()  && reserve_out > Zero::zero() , Error::<T>::InsufficientLiquidity);
+			let route = Self::build_route(&markets, &path)?;
 
-		let numerator = reserve_in * amount_out * 1000u32.into();
-		let denominator = (reserve_out - amount_out) * 995u32.into();
-		let (amount_in, _) = Self::div_round(numerator, denominator);
+			let mut amount = amount_in;
+			for (i, leg) in route.iter().enumerate() {
+				let (currency_amount, amount_out) = Self::quote_leg_out(leg, amount)?;
+				let recipient = if i + 1 == route.len() { &to } else { &sender };
 
-		Ok(amount_in)
-	}
+				Self::apply_leg(leg, &sender, recipient, amount, currency_amount, amount_out, max_price_impact, i == 0 && referrer.is_some())?;
+				amount = amount_out;
+			}
 
+			ensure!(amount >= min_amount_out, Error::<T>::InsufficientOutputAmount);
+			if let Some(referrer) = &referrer {
+				Self::pay_route_referral(referrer, &route[0], amount_in)?;
+			}
+
+			let pools = Self::route_pool_states(&route);
+			let exchange_ids = route.iter().map(|leg| leg.exchange_id).collect();
+			Self::deposit_event(RawEvent::TokenToToken(sender, to, exchange_ids, path, amount_in, amount, pools));
+
+			Ok(())
+		}
+
+		/// Buy exactly `amount_out` of the last token in `path`, selling at most `max_amount_in`
+		/// of `path[0]`, trading `path[i]` for `path[i + 1]` in `markets[i]`.
+		#[weight = 0]
+		#[transactional]
+		pub fn swap_tokens_for_exact_tokens(
+			origin,
+			markets: Vec<(ExchangeId, CurrencyId)>,
+			path: Vec<T::TokenId>,
+			amount_out: T::TokenBalance,
+			max_amount_in: T::TokenBalance,
+			to: T::AccountId,
+			deadline: BlockNumber,
+			max_price_impact: Option<u32>,
+			referrer: Option<T::AccountId>,
+		) -> DispatchResult {
+			let sender = ensure_signed(origin)?;
+			ensure!(referrer.as_ref() != Some(&sender), Error::<T>::InvalidReferrer);
+			Self::ensure_deadline(deadline)?;
+
+			let route = Self::build_route(&markets, &path)?;
+
+			// Quote backwards from the output; pools are never visited twice, so the
+			// quotes stay valid while the legs are applied in order.
+			let mut amounts = vec![(T::TokenBalance::from(0u32), T::TokenBalance::from(0u32), amount_out); route.len()];
+			let mut amount = amount_out;
+			for i in (0..route.len()).rev() {
+				let (amount_in, currency_amount) = Self::quote_leg_in(&route[i], amount)?;
+				amounts[i] = (amount_in, currency_amount, amount);
+				amount = amount_in;
+			}
+
+			ensure!(amount <= max_amount_in, Error::<T>::MaxTokenAmountExceeded);
+
+			for (i, leg) in route.iter().enumerate() {
+				let (leg_in, currency_amount, leg_out) = amounts[i];
+				let recipient = if i + 1 == route.len() { &to } else { &sender };
+
+				Self::apply_leg(leg, &sender, recipient, leg_in, currency_amount, leg_out, max_price_impact, i == 0 && referrer.is_some())?;
+			}
+			if let Some(referrer) = &referrer {
+				Self::pay_route_referral(referrer, &route[0], amount)?;
+			}
+
+			let pools = Self::route_pool_states(&route);
+			let exchange_ids = route.iter().map(|leg| leg.exchange_id).collect();
+			Self::deposit_event(RawEvent::TokenToToken(sender, to, exchange_ids, path, amount, amount_out, pools));
+
+			Ok(())
+		}
+
+		/// Lend `token_amount_out` and `currency_amount_out` of a pool to the caller, dispatch
+		/// `call` on their behalf, then take back `token_amount_in` and `currency_amount_in`.
+		///
+		/// Reverts unless the repaid reserves, net of the swap fee on what was paid in, keep
+		/// the pool invariant at least where it was.
+		///
+		/// Weighs the inner call plus the lock, the four transfers and the pool checks around it.
+		#[weight = call.get_dispatch_info().weight.saturating_add(T::DbWeight::get().reads_writes(16, 14))]
+		#[transactional]
+		pub fn flash_swap(
+			origin,
+			exchange_id: ExchangeId,
+			currency_id: CurrencyId,
+			token_id: T::TokenId,
+			token_amount_out: T::TokenBalance,
+			currency_amount_out: T::TokenBalance,
+			token_amount_in: T::TokenBalance,
+			currency_amount_in: T::TokenBalance,
+			call: Box<<T as Trait>::Call>,
+		) -> DispatchResult {
+			let sender = ensure_signed(origin)?;
+			let exchange_id = Self::market_id(exchange_id, currency_id)?;
+
+			let exchange = Self::get_trading_exchange(exchange_id)?;
+			ensure!(Self::total_supplies(exchange_id, token_id) > Zero::zero(), Error::<T>::InsufficientLiquidity);
+			ensure!(
+				token_amount_out > Zero::zero() || currency_amount_out > Zero::zero(),
+				Error::<T>::InsufficientOutputAmount
+			);
+
+			let token_reserve = token::Module::<T>::balance_of(&exchange.vault, &token_id);
+			let currency_reserve = Self::currency_reserves(exchange_id, token_id);
+			ensure!(
+				token_amount_out < token_reserve && currency_amount_out < currency_reserve,
+				Error::<T>::InsufficientLiquidity
+			);
+			Self::update_price_observations(exchange_id, token_id, currency_reserve, token_reserve);
+
+			FlashSwapLocks::insert(exchange_id, true);
+
+			token::Module::<T>::transfer_from(&exchange.vault, &sender, &token_id, token_amount_out)?;
+			currency::Module::<T>::do_transfer_from(&exchange.vault, &sender, &exchange.currency, currency_amount_out)?;
+
+			call.dispatch(system::RawOrigin::Signed(sender.clone()).into()).map_err(|e| e.error)?;
+
+			token::Module::<T>::transfer_from(&sender, &exchange.vault, &token_id, token_amount_in)?;
+			currency::Module::<T>::do_transfer_from(&sender, &exchange.vault, &exchange.currency, currency_amount_in)?;
+
+			FlashSwapLocks::remove(exchange_id);
+
+			let new_token_reserve = (token_reserve - token_amount_out)
+				.checked_add(&token_amount_in)
+				.ok_or(Error::<T>::Overflow)?;
+			let new_currency_reserve = (currency_reserve - currency_amount_out)
+				.checked_add(&currency_amount_in)
+				.ok_or(Error::<T>::Overflow)?;
+
+			let curve = Self::pool_curve(exchange_id, token_id);
+			ensure!(
+				Self::invariant_with_fee(curve, new_token_reserve, token_amount_in, new_currency_reserve, currency_amount_in)?
+					>= Self::invariant_with_fee(curve, token_reserve, Zero::zero(), currency_reserve, Zero::zero())?,
+				Error::<T>::FlashSwapNotRepaid
+			);
+
+			Self::ensure_price_move(
+				exchange_id, token_id,
+				(currency_reserve, token_reserve),
+				(new_currency_reserve, new_token_reserve),
+				None,
+			)?;
+
+			CurrencyReserves::<T>::insert(exchange_id, token_id, new_currency_reserve);
+
+			Self::deposit_event(RawEvent::FlashSwap(
+				exchange_id,
+				sender,
+				token_id,
+				token_amount_out,
+				currency_amount_out,
+				token_amount_in,
+				currency_amount_in,
+			));
+
+			Ok(())
+		}
+
+		/// Deposit tokens and the matching currency, minting liquidity shares to `to`.
+		///
+		/// With `as_positions` the shares stay with the DEX and `to` gets one position NFT per
+		/// token instead, recording the pool, the shares and the entry prices.
+		#[weight = 0]
+		#[transactional]
+		pub fn add_liquidity(
+			origin,
+			exchange_id: ExchangeId,
+			currency_id: CurrencyId,
+			to: T::AccountId,
+			token_ids: Vec<T::TokenId>,
+			token_amounts: Vec<T::TokenBalance>,
+			max_currencys: Vec<T::TokenBalance>,
+			deadline: BlockNumber,
+			as_positions: bool,
+		) -> DispatchResult {
+			let sender = ensure_signed(origin)?;
+			let exchange_id = Self::market_id(exchange_id, currency_id)?;
+			Self::ensure_deadline(deadline)?;
+
+			let exchange = Self::get_deposit_exchange(exchange_id)?;
+			let currency_token = currency::Module::<T>::get_currency_token(&exchange.currency)?;
+			Self::ensure_token_ids(&token_ids, &[&token_amounts, &max_currencys])?;
+
+			let n = token_ids.len();
+			let mut total_currency = T::TokenBalance::from(0u32);
+
+			let mut liquidity_tokens = Vec::with_capacity(n);
+			let mut liquidities_to_mint = vec![T::TokenBalance::from(0u32); n];
+			let mut currency_amounts = vec![T::TokenBalance::from(0u32); n];
+
+			let token_reserves = Self::get_token_reserves(&exchange.vault, &token_ids)?;
+
+			for i in 0..n {
+				let id = token_ids[i];
+				let amount = token_amounts[i];
+
+				ensure!(max_currencys[i] > Zero::zero() , Error::<T>::InvalidMaxCurrency);
+				ensure!(amount > Zero::zero() , Error::<T>::InsufficientTokenAmount);
+
+				ensure!(currency_token != id, Error::<T>::SameCurrencyAndToken);
+
+				liquidity_tokens.push(Self::get_or_create_liquidity_token(exchange_id, &exchange.vault, id)?);
+
+				let mut total_liquidity = Self::total_supplies(exchange_id, id);
+				Self::update_price_observations(exchange_id, id, Self::currency_reserves(exchange_id, id), token_reserves[i]);
+
+				if total_liquidity > Zero::zero()  {
+					let currency_reserve = Self::currency_reserves(exchange_id, id);
+					let token_reserve = token_reserves[i];
+
+					// The provider pays the currency amount rounded up but is credited shares
+					// for the amount rounded down.
+					let numerator = amount.checked_mul(&currency_reserve).ok_or(Error::<T>::Overflow)?;
+					let (currency_amount, rounded) = Self::div_round(numerator, token_reserve)?;
+					ensure!(max_currencys[i] >= currency_amount, Error::<T>::MaxCurrencyAmountExceeded);
+
+					let fixed_currency_amount = if rounded { currency_amount - 1u32.into() } else { currency_amount };
+					liquidities_to_mint[i] = Self::mul_div(fixed_currency_amount, total_liquidity, currency_reserve)?;
+					ensure!(liquidities_to_mint[i] > Zero::zero(), Error::<T>::InsufficientLiquidityMinted);
+					currency_amounts[i] = currency_amount;
+
+					Self::increase_currency_reserve(exchange_id, id, currency_amount)?;
+				} else {
+					// The first shares are locked for good, so the pool can never be drained
+					// back to a price anyone may set.
+					ensure!(token::Module::<T>::is_fungible(&id), Error::<T>::NonFungibleToken);
+					let max_currency = max_currencys[i];
+					let locked = T::MinimumLiquidity::get();
+					ensure!(max_currency >= Self::min_pool_size(exchange_id), Error::<T>::InvalidCurrencyAmount);
+					ensure!(max_currency > locked, Error::<T>::InvalidCurrencyAmount);
+
+					if !locked.is_zero() {
+						token::Module::<T>::mint(&Self::locked_liquidity_account(), &liquidity_tokens[i], locked)?;
+					}
+
+					liquidities_to_mint[i] = max_currency - locked;
+					currency_amounts[i] = max_currency;
+
+					CurrencyReserves::<T>::insert(exchange_id, id, max_currency);
+					total_liquidity = locked;
+				}
+
+				total_currency = total_currency.checked_add(&currency_amounts[i]).ok_or(Error::<T>::Overflow)?;
+				let total_supply = total_liquidity.checked_add(&liquidities_to_mint[i]).ok_or(Error::<T>::Overflow)?;
+				TotalSupplies::<T>::insert(exchange_id, id, total_supply);
+			}
+
+			if as_positions {
+				token::Module::<T>::batch_mint(&Self::position_custody(), &liquidity_tokens, liquidities_to_mint.clone())?;
+			} else {
+				token::Module::<T>::batch_mint(&to, &liquidity_tokens, liquidities_to_mint.clone())?;
+			}
+
+			currency::Module::<T>::do_transfer_from(&sender, &exchange.vault, &exchange.currency, total_currency)?;
+			token::Module::<T>::batch_transfer_from(&sender, &exchange.vault, &token_ids, token_amounts.clone())?;
+
+			if as_positions {
+				for i in 0..n {
+					Self::open_position(&to, exchange_id, &exchange.vault, token_ids[i], liquidities_to_mint[i])?;
+				}
+			}
+
+			let pools = Self::pool_states(exchange_id, &exchange.vault, &token_ids);
+			Self::deposit_event(RawEvent::LiquidityAdded(
+				exchange_id, sender, to, token_ids, token_amounts, currency_amounts, liquidities_to_mint, pools,
+			));
+
+			Ok(())
+		}
+
+		/// Add liquidity to the `token_id` pool from currency alone.
+		///
+		/// Swaps the part of `currency_amount` that leaves the rest balanced against the tokens
+		/// bought, then deposits both, minting at least `min_liquidity` shares to `to`. Rounding
+		/// dust is left with the sender.
+		#[weight = 0]
+		#[transactional]
+		pub fn zap_currency(
+			origin,
+			exchange_id: ExchangeId,
+			currency_id: CurrencyId,
+			token_id: T::TokenId,
+			currency_amount: T::TokenBalance,
+			min_liquidity: T::TokenBalance,
+			to: T::AccountId,
+			deadline: BlockNumber,
+		) -> DispatchResult {
+			let sender = ensure_signed(origin)?;
+			let exchange_id = Self::market_id(exchange_id, currency_id)?;
+			Self::ensure_deadline(deadline)?;
+
+			let exchange = Self::get_zap_exchange(exchange_id, token_id)?;
+			let token_reserve = token::Module::<T>::balance_of(&exchange.vault, &token_id);
+			let swap_amount = Self::zap_swap_amount(
+				Self::pool_curve(exchange_id, token_id), currency_amount, Self::currency_reserves(exchange_id, token_id), token_reserve,
+			)?;
+			let tokens_bought = Self::do_exact_currency_for_tokens(exchange_id, &exchange, token_id, swap_amount, None, false)?;
+			ensure!(tokens_bought > Zero::zero(), Error::<T>::InsufficientOutputAmount);
+
+			currency::Module::<T>::do_transfer_from(&sender, &exchange.vault, &exchange.currency, swap_amount)?;
+			token::Module::<T>::transfer_from(&exchange.vault, &sender, &token_id, tokens_bought)?;
+
+			let pools = Self::pool_states(exchange_id, &exchange.vault, &vec![token_id]);
+			Self::deposit_event(RawEvent::CurrencyToToken(
+				exchange_id, sender.clone(), sender.clone(), vec![token_id], vec![tokens_bought], vec![swap_amount],
+				vec![Self::swap_fee(swap_amount)?], pools,
+			));
+
+			Self::zap_deposit(exchange_id, &exchange, &sender, &to, token_id, tokens_bought, currency_amount - swap_amount, min_liquidity)
+		}
+
+		/// Add liquidity to the `token_id` pool from tokens alone, the counterpart of `zap_currency`.
+		#[weight = 0]
+		#[transactional]
+		pub fn zap_tokens(
+			origin,
+			exchange_id: ExchangeId,
+			currency_id: CurrencyId,
+			token_id: T::TokenId,
+			token_amount: T::TokenBalance,
+			min_liquidity: T::TokenBalance,
+			to: T::AccountId,
+			deadline: BlockNumber,
+		) -> DispatchResult {
+			let sender = ensure_signed(origin)?;
+			let exchange_id = Self::market_id(exchange_id, currency_id)?;
+			Self::ensure_deadline(deadline)?;
+
+			let exchange = Self::get_zap_exchange(exchange_id, token_id)?;
+			let token_reserve = token::Module::<T>::balance_of(&exchange.vault, &token_id);
+			let swap_amount = Self::zap_swap_amount(
+				Self::pool_curve(exchange_id, token_id), token_amount, token_reserve, Self::currency_reserves(exchange_id, token_id),
+			)?;
+			let currency_bought = Self::do_exact_tokens_for_currency(exchange_id, &exchange, token_id, swap_amount, None, false)?;
+			ensure!(currency_bought > Zero::zero(), Error::<T>::InsufficientOutputAmount);
+
+			token::Module::<T>::transfer_from(&sender, &exchange.vault, &token_id, swap_amount)?;
+			currency::Module::<T>::do_transfer_from(&exchange.vault, &sender, &exchange.currency, currency_bought)?;
+
+			let pools = Self::pool_states(exchange_id, &exchange.vault, &vec![token_id]);
+			Self::deposit_event(RawEvent::TokenToCurrency(
+				exchange_id, sender.clone(), sender.clone(), vec![token_id], vec![swap_amount], vec![currency_bought],
+				vec![Self::swap_fee(swap_amount)?], pools,
+			));
+
+			Self::zap_deposit(exchange_id, &exchange, &sender, &to, token_id, token_amount - swap_amount, currency_bought, min_liquidity)
+		}
+
+		/// Burn liquidity shares held by the sender, paying the pool share out to `to`.
+		///
+		/// Position NFTs of the sender listed in `positions` are burned first, releasing their
+		/// shares to the sender, so `liquidities` may spend them.
+		#[weight = 0]
+		#[transactional]
+		pub fn remove_liquidity(
+			origin,
+			exchange_id: ExchangeId,
+			currency_id: CurrencyId,
+			to: T::AccountId,
+			token_ids: Vec<T::TokenId>,
+			liquidities: Vec<T::TokenBalance>,
+			min_currencys: Vec<T::TokenBalance>,
+			min_tokens: Vec<T::TokenBalance>,
+			deadline: BlockNumber,
+			positions: Vec<AssetId>,
+		) -> DispatchResult {
+			let sender = ensure_signed(origin)?;
+			let exchange_id = Self::market_id(exchange_id, currency_id)?;
+			Self::ensure_deadline(deadline)?;
+
+			let exchange = Self::get_exchange(exchange_id)?;
+			Self::ensure_token_ids(&token_ids, &[&liquidities, &min_currencys, &min_tokens])?;
+
+			for asset_id in positions {
+				Self::close_position(&sender, exchange_id, asset_id)?;
+			}
+
+			let n = token_ids.len();
+			let mut total_currency = T::TokenBalance::from(0u32);
+
+			let mut liquidity_tokens = Vec::with_capacity(n);
+			let mut token_amounts = vec![T::TokenBalance::from(0u32); n];
+			let mut currency_amounts = vec![T::TokenBalance::from(0u32); n];
+
+			let token_reserves = Self::get_token_reserves(&exchange.vault, &token_ids)?;
+
+			for i in 0..n {
+				let id = token_ids[i];
+				let liquidity = liquidities[i];
+				let token_reserve = token_reserves[i];
+
+				let liquidity_token = Self::liquidity_tokens(exchange_id, id).ok_or(Error::<T>::InsufficientLiquidity)?;
+				liquidity_tokens.push(liquidity_token);
+
+				let total_liquidity = Self::total_supplies(exchange_id, id);
+				ensure!(total_liquidity > Zero::zero() , Error::<T>::InsufficientLiquidity);
+				let remaining_liquidity = total_liquidity.checked_sub(&liquidity).ok_or(Error::<T>::InsufficientLiquidity)?;
+
+				let currency_reserve = Self::currency_reserves(exchange_id, id);
+				Self::update_price_observations(exchange_id, id, currency_reserve, token_reserve);
+
+				// Both shares of the reserves are rounded down in favour of the remaining providers.
+				let currency_amount = Self::mul_div(liquidity, currency_reserve, total_liquidity)?;
+				let token_amount = Self::mul_div(liquidity, token_reserve, total_liquidity)?;
+
+				ensure!(currency_amount >= min_currencys[i], Error::<T>::InsufficientCurrencyAmount);
+				ensure!(token_amount >= min_tokens[i], Error::<T>::InsufficientTokenAmount);
+
+				total_currency = total_currency.checked_add(&currency_amount).ok_or(Error::<T>::Overflow)?;
+				token_amounts[i] = token_amount;
+				currency_amounts[i] = currency_amount;
+
+				Self::decrease_currency_reserve(exchange_id, id, currency_amount)?;
+				TotalSupplies::<T>::insert(exchange_id, id, remaining_liquidity);
+			}
+
+			token::Module::<T>::batch_burn(&sender, &liquidity_tokens, liquidities.clone())?;
+
+			currency::Module::<T>::do_transfer_from(&exchange.vault, &to, &exchange.currency, total_currency)?;
+			token::Module::<T>::batch_transfer_from(&exchange.vault, &to, &token_ids, token_amounts.clone())?;
+
+			let pools = Self::pool_states(exchange_id, &exchange.vault, &token_ids);
+			Self::deposit_event(RawEvent::LiquidityRemoved(
+				exchange_id, sender, to, token_ids, token_amounts, currency_amounts, liquidities, pools,
+			));
+
+			if Self::exchange_status(exchange_id).closed {
+				Self::remove_exchange_if_empty(exchange_id)?;
+			}
+
+			Ok(())
+		}
+
+		/// Escrow `amount_in` of `token_in` for a swap whose other parameters stay hidden until
+		/// revealed.
+		///
+		/// `commitment` is `swap_commitment_hash` of the sender and the whole swap, input
+		/// included. Unrevealed commitments are refunded after `RevealWindow` blocks.
+		#[weight = 0]
+		#[transactional]
+		pub fn commit_swap(origin, commitment: T::Hash, token_in: T::TokenId, amount_in: T::TokenBalance) -> DispatchResult {
+			let sender = ensure_signed(origin)?;
+			ensure!(amount_in > Zero::zero(), Error::<T>::InsufficientInputAmount);
+			ensure!(!SwapCommitments::<T>::contains_key(&sender, commitment), Error::<T>::CommitmentAlreadyExists);
+
+			token::Module::<T>::transfer_from(&sender, &Self::commitment_escrow(), &token_in, amount_in)?;
+
+			let now = system::Module::<T>::block_number();
+			SwapCommitments::<T>::insert(&sender, commitment, SwapCommitment {
+				token_in,
+				amount_in,
+				committed_at: now,
+			});
+			CommitmentExpiries::<T>::append(now.saturating_add(T::RevealWindow::get()), (sender.clone(), commitment));
+
+			Self::deposit_event(RawEvent::SwapCommitted(commitment, sender, token_in, amount_in));
+
+			Ok(())
+		}
+
+		/// Reveal a commitment and execute its swap against the pool, selling the escrowed input
+		/// for at least `min_amount_out` of `token_out`, paid to `to`.
+		#[weight = 0]
+		#[transactional]
+		pub fn reveal_swap(
+			origin,
+			commitment: T::Hash,
+			exchange_id: ExchangeId,
+			currency_id: CurrencyId,
+			token_out: T::TokenId,
+			min_amount_out: T::TokenBalance,
+			to: T::AccountId,
+			salt: T::Hash,
+		) -> DispatchResult {
+			let sender = ensure_signed(origin)?;
+
+			let swap = SwapCommitments::<T>::take(&sender, commitment).ok_or(Error::<T>::InvalidCommitment)?;
+
+			let now = system::Module::<T>::block_number();
+			ensure!(now > swap.committed_at, Error::<T>::RevealTooEarly);
+			ensure!(now <= swap.committed_at.saturating_add(T::RevealWindow::get()), Error::<T>::RevealWindowExpired);
+			ensure!(
+				Self::swap_commitment_hash(
+					&sender, exchange_id, currency_id, swap.token_in, swap.amount_in, token_out, min_amount_out, &to, salt,
+				) == commitment,
+				Error::<T>::InvalidReveal
+			);
+
+			let exchange_id = Self::market_id(exchange_id, currency_id)?;
+			let exchange = Self::get_trading_exchange(exchange_id)?;
+			let currency_token = currency::Module::<T>::get_currency_token(&exchange.currency)?;
+			let escrow = Self::commitment_escrow();
+
+			Self::deposit_event(RawEvent::SwapRevealed(commitment, sender.clone()));
+
+			if swap.token_in == currency_token {
+				let token_amount = Self::do_exact_currency_for_tokens(exchange_id, &exchange, token_out, swap.amount_in, None, false)?;
+				ensure!(token_amount > Zero::zero(), Error::<T>::InsufficientOutputAmount);
+				ensure!(token_amount >= min_amount_out, Error::<T>::InsufficientOutputAmount);
+
+				currency::Module::<T>::do_transfer_from(&escrow, &exchange.vault, &exchange.currency, swap.amount_in)?;
+				token::Module::<T>::transfer_from(&exchange.vault, &to, &token_out, token_amount)?;
+
+				let pools = Self::pool_states(exchange_id, &exchange.vault, &vec![token_out]);
+				Self::deposit_event(RawEvent::CurrencyToToken(
+					exchange_id, sender, to, vec![token_out], vec![token_amount], vec![swap.amount_in],
+					vec![Self::swap_fee(swap.amount_in)?], pools,
+				));
+			} else {
+				ensure!(token_out == currency_token, Error::<T>::InvalidPath);
+
+				let currency_amount = Self::do_exact_tokens_for_currency(exchange_id, &exchange, swap.token_in, swap.amount_in, None, false)?;
+				ensure!(currency_amount > Zero::zero(), Error::<T>::InsufficientOutputAmount);
+				ensure!(currency_amount >= min_amount_out, Error::<T>::InsufficientOutputAmount);
+
+				token::Module::<T>::transfer_from(&escrow, &exchange.vault, &swap.token_in, swap.amount_in)?;
+				currency::Module::<T>::do_transfer_from(&exchange.vault, &to, &exchange.currency, currency_amount)?;
+
+				let pools = Self::pool_states(exchange_id, &exchange.vault, &vec![swap.token_in]);
+				Self::deposit_event(RawEvent::TokenToCurrency(
+					exchange_id, sender, to, vec![swap.token_in], vec![swap.amount_in], vec![currency_amount],
+					vec![Self::swap_fee(swap.amount_in)?], pools,
+				));
+			}
+
+			Ok(())
+		}
+
+		/// Place a limit order to trade `amount` of `token_id` at `price` currency per token.
+		///
+		/// A buy order escrows `amount * price` of the exchange currency rounded up, a sell order
+		/// escrows the tokens. `OrderDeposit` is reserved on top until the order is filled or
+		/// cancelled. Swaps fill the order whenever it beats the pool price.
+		#[weight = 0]
+		#[transactional]
+		pub fn place_order(
+			origin,
+			exchange_id: ExchangeId,
+			currency_id: CurrencyId,
+			token_id: T::TokenId,
+			side: OrderSide,
+			price: FixedU128,
+			amount: T::TokenBalance,
+		) -> DispatchResult {
+			let sender = ensure_signed(origin)?;
+			let exchange_id = Self::market_id(exchange_id, currency_id)?;
+
+			let exchange = Self::get_trading_exchange(exchange_id)?;
+			let currency_token = currency::Module::<T>::get_currency_token(&exchange.currency)?;
+			ensure!(currency_token != token_id, Error::<T>::SameCurrencyAndToken);
+			ensure!(token::Module::<T>::is_fungible(&token_id), Error::<T>::NonFungibleToken);
+			ensure!(!price.is_zero(), Error::<T>::InvalidPrice);
+			ensure!(amount > Zero::zero(), Error::<T>::InsufficientTokenAmount);
+
+			let deposit = T::OrderDeposit::get();
+			T::DepositCurrency::reserve(&sender, deposit)?;
+
+			let escrow = Self::order_escrow();
+			let currency_escrowed = match side {
+				OrderSide::Buy => {
+					let cost = Self::order_value(amount, price, true)?;
+					currency::Module::<T>::do_transfer_from(&sender, &escrow, &exchange.currency, cost)?;
+					cost
+				}
+				OrderSide::Sell => {
+					token::Module::<T>::transfer_from(&sender, &escrow, &token_id, amount)?;
+					Zero::zero()
+				}
+			};
+
+			let order_id = Self::next_order_id();
+			Self::mutate_book(exchange_id, token_id, side, |book| -> DispatchResult {
+				ensure!(book.len() < T::MaxOrdersPerPool::get() as usize, Error::<T>::OrderBookFull);
+
+				let position = match side {
+					OrderSide::Buy => book.iter().position(|(p, _)| *p < price),
+					OrderSide::Sell => book.iter().position(|(p, _)| *p > price),
+				};
+				book.insert(position.unwrap_or(book.len()), (price, order_id));
+
+				Ok(())
+			})?;
+
+			Orders::<T>::insert(order_id, LimitOrder {
+				owner: sender.clone(),
+				exchange_id,
+				token_id,
+				side,
+				price,
+				amount,
+				currency_escrowed,
+				deposit,
+			});
+			NextOrderId::mutate(|id| *id += <OrderId as One>::one());
+
+			Self::deposit_event(RawEvent::OrderPlaced(order_id, sender, exchange_id, token_id, side, price, amount));
+
+			Ok(())
+		}
+
+		/// Cancel an order, refunding what is left in escrow to its owner.
+		#[weight = 0]
+		#[transactional]
+		pub fn cancel_order(origin, order_id: OrderId) -> DispatchResult {
+			let sender = ensure_signed(origin)?;
+
+			let order = Self::orders(order_id).ok_or(Error::<T>::InvalidOrderId)?;
+			ensure!(order.owner == sender, Error::<T>::NotOrderOwner);
+			let exchange = Self::exchanges(order.exchange_id).ok_or(Error::<T>::InvalidExchangeId)?;
+
+			Self::refund_order(&order, &exchange)?;
+			Self::remove_order(order_id, &order);
+
+			Self::deposit_event(RawEvent::OrderCancelled(order_id, sender));
+
+			Ok(())
+		}
+
+		/// Take `amount` tokens of an order at its price, whatever the pool price.
+		#[weight = 0]
+		#[transactional]
+		pub fn fill_order(origin, order_id: OrderId, amount: T::TokenBalance, to: T::AccountId) -> DispatchResult {
+			let sender = ensure_signed(origin)?;
+
+			let order = Self::orders(order_id).ok_or(Error::<T>::InvalidOrderId)?;
+			ensure!(amount > Zero::zero() && amount <= order.amount, Error::<T>::InsufficientTokenAmount);
+
+			Self::do_fill_order(order_id, order, &sender, &to, amount)?;
+
+			Ok(())
+		}
+	}
+}
+
+impl<T: Trait> Module<T> {
+	/// The exchange of `currency_id` and the tokens with liquidity in it.
+	pub fn exchange_for_currency(currency_id: CurrencyId) -> Option<(ExchangeId, Vec<T::TokenId>)> {
+		let exchange_id = Self::exchange_by_currency(currency_id)?;
+		let mut token_ids: Vec<_> = TotalSupplies::<T>::iter_prefix(exchange_id)
+			.filter(|(_, supply)| !supply.is_zero())
+			.map(|(token_id, _)| token_id)
+			.collect();
+		token_ids.sort();
+
+		Some((exchange_id, token_ids))
+	}
+
+	/// What selling `amount_in` of `path[0]` along a route pays out, as `swap_exact_tokens_for_tokens`
+	/// would trade it through the pools.
+	pub fn quote_exact_in(
+		markets: Vec<(ExchangeId, CurrencyId)>,
+		path: Vec<T::TokenId>,
+		amount_in: T::TokenBalance,
+	) -> Option<T::TokenBalance> {
+		let route = Self::build_route(&markets, &path).ok()?;
+		route.iter().try_fold(amount_in, |amount, leg| Self::quote_leg_out(leg, amount).ok().map(|(_, amount_out)| amount_out))
+	}
+
+	/// What buying `amount_out` of the last token of `path` along a route costs, as
+	/// `swap_tokens_for_exact_tokens` would trade it through the pools.
+	pub fn quote_exact_out(
+		markets: Vec<(ExchangeId, CurrencyId)>,
+		path: Vec<T::TokenId>,
+		amount_out: T::TokenBalance,
+	) -> Option<T::TokenBalance> {
+		let route = Self::build_route(&markets, &path).ok()?;
+		route.iter().rev().try_fold(amount_out, |amount, leg| Self::quote_leg_in(leg, amount).ok().map(|(amount_in, _)| amount_in))
+	}
+
+	/// Moves pools of the layout keyed by token alone to the oldest exchange whose vault holds
+	/// their tokens, once, and gives them a liquidity token.
+	///
+	/// Shares were minted as the pool token itself then, so they can't be told apart from
+	/// holders' tokens on chain. They stay outstanding in `LegacyLiquidity` until converted
+	/// with `convert_legacy_liquidity`.
+	fn migrate_legacy_pools() -> Weight {
+		if LegacyPoolsMigrated::get() {
+			return 0;
+		}
+
+		let mut exchanges: Vec<_> = Exchanges::<T>::iter().collect();
+		exchanges.sort_by_key(|(exchange_id, _)| *exchange_id);
+		let mut reads = exchanges.len() as Weight + 1;
+		let mut writes: Weight = 0;
+		let mut complete = true;
+
+		let pools: Vec<_> = StorageIterator::<T::TokenBalance>::new(b"DexModule", b"TotalSupplies")
+			.filter_map(|(key, supply)| Self::legacy_token_id(&key).map(|token_id| (key, token_id, supply)))
+			.collect();
+
+		for (key, token_id, supply) in pools {
+			reads += exchanges.len() as Weight + 2;
+
+			let exchange = exchanges
+				.iter()
+				.find(|(_, exchange)| !token::Module::<T>::balance_of(&exchange.vault, &token_id).is_zero());
+			if let Some((exchange_id, exchange)) = exchange {
+				if Self::get_or_create_liquidity_token(*exchange_id, &exchange.vault, token_id).is_err() {
+					complete = false;
+					continue;
+				}
+				let currency_reserve = take_storage_value::<T::TokenBalance>(b"DexModule", b"CurrencyReserves", &key)
+					.unwrap_or_else(Zero::zero);
+				TotalSupplies::<T>::insert(exchange_id, token_id, supply);
+				CurrencyReserves::<T>::insert(exchange_id, token_id, currency_reserve);
+				LegacyLiquidity::<T>::insert(exchange_id, token_id, supply);
+				writes += 5;
+			}
+			take_storage_value::<T::TokenBalance>(b"DexModule", b"TotalSupplies", &key);
+			writes += 1;
+		}
+
+		if complete {
+			// What is left belongs to pools no vault holds tokens of, which are empty.
+			let leftovers: Vec<_> = StorageIterator::<T::TokenBalance>::new(b"DexModule", b"CurrencyReserves")
+				.filter(|(key, _)| Self::legacy_token_id(key).is_some())
+				.map(|(key, _)| key)
+				.collect();
+			for key in leftovers {
+				take_storage_value::<T::TokenBalance>(b"DexModule", b"CurrencyReserves", &key);
+				writes += 1;
+			}
+
+			LegacyPoolsMigrated::put(true);
+			writes += 1;
+		}
+
+		T::DbWeight::get().reads_writes(reads, writes)
+	}
+
+	/// Decodes a `blake2_128_concat` key of a map keyed by token alone, as pool state was
+	/// before it was keyed by exchange.
+	fn legacy_token_id(key: &[u8]) -> Option<T::TokenId> {
+		if key.len() < 16 {
+			return None;
+		}
+		let (hash, mut encoded) = key.split_at(16);
+		let token_id = T::TokenId::decode(&mut encoded).ok()?;
+		if !encoded.is_empty() || hash != &sp_io::hashing::blake2_128(&token_id.encode())[..] {
+			return None;
+		}
+		Some(token_id)
+	}
+
+	/// Indexes exchanges created before `ExchangeByCurrency` existed. Where a currency has
+	/// several exchanges the oldest one is made canonical; the others can still be traded
+	/// and withdrawn from, but new exchanges for the currency are refused. Runs once.
+	fn migrate_exchange_index() -> Weight {
+		if ExchangeIndexMigrated::get() {
+			return 0;
+		}
+
+		let mut count: Weight = 0;
+
+		for (exchange_id, exchange) in Exchanges::<T>::iter() {
+			count += 1;
+			// Quote markets are reached through their parent exchange only.
+			if MarketParents::contains_key(exchange_id) {
+				continue;
+			}
+			ExchangeByCurrency::mutate(exchange.currency, |canonical| match canonical {
+				Some(id) if *id <= exchange_id => {}
+				_ => *canonical = Some(exchange_id),
+			});
+		}
+		ExchangeIndexMigrated::put(true);
+
+		T::DbWeight::get().reads_writes(count + 1, count + 1)
+	}
+
+	/// Sells `currency_amount` to the `token_id` pool, returning the tokens bought.
+	fn do_exact_currency_for_tokens(
+		exchange_id: ExchangeId,
+		exchange: &ExchangeInfo<T::AccountId>,
+		token_id: T::TokenId,
+		currency_amount: T::TokenBalance,
+		max_price_impact: Option<u32>,
+		referred: bool,
+	) -> Result<T::TokenBalance, DispatchError> {
+		let token_reserve = token::Module::<T>::balance_of(&exchange.vault, &token_id);
+		let currency_reserve = Self::currency_reserves(exchange_id, token_id);
+		Self::update_price_observations(exchange_id, token_id, currency_reserve, token_reserve);
+
+		let curve = Self::pool_curve(exchange_id, token_id);
+		let token_amount = Self::get_amount_out(curve, currency_amount, currency_reserve, token_reserve)?;
+		Self::increase_currency_reserve(exchange_id, token_id, currency_amount)?;
+		Self::record_volume(exchange_id, token_id, currency_amount)?;
+
+		let referral = Self::referral_amount(referred, currency_amount)?;
+
+		Self::ensure_price_move(
+			exchange_id, token_id,
+			(currency_reserve, token_reserve),
+			(currency_reserve.saturating_add(currency_amount).saturating_sub(referral), token_reserve.saturating_sub(token_amount)),
+			max_price_impact,
+		)?;
+
+		Ok(token_amount)
+	}
+
+	/// Buys `token_amount` from the `token_id` pool, returning the currency it costs.
+	fn do_currency_for_exact_tokens(
+		exchange_id: ExchangeId,
+		exchange: &ExchangeInfo<T::AccountId>,
+		token_id: T::TokenId,
+		token_amount: T::TokenBalance,
+		max_price_impact: Option<u32>,
+		referred: bool,
+	) -> Result<T::TokenBalance, DispatchError> {
+		let token_reserve = token::Module::<T>::balance_of(&exchange.vault, &token_id);
+		let currency_reserve = Self::currency_reserves(exchange_id, token_id);
+		Self::update_price_observations(exchange_id, token_id, currency_reserve, token_reserve);
+
+		let curve = Self::pool_curve(exchange_id, token_id);
+		let currency_amount = Self::get_amount_in(curve, token_amount, currency_reserve, token_reserve)?;
+		Self::increase_currency_reserve(exchange_id, token_id, currency_amount)?;
+		Self::record_volume(exchange_id, token_id, currency_amount)?;
+
+		let referral = Self::referral_amount(referred, currency_amount)?;
+
+		Self::ensure_price_move(
+			exchange_id, token_id,
+			(currency_reserve, token_reserve),
+			(currency_reserve.saturating_add(currency_amount).saturating_sub(referral), token_reserve.saturating_sub(token_amount)),
+			max_price_impact,
+		)?;
+
+		Ok(currency_amount)
+	}
+
+	/// Sells `token_amount` to the `token_id` pool, returning the currency received.
+	fn do_exact_tokens_for_currency(
+		exchange_id: ExchangeId,
+		exchange: &ExchangeInfo<T::AccountId>,
+		token_id: T::TokenId,
+		token_amount: T::TokenBalance,
+		max_price_impact: Option<u32>,
+		referred: bool,
+	) -> Result<T::TokenBalance, DispatchError> {
+		let token_reserve = token::Module::<T>::balance_of(&exchange.vault, &token_id);
+		let currency_reserve = Self::currency_reserves(exchange_id, token_id);
+		Self::update_price_observations(exchange_id, token_id, currency_reserve, token_reserve);
+
+		let curve = Self::pool_curve(exchange_id, token_id);
+		let currency_amount = Self::get_amount_out(curve, token_amount, token_reserve, currency_reserve)?;
+		Self::decrease_currency_reserve(exchange_id, token_id, currency_amount)?;
+		Self::record_volume(exchange_id, token_id, currency_amount)?;
+
+		let referral = Self::referral_amount(referred, token_amount)?;
+
+		Self::ensure_price_move(
+			exchange_id, token_id,
+			(currency_reserve, token_reserve),
+			(currency_reserve.saturating_sub(currency_amount), token_reserve.saturating_add(token_amount).saturating_sub(referral)),
+			max_price_impact,
+		)?;
+
+		Ok(currency_amount)
+	}
+
+	/// Buys `currency_amount` from the `token_id` pool, returning the tokens it costs.
+	fn do_tokens_for_exact_currency(
+		exchange_id: ExchangeId,
+		exchange: &ExchangeInfo<T::AccountId>,
+		token_id: T::TokenId,
+		currency_amount: T::TokenBalance,
+		max_price_impact: Option<u32>,
+		referred: bool,
+	) -> Result<T::TokenBalance, DispatchError> {
+		let token_reserve = token::Module::<T>::balance_of(&exchange.vault, &token_id);
+		let currency_reserve = Self::currency_reserves(exchange_id, token_id);
+		Self::update_price_observations(exchange_id, token_id, currency_reserve, token_reserve);
+
+		let curve = Self::pool_curve(exchange_id, token_id);
+		let token_amount = Self::get_amount_in(curve, currency_amount, token_reserve, currency_reserve)?;
+		Self::decrease_currency_reserve(exchange_id, token_id, currency_amount)?;
+		Self::record_volume(exchange_id, token_id, currency_amount)?;
+
+		let referral = Self::referral_amount(referred, token_amount)?;
+
+		Self::ensure_price_move(
+			exchange_id, token_id,
+			(currency_reserve, token_reserve),
+			(currency_reserve.saturating_sub(currency_amount), token_reserve.saturating_add(token_amount).saturating_sub(referral)),
+			max_price_impact,
+		)?;
+
+		Ok(token_amount)
+	}
+
+	/// Checks that the exchange takes both swaps and deposits and that the pool is funded.
+	fn get_zap_exchange(exchange_id: ExchangeId, token_id: T::TokenId) -> Result<ExchangeInfo<T::AccountId>, DispatchError> {
+		Self::get_trading_exchange(exchange_id)?;
+		let exchange = Self::get_deposit_exchange(exchange_id)?;
+		ensure!(Self::total_supplies(exchange_id, token_id) > Zero::zero(), Error::<T>::InsufficientLiquidity);
+		Ok(exchange)
+	}
+
+	/// Part of `amount_in` to swap so that the rest matches the pool ratio after the swap.
+	///
+	/// For a constant product pool, solves `f * s^2 + (1 + f) * r * s - r * a = 0` for the
+	/// swapped amount `s`, with `r` the reserve of the input side and `f` the 99.5% of the
+	/// input left after the fee. StableSwap pools have no closed form, so `s` is bisected
+	/// instead.
+	fn zap_swap_amount(
+		curve: PoolCurve,
+		amount_in: T::TokenBalance,
+		reserve_in: T::TokenBalance,
+		reserve_out: T::TokenBalance,
+	) -> Result<T::TokenBalance, DispatchError> {
+		ensure!(amount_in > Zero::zero(), Error::<T>::InsufficientInputAmount);
+		ensure!(reserve_in > Zero::zero(), Error::<T>::InsufficientLiquidity);
+
+		if let PoolCurve::StableSwap { .. } = curve {
+			return Self::stable_zap_swap_amount(curve, amount_in, reserve_in, reserve_out);
+		}
+
+		let (a, r) = (Self::to_u256(amount_in), Self::to_u256(reserve_in));
+
+		let discriminant = r
+			.checked_mul(r)
+			.and_then(|rr| rr.checked_mul(U256::from(1995u32 * 1995)))
+			.and_then(|d| r.checked_mul(a)?.checked_mul(U256::from(4u32 * 995 * 1000)).and_then(|e| d.checked_add(e)))
+			.ok_or(Error::<T>::Overflow)?;
+		let swap_amount = (discriminant.integer_sqrt() - r * U256::from(1995u32)) / U256::from(1990u32);
+
+		Ok(Self::from_u256(swap_amount))
+	}
+
+	/// The largest swap `s` of `amount_in` whose output is still no larger a share of the pool
+	/// than what is left of the input: `(a - s) * (reserve_out - out) >= out * (reserve_in + s)`.
+	fn stable_zap_swap_amount(
+		curve: PoolCurve,
+		amount_in: T::TokenBalance,
+		reserve_in: T::TokenBalance,
+		reserve_out: T::TokenBalance,
+	) -> Result<T::TokenBalance, DispatchError> {
+		let (a, r_in, r_out) = (Self::to_u256(amount_in), Self::to_u256(reserve_in), Self::to_u256(reserve_out));
+		let balanced = |s: U256| -> Result<bool, DispatchError> {
+			if s.is_zero() {
+				return Ok(true);
+			}
+			let out = Self::to_u256(Self::get_amount_out(curve, Self::from_u256(s), reserve_in, reserve_out)?);
+			let left = (a - s).checked_mul(r_out.saturating_sub(out)).ok_or(Error::<T>::Overflow)?;
+			let bought = out.checked_mul(r_in + s).ok_or(Error::<T>::Overflow)?;
+			Ok(left >= bought)
+		};
+
+		let (mut low, mut high) = (U256::zero(), a);
+		while low < high {
+			let middle = (low + high + U256::one()) / U256::from(2u32);
+			if balanced(middle)? {
+				low = middle;
+			} else {
+				high = middle - U256::one();
+			}
+		}
+
+		Ok(Self::from_u256(low))
+	}
+
+	/// Deposits the largest balanced share of `max_tokens` and `max_currency` held by `sender`
+	/// into a funded pool, minting at least `min_liquidity` shares to `to`.
+	fn zap_deposit(
+		exchange_id: ExchangeId,
+		exchange: &ExchangeInfo<T::AccountId>,
+		sender: &T::AccountId,
+		to: &T::AccountId,
+		token_id: T::TokenId,
+		max_tokens: T::TokenBalance,
+		max_currency: T::TokenBalance,
+		min_liquidity: T::TokenBalance,
+	) -> DispatchResult {
+		let token_reserve = token::Module::<T>::balance_of(&exchange.vault, &token_id);
+		let currency_reserve = Self::currency_reserves(exchange_id, token_id);
+		let total_liquidity = Self::total_supplies(exchange_id, token_id);
+
+		let liquidity = Self::mul_div(max_currency, total_liquidity, currency_reserve)?
+			.min(Self::mul_div(max_tokens, total_liquidity, token_reserve)?);
+		ensure!(liquidity > Zero::zero(), Error::<T>::InsufficientLiquidityMinted);
+		ensure!(liquidity >= min_liquidity, Error::<T>::InsufficientLiquidityMinted);
+
+		// Both sides are paid rounded up, never more than the provider holds.
+		let (currency_amount, _) = Self::div_round(
+			liquidity.checked_mul(&currency_reserve).ok_or(Error::<T>::Overflow)?, total_liquidity,
+		)?;
+		let (token_amount, _) = Self::div_round(
+			liquidity.checked_mul(&token_reserve).ok_or(Error::<T>::Overflow)?, total_liquidity,
+		)?;
+
+		Self::increase_currency_reserve(exchange_id, token_id, currency_amount)?;
+		let total_supply = total_liquidity.checked_add(&liquidity).ok_or(Error::<T>::Overflow)?;
+		TotalSupplies::<T>::insert(exchange_id, token_id, total_supply);
+
+		let liquidity_token = Self::liquidity_tokens(exchange_id, token_id).ok_or(Error::<T>::InsufficientLiquidity)?;
+		token::Module::<T>::mint(to, &liquidity_token, liquidity)?;
+
+		currency::Module::<T>::do_transfer_from(sender, &exchange.vault, &exchange.currency, currency_amount)?;
+		token::Module::<T>::transfer_from(sender, &exchange.vault, &token_id, token_amount)?;
+
+		let pools = Self::pool_states(exchange_id, &exchange.vault, &vec![token_id]);
+		Self::deposit_event(RawEvent::LiquidityAdded(
+			exchange_id, sender.clone(), to.clone(), vec![token_id], vec![token_amount], vec![currency_amount], vec![liquidity], pools,
+		));
+
+		Ok(())
+	}
+
+	/// Fills resting orders priced better than the pool's marginal price, as the counterparty
+	/// of a taker on `side` trading at most `max_tokens` for at most `max_currency`.
+	///
+	/// Returns the tokens and currency traded against the book.
+	fn cross_orders(
+		exchange_id: ExchangeId,
+		exchange: &ExchangeInfo<T::AccountId>,
+		token_id: T::TokenId,
+		side: OrderSide,
+		taker: &T::AccountId,
+		to: &T::AccountId,
+		max_tokens: T::TokenBalance,
+		max_currency: T::TokenBalance,
+	) -> Result<(T::TokenBalance, T::TokenBalance), DispatchError> {
+		let pool_price = Self::pool_marginal_price(exchange_id, exchange, token_id, side);
+		let book = match side {
+			OrderSide::Buy => Self::asks(exchange_id, token_id),
+			OrderSide::Sell => Self::bids(exchange_id, token_id),
+		};
+
+		let mut tokens = T::TokenBalance::from(0u32);
+		let mut currency = T::TokenBalance::from(0u32);
+
+		for (price, order_id) in book {
+			let better = match (side, pool_price) {
+				(_, None) => true,
+				(OrderSide::Buy, Some(pool_price)) => price < pool_price,
+				(OrderSide::Sell, Some(pool_price)) => price > pool_price,
+			};
+			if !better {
+				break;
+			}
+
+			let order = Self::orders(order_id).ok_or(Error::<T>::InvalidOrderId)?;
+			let tokens_left = max_tokens.checked_sub(&tokens).ok_or(Error::<T>::Overflow)?;
+			let currency_left = max_currency.checked_sub(&currency).ok_or(Error::<T>::Overflow)?;
+			let amount = order.amount
+				.min(tokens_left)
+				.min(Self::order_amount_for(currency_left, price));
+			if Self::order_value(amount, price, side == OrderSide::Buy)?.is_zero() {
+				break;
+			}
+
+			let cost = Self::do_fill_order(order_id, order, taker, to, amount)?;
+			tokens = tokens.checked_add(&amount).ok_or(Error::<T>::Overflow)?;
+			currency = currency.checked_add(&cost).ok_or(Error::<T>::Overflow)?;
+		}
+
+		Ok((tokens, currency))
+	}
+
+	/// Currency per token the pool charges a buying taker or pays a selling one at the margin,
+	/// swap fee included. `None` if the pool has no liquidity.
+	fn pool_marginal_price(
+		exchange_id: ExchangeId,
+		exchange: &ExchangeInfo<T::AccountId>,
+		token_id: T::TokenId,
+		side: OrderSide,
+	) -> Option<FixedU128> {
+		if Self::total_supplies(exchange_id, token_id).is_zero() {
+			return None;
+		}
+
+		let token_reserve = token::Module::<T>::balance_of(&exchange.vault, &token_id);
+		let currency_reserve = Self::currency_reserves(exchange_id, token_id);
+
+		let curve = Self::pool_curve(exchange_id, token_id);
+		let (currency_per_token, _) = Self::spot_prices(curve, currency_reserve, token_reserve)?;
+		let after_fee = FixedU128::saturating_from_rational(995u32, 1000u32);
+
+		match side {
+			OrderSide::Buy => currency_per_token.checked_div(&after_fee),
+			OrderSide::Sell => Some(currency_per_token.saturating_mul(after_fee)),
+		}
+	}
+
+	/// Currency worth `amount` tokens at `price`, rounded up or down.
+	fn order_value(amount: T::TokenBalance, price: FixedU128, round_up: bool) -> Result<T::TokenBalance, DispatchError> {
+		let (value, remainder) = (Self::to_u256(amount) * U256::from(price.into_inner()))
+			.div_mod(U256::from(FixedU128::accuracy()));
+		let value = if round_up && !remainder.is_zero() { value + 1 } else { value };
+		ensure!(value <= U256::from(u128::max_value()), Error::<T>::Overflow);
+
+		Ok(Self::from_u256(value))
+	}
+
+	/// Most tokens `currency` pays for at `price`, saturating when the price is tiny.
+	fn order_amount_for(currency: T::TokenBalance, price: FixedU128) -> T::TokenBalance {
+		Self::from_u256(Self::to_u256(currency) * U256::from(FixedU128::accuracy()) / U256::from(price.into_inner()))
+	}
+
+	/// Trades `amount` tokens of an order with `taker`, sending what the taker gets to `to`.
+	///
+	/// Returns the currency traded.
+	fn do_fill_order(
+		order_id: OrderId,
+		order: LimitOrder<T::AccountId, T::TokenId, T::TokenBalance, BalanceOf<T>>,
+		taker: &T::AccountId,
+		to: &T::AccountId,
+		amount: T::TokenBalance,
+	) -> Result<T::TokenBalance, DispatchError> {
+		let exchange = Self::get_trading_exchange(order.exchange_id)?;
+		// Rounds in the maker's favour: a selling maker is paid up, a buying maker pays down.
+		let currency = Self::order_value(amount, order.price, order.side == OrderSide::Sell)?;
+		ensure!(!currency.is_zero(), Error::<T>::InsufficientTokenAmount);
+
+		let escrow = Self::order_escrow();
+		let mut currency_escrowed = order.currency_escrowed;
+		match order.side {
+			OrderSide::Buy => {
+				currency_escrowed = currency_escrowed.checked_sub(&currency).ok_or(Error::<T>::Overflow)?;
+				token::Module::<T>::transfer_from(taker, &order.owner, &order.token_id, amount)?;
+				currency::Module::<T>::do_transfer_from(&escrow, to, &exchange.currency, currency)?;
+			}
+			OrderSide::Sell => {
+				currency::Module::<T>::do_transfer_from(taker, &order.owner, &exchange.currency, currency)?;
+				token::Module::<T>::transfer_from(&escrow, to, &order.token_id, amount)?;
+			}
+		}
+
+		let remaining = order.amount.checked_sub(&amount).ok_or(Error::<T>::InsufficientTokenAmount)?;
+		if remaining.is_zero() {
+			// Rounding down fills of a buy order can leave some of its escrow unspent.
+			if !currency_escrowed.is_zero() {
+				currency::Module::<T>::do_transfer_from(&escrow, &order.owner, &exchange.currency, currency_escrowed)?;
+			}
+			Self::remove_order(order_id, &order);
+		} else {
+			Orders::<T>::insert(order_id, LimitOrder { amount: remaining, currency_escrowed, ..order });
+		}
+
+		Self::deposit_event(RawEvent::OrderFilled(order_id, taker.clone(), amount, currency));
+
+		Ok(currency)
+	}
+
+	/// Returns what is left of an order in escrow to its owner.
+	fn refund_order(
+		order: &LimitOrder<T::AccountId, T::TokenId, T::TokenBalance, BalanceOf<T>>,
+		exchange: &ExchangeInfo<T::AccountId>,
+	) -> DispatchResult {
+		let escrow = Self::order_escrow();
+		match order.side {
+			OrderSide::Buy => {
+				currency::Module::<T>::do_transfer_from(&escrow, &order.owner, &exchange.currency, order.currency_escrowed)
+			}
+			OrderSide::Sell => {
+				token::Module::<T>::transfer_from(&escrow, &order.owner, &order.token_id, order.amount)
+			}
+		}
+	}
+
+	fn remove_order(order_id: OrderId, order: &LimitOrder<T::AccountId, T::TokenId, T::TokenBalance, BalanceOf<T>>) {
+		Orders::<T>::remove(order_id);
+		T::DepositCurrency::unreserve(&order.owner, order.deposit);
+		let _ = Self::mutate_book(order.exchange_id, order.token_id, order.side, |book| -> DispatchResult {
+			book.retain(|(_, id)| *id != order_id);
+			Ok(())
+		});
+	}
+
+	fn mutate_book<F>(exchange_id: ExchangeId, token_id: T::TokenId, side: OrderSide, f: F) -> DispatchResult
+	where
+		F: FnOnce(&mut Vec<(FixedU128, OrderId)>) -> DispatchResult,
+	{
+		match side {
+			OrderSide::Buy => Bids::<T>::try_mutate(exchange_id, token_id, f),
+			OrderSide::Sell => Asks::<T>::try_mutate(exchange_id, token_id, f),
+		}
+	}
+
+	/// Account holding the funds of resting orders, kept apart from the exchange vaults whose
+	/// balances are the pool reserves.
+	fn order_escrow() -> T::AccountId {
+		<T as Trait>::ModuleId::get().into_sub_account(b"orders")
+	}
+
+	/// The commitment `commit_swap` expects for a swap revealed with these parameters.
+	pub fn swap_commitment_hash(
+		owner: &T::AccountId,
+		exchange_id: ExchangeId,
+		currency_id: CurrencyId,
+		token_in: T::TokenId,
+		amount_in: T::TokenBalance,
+		token_out: T::TokenId,
+		min_amount_out: T::TokenBalance,
+		to: &T::AccountId,
+		salt: T::Hash,
+	) -> T::Hash {
+		T::Hashing::hash_of(&(owner, exchange_id, currency_id, token_in, amount_in, token_out, min_amount_out, to, salt))
+	}
+
+	/// Holds the input of every pending swap commitment.
+	fn commitment_escrow() -> T::AccountId {
+		<T as Trait>::ModuleId::get().into_sub_account(b"commits")
+	}
+
+	/// Refunds the commitments expiring at `now` that were never revealed.
+	fn refund_expired_commitments(now: T::BlockNumber) {
+		let escrow = Self::commitment_escrow();
+
+		for (owner, commitment) in CommitmentExpiries::<T>::take(now) {
+			if let Some(swap) = SwapCommitments::<T>::take(&owner, commitment) {
+				// The escrow holds the input, which the owner held before committing.
+				let _ = token::Module::<T>::transfer_from(&escrow, &owner, &swap.token_in, swap.amount_in);
+				Self::deposit_event(RawEvent::SwapCommitmentExpired(commitment, owner));
+			}
+		}
+	}
+
+	/// Holds the input of every queued batch swap.
+	fn batch_escrow() -> T::AccountId {
+		<T as Trait>::ModuleId::get().into_sub_account(b"batches")
+	}
+
+	/// Clears every pool with queued batch swaps, at most `MaxBatchSwapsPerBlock` swaps in all,
+	/// each already weighed by its `submit_batch_swap`.
+	///
+	/// Batches of paused or closed exchanges are refunded without trading, and a batch that
+	/// fails, the circuit breaker tripping included, is rolled back and refunded.
+	fn clear_batch_auctions() {
+		BatchSwapsQueued::kill();
+		let batches: Vec<_> = BatchSwaps::<T>::drain().collect();
+
+		for (exchange_id, token_id, swaps) in batches {
+			let status = Self::market_status(exchange_id);
+			let cleared = if status.closed || status.trading_paused {
+				Err(Error::<T>::TradingPaused.into())
+			} else {
+				with_transaction(|| match Self::clear_batch(exchange_id, token_id, swaps.clone()) {
+					Ok(()) => TransactionOutcome::Commit(Ok(())),
+					Err(e) => TransactionOutcome::Rollback(Err(e)),
+				})
+			};
+
+			if cleared.is_err() {
+				if let Some(exchange) = Self::exchanges(exchange_id) {
+					for swap in &swaps {
+						let _ = Self::refund_batch_swap(exchange_id, &exchange, token_id, swap);
+					}
+				}
+			}
+		}
+	}
+
+	/// Upper bound of the weight of clearing a pool's batch of `swaps` swaps: the pool state
+	/// and its observations, plus moving each swap's input and output.
+	fn clear_batch_weight(swaps: Weight) -> Weight {
+		T::DbWeight::get().reads_writes(10 + 4 * swaps, 8 + 4 * swaps)
+	}
+
+	/// Settles the swaps queued for a pool at one price, refunding those the price misses.
+	fn clear_batch(
+		exchange_id: ExchangeId,
+		token_id: T::TokenId,
+		mut swaps: Vec<BatchSwap<T::AccountId, T::TokenBalance>>,
+	) -> DispatchResult {
+		let exchange = Self::get_exchange(exchange_id)?;
+		ensure!(Self::total_supplies(exchange_id, token_id) > Zero::zero(), Error::<T>::InsufficientLiquidity);
+
+		let token_reserve = token::Module::<T>::balance_of(&exchange.vault, &token_id);
+		let currency_reserve = Self::currency_reserves(exchange_id, token_id);
+		Self::update_price_observations(exchange_id, token_id, currency_reserve, token_reserve);
+
+		// Dropping a swap moves the price, so repeat until every remaining swap is satisfied.
+		let (price, filled) = loop {
+			if swaps.is_empty() {
+				return Ok(());
+			}
+
+			let (price, amounts_out) = Self::batch_price(currency_reserve, token_reserve, &swaps)?;
+			let (filled, missed): (Vec<_>, Vec<_>) = swaps
+				.into_iter()
+				.zip(amounts_out)
+				.partition(|(swap, amount_out)| !amount_out.is_zero() && *amount_out >= swap.min_amount_out);
+
+			if missed.is_empty() {
+				break (price, filled);
+			}
+			for (swap, _) in missed {
+				Self::refund_batch_swap(exchange_id, &exchange, token_id, &swap)?;
+			}
+			swaps = filled.into_iter().map(|(swap, _)| swap).collect();
+		};
+
+		let escrow = Self::batch_escrow();
+		let zero = T::TokenBalance::from(0u32);
+		let (mut currency_in, mut tokens_in, mut currency_out, mut tokens_out) = (zero, zero, zero, zero);
+		for (swap, amount_out) in &filled {
+			let (amount_in, amount_out) = (swap.amount_in, *amount_out);
+			match swap.side {
+				OrderSide::Buy => {
+					currency_in = currency_in.checked_add(&amount_in).ok_or(Error::<T>::Overflow)?;
+					tokens_out = tokens_out.checked_add(&amount_out).ok_or(Error::<T>::Overflow)?;
+				}
+				OrderSide::Sell => {
+					tokens_in = tokens_in.checked_add(&amount_in).ok_or(Error::<T>::Overflow)?;
+					currency_out = currency_out.checked_add(&amount_out).ok_or(Error::<T>::Overflow)?;
+				}
+			}
+		}
+
+		let new_currency_reserve = currency_reserve
+			.checked_add(&currency_in)
+			.and_then(|r| r.checked_sub(&currency_out))
+			.ok_or(Error::<T>::InsufficientLiquidity)?;
+		let new_token_reserve = token_reserve
+			.checked_add(&tokens_in)
+			.and_then(|r| r.checked_sub(&tokens_out))
+			.ok_or(Error::<T>::InsufficientLiquidity)?;
+		ensure!(!new_currency_reserve.is_zero() && !new_token_reserve.is_zero(), Error::<T>::InsufficientLiquidity);
+		// The pool only trades the imbalance between buyers and sellers.
+		let pool_currency = if new_currency_reserve > currency_reserve {
+			new_currency_reserve - currency_reserve
+		} else {
+			currency_reserve - new_currency_reserve
+		};
+		Self::record_volume(exchange_id, token_id, pool_currency)?;
+		Self::ensure_price_move(
+			exchange_id, token_id,
+			(currency_reserve, token_reserve),
+			(new_currency_reserve, new_token_reserve),
+			None,
+		)?;
+		CurrencyReserves::<T>::insert(exchange_id, token_id, new_currency_reserve);
+
+		currency::Module::<T>::do_transfer_from(&escrow, &exchange.vault, &exchange.currency, currency_in)?;
+		token::Module::<T>::transfer_from(&escrow, &exchange.vault, &token_id, tokens_in)?;
+
+		for (swap, amount_out) in filled {
+			match swap.side {
+				OrderSide::Buy => token::Module::<T>::transfer_from(&exchange.vault, &swap.to, &token_id, amount_out)?,
+				OrderSide::Sell => currency::Module::<T>::do_transfer_from(&exchange.vault, &swap.to, &exchange.currency, amount_out)?,
+			}
+			Self::deposit_event(RawEvent::BatchSwapFilled(
+				exchange_id, token_id, swap.owner, swap.to, swap.side, swap.amount_in, amount_out,
+			));
+		}
+
+		let pool = Self::pool_state(exchange_id, &exchange.vault, token_id);
+		Self::deposit_event(RawEvent::BatchCleared(exchange_id, token_id, price, pool));
+
+		Ok(())
+	}
+
+	/// Uniform clearing price of a batch and the output of each swap at it, rounded down.
+	///
+	/// With `B` currency bought with and `S` tokens sold, the pool trades the imbalance at the
+	/// same price `p` as everyone else. Solving the 0.5% fee constant product for it gives
+	/// `p = (c + f * B) / (f * (t + S))` when buyers are in excess and
+	/// `p = f * (c + B) / (t + f * S)` when sellers are, `c` and `t` being the reserves.
+	fn batch_price(
+		currency_reserve: T::TokenBalance,
+		token_reserve: T::TokenBalance,
+		swaps: &Vec<BatchSwap<T::AccountId, T::TokenBalance>>,
+	) -> Result<(FixedU128, Vec<T::TokenBalance>), DispatchError> {
+		let mul = |a: U256, b: U256| a.checked_mul(b).ok_or(Error::<T>::Overflow);
+		let add = |a: U256, b: U256| a.checked_add(b).ok_or(Error::<T>::Overflow);
+
+		let (mut bought_with, mut sold) = (U256::zero(), U256::zero());
+		for swap in swaps {
+			match swap.side {
+				OrderSide::Buy => bought_with = add(bought_with, Self::to_u256(swap.amount_in))?,
+				OrderSide::Sell => sold = add(sold, Self::to_u256(swap.amount_in))?,
+			}
+		}
+
+		let (c, t) = (Self::to_u256(currency_reserve), Self::to_u256(token_reserve));
+		let (fee, scale) = (U256::from(995u32), U256::from(1000u32));
+
+		// The price as `numerator / denominator` currency per token.
+		let (numerator, denominator) = {
+			let buy_numerator = add(mul(c, scale)?, mul(bought_with, fee)?)?;
+			let buy_denominator = mul(fee, add(t, sold)?)?;
+			if mul(bought_with, buy_denominator)? >= mul(buy_numerator, sold)? {
+				(buy_numerator, buy_denominator)
+			} else {
+				(mul(fee, add(c, bought_with)?)?, add(mul(t, scale)?, mul(fee, sold)?)?)
+			}
+		};
+		ensure!(!numerator.is_zero() && !denominator.is_zero(), Error::<T>::InsufficientLiquidity);
+
+		let amounts_out = swaps
+			.iter()
+			.map(|swap| {
+				let amount_in = Self::to_u256(swap.amount_in);
+				let amount_out = match swap.side {
+					OrderSide::Buy => mul(amount_in, denominator)? / numerator,
+					OrderSide::Sell => mul(amount_in, numerator)? / denominator,
+				};
+				Ok(Self::from_u256(amount_out))
+			})
+			.collect::<Result<Vec<_>, DispatchError>>()?;
+
+		let price = mul(numerator, U256::from(FixedU128::accuracy()))
+			.ok()
+			.map(|n| n / denominator)
+			.filter(|p| *p <= U256::from(u128::max_value()))
+			.map(|p| FixedU128::from_inner(p.low_u128()))
+			.unwrap_or_else(FixedU128::max_value);
+
+		Ok((price, amounts_out))
+	}
+
+	fn refund_batch_swap(
+		exchange_id: ExchangeId,
+		exchange: &ExchangeInfo<T::AccountId>,
+		token_id: T::TokenId,
+		swap: &BatchSwap<T::AccountId, T::TokenBalance>,
+	) -> DispatchResult {
+		let escrow = Self::batch_escrow();
+		match swap.side {
+			OrderSide::Buy => currency::Module::<T>::do_transfer_from(&escrow, &swap.owner, &exchange.currency, swap.amount_in)?,
+			OrderSide::Sell => token::Module::<T>::transfer_from(&escrow, &swap.owner, &token_id, swap.amount_in)?,
+		}
+
+		Self::deposit_event(RawEvent::BatchSwapRefunded(exchange_id, token_id, swap.owner.clone()));
+
+		Ok(())
+	}
+
+	/// Holds the liquidity shares of every position NFT.
+	pub fn position_custody() -> T::AccountId {
+		<T as Trait>::ModuleId::get().into_sub_account(b"positions")
+	}
+
+	/// Mints a position NFT to `owner` for `shares` held in custody.
+	fn open_position(
+		owner: &T::AccountId,
+		exchange_id: ExchangeId,
+		vault: &T::AccountId,
+		token_id: T::TokenId,
+		shares: T::TokenBalance,
+	) -> DispatchResult {
+		let collection_id = match Self::position_collection() {
+			Some(collection_id) => collection_id,
+			None => {
+				let collection_id = nft::Module::<T>::do_create_collection(&Self::position_custody(), &b"dex/lp".to_vec(), Vec::new())?;
+				PositionCollection::put(collection_id);
+				collection_id
+			}
+		};
+
+		let token_reserve = token::Module::<T>::balance_of(vault, &token_id);
+		let entry_prices = Self::spot_prices(
+			Self::pool_curve(exchange_id, token_id), Self::currency_reserves(exchange_id, token_id), token_reserve,
+		).unwrap_or_default();
+		let position = LpPosition { exchange_id, token_id, shares, entry_prices };
+
+		let data = NftAssetData {
+			name: b"DEX LP position".to_vec(),
+			description: Vec::new(),
+			properties: position.encode(),
+		};
+		let asset_id = nft::Module::<T>::do_mint(owner, collection_id, data)?;
+		Positions::<T>::insert(asset_id, position);
+
+		Self::deposit_event(RawEvent::PositionOpened(asset_id, owner.clone(), exchange_id, token_id, shares));
+
+		Ok(())
+	}
+
+	/// Burns a position NFT of `owner`, releasing its shares to them.
+	fn close_position(owner: &T::AccountId, exchange_id: ExchangeId, asset_id: AssetId) -> DispatchResult {
+		let collection_id = Self::position_collection().ok_or(Error::<T>::InvalidPosition)?;
+		let position = Positions::<T>::take(asset_id).ok_or(Error::<T>::InvalidPosition)?;
+		ensure!(position.exchange_id == exchange_id, Error::<T>::InvalidPosition);
+
+		nft::Module::<T>::do_burn(owner, collection_id, asset_id)?;
+
+		let liquidity_token = Self::liquidity_tokens(exchange_id, position.token_id).ok_or(Error::<T>::InvalidPosition)?;
+		token::Module::<T>::transfer_from(&Self::position_custody(), owner, &liquidity_token, position.shares)?;
+
+		Self::deposit_event(RawEvent::PositionClosed(asset_id, owner.clone()));
+
+		Ok(())
+	}
+
+	/// Holds the locked `MinimumLiquidity` shares of every pool. No one can sign for it.
+	pub fn locked_liquidity_account() -> T::AccountId {
+		<T as Trait>::ModuleId::get().into_sub_account(b"locked")
+	}
+
+	/// Currency a new pool of `exchange_id` must be funded with, falling back to the minimum
+	/// of its parent exchange for a quote market.
+	pub fn min_pool_size(exchange_id: ExchangeId) -> T::TokenBalance {
+		Self::min_pool_sizes(exchange_id)
+			.or_else(|| Self::market_parents(exchange_id).and_then(|parent_id| Self::min_pool_sizes(parent_id)))
+			.unwrap_or_else(T::DefaultMinPoolSize::get)
+	}
+
+	fn build_route(markets: &Vec<(ExchangeId, CurrencyId)>, path: &Vec<T::TokenId>) -> Result<Vec<RouteLeg<T>>, DispatchError> {
+		ensure!(path.len() >= 2 && markets.len() + 1 == path.len(), Error::<T>::InvalidPath);
+
+		let mut pools = BTreeSet::new();
+		let mut route = Vec::with_capacity(markets.len());
+
+		for i in 0..markets.len() {
+			let exchange_id = Self::market_id(markets[i].0, markets[i].1)?;
+			let exchange = Self::get_trading_exchange(exchange_id)?;
+			let currency_token = currency::Module::<T>::get_currency_token(&exchange.currency)?;
+
+			let (token_in, token_out) = (path[i], path[i + 1]);
+			ensure!(token_in != token_out, Error::<T>::InvalidPath);
+
+			for token in &[token_in, token_out] {
+				if *token != currency_token {
+					ensure!(pools.insert((exchange_id, *token)), Error::<T>::InvalidPath);
+				}
+			}
+
+			route.push(RouteLeg { exchange_id, exchange, currency_token, token_in, token_out });
+		}
+
+		Ok(route)
+	}
+
+	/// Returns the intermediate currency amount and the output of selling `amount_in` on `leg`.
+	fn quote_leg_out(leg: &RouteLeg<T>, amount_in: T::TokenBalance) -> Result<(T::TokenBalance, T::TokenBalance), DispatchError> {
+		let currency_amount = if leg.token_in == leg.currency_token {
+			amount_in
+		} else {
+			let token_reserve = token::Module::<T>::balance_of(&leg.exchange.vault, &leg.token_in);
+			let curve = Self::pool_curve(leg.exchange_id, leg.token_in);
+			Self::get_amount_out(curve, amount_in, token_reserve, Self::currency_reserves(leg.exchange_id, leg.token_in))?
+		};
+
+		let amount_out = if leg.token_out == leg.currency_token {
+			currency_amount
+		} else {
+			let token_reserve = token::Module::<T>::balance_of(&leg.exchange.vault, &leg.token_out);
+			let curve = Self::pool_curve(leg.exchange_id, leg.token_out);
+			Self::get_amount_out(curve, currency_amount, Self::currency_reserves(leg.exchange_id, leg.token_out), token_reserve)?
+		};
+		ensure!(amount_out > Zero::zero(), Error::<T>::InsufficientOutputAmount);
+
+		Ok((currency_amount, amount_out))
+	}
+
+	/// Returns the input and the intermediate currency amount needed to buy `amount_out` on `leg`.
+	fn quote_leg_in(leg: &RouteLeg<T>, amount_out: T::TokenBalance) -> Result<(T::TokenBalance, T::TokenBalance), DispatchError> {
+		let currency_amount = if leg.token_out == leg.currency_token {
+			amount_out
+		} else {
+			let token_reserve = token::Module::<T>::balance_of(&leg.exchange.vault, &leg.token_out);
+			let curve = Self::pool_curve(leg.exchange_id, leg.token_out);
+			Self::get_amount_in(curve, amount_out, Self::currency_reserves(leg.exchange_id, leg.token_out), token_reserve)?
+		};
+
+		let amount_in = if leg.token_in == leg.currency_token {
+			currency_amount
+		} else {
+			let token_reserve = token::Module::<T>::balance_of(&leg.exchange.vault, &leg.token_in);
+			let curve = Self::pool_curve(leg.exchange_id, leg.token_in);
+			Self::get_amount_in(curve, currency_amount, token_reserve, Self::currency_reserves(leg.exchange_id, leg.token_in))?
+		};
+
+		Ok((amount_in, currency_amount))
+	}
+
+	fn apply_leg(
+		leg: &RouteLeg<T>,
+		from: &T::AccountId,
+		to: &T::AccountId,
+		amount_in: T::TokenBalance,
+		currency_amount: T::TokenBalance,
+		amount_out: T::TokenBalance,
+		max_price_impact: Option<u32>,
+		referred: bool,
+	) -> DispatchResult {
+		// The referral on the first leg leaves in the asset paid in, so it only trims that side.
+		let referral = Self::referral_amount(referred, amount_in)?;
+
+		for token in &[leg.token_in, leg.token_out] {
+			if *token != leg.currency_token {
+				let token_reserve = token::Module::<T>::balance_of(&leg.exchange.vault, token);
+				Self::update_price_observations(leg.exchange_id, *token, Self::currency_reserves(leg.exchange_id, token), token_reserve);
+			}
+		}
+
+		if leg.token_in != leg.currency_token {
+			let token_reserve = token::Module::<T>::balance_of(&leg.exchange.vault, &leg.token_in);
+			let currency_reserve = Self::currency_reserves(leg.exchange_id, leg.token_in);
+			Self::decrease_currency_reserve(leg.exchange_id, leg.token_in, currency_amount)?;
+			Self::record_volume(leg.exchange_id, leg.token_in, currency_amount)?;
+
+			Self::ensure_price_move(
+				leg.exchange_id, leg.token_in,
+				(currency_reserve, token_reserve),
+				(currency_reserve.saturating_sub(currency_amount), token_reserve.saturating_add(amount_in).saturating_sub(referral)),
+				max_price_impact,
+			)?;
+		}
+		if leg.token_out != leg.currency_token {
+			let token_reserve = token::Module::<T>::balance_of(&leg.exchange.vault, &leg.token_out);
+			let currency_reserve = Self::currency_reserves(leg.exchange_id, leg.token_out);
+			Self::increase_currency_reserve(leg.exchange_id, leg.token_out, currency_amount)?;
+			Self::record_volume(leg.exchange_id, leg.token_out, currency_amount)?;
+
+			Self::ensure_price_move(
+				leg.exchange_id, leg.token_out,
+				(currency_reserve, token_reserve),
+				(currency_reserve.saturating_add(currency_amount).saturating_sub(referral), token_reserve.saturating_sub(amount_out)),
+				max_price_impact,
+			)?;
+		}
+
+		token::Module::<T>::transfer_from(from, &leg.exchange.vault, &leg.token_in, amount_in)?;
+		token::Module::<T>::transfer_from(&leg.exchange.vault, to, &leg.token_out, amount_out)?;
+
+		Ok(())
+	}
+
+	/// Amount of input needed to buy `amount_out`.
+	///
+	/// Rounds up, so a buyer always pays at least what the curve with fee requires.
+	fn get_amount_in(
+		curve: PoolCurve,
+		amount_out: T::TokenBalance,
+		reserve_in: T::TokenBalance,
+		reserve_out: T::TokenBalance,
+	) -> Result<T::TokenBalance, DispatchError> {
+		ensure!(amount_out > Zero::zero() , Error::<T>::InsufficientOutputAmount);
+		ensure!(reserve_in > Zero::zero()  && reserve_out > amount_out , Error::<T>::InsufficientLiquidity);
+
+		if let PoolCurve::StableSwap { amplification } = curve {
+			let (x, y) = (Self::to_u256(reserve_in), Self::to_u256(reserve_out));
+			let d = Self::stable_invariant(amplification, x, y)?;
+			let new_reserve_in = Self::stable_reserve(amplification, y - Self::to_u256(amount_out), d)?;
+			// One more than the curve asks for, so invariant rounding never favours the buyer.
+			let amount_in_with_fee = new_reserve_in.saturating_sub(x) + U256::one();
+			let (amount_in, _) = Self::div_round(
+				Self::from_u256(amount_in_with_fee).checked_mul(&1000u32.into()).ok_or(Error::<T>::Overflow)?,
+				995u32.into(),
+			)?;
+			return Ok(amount_in);
+		}
+
+		let numerator = reserve_in
+			.checked_mul(&amount_out)
+			.and_then(|n| n.checked_mul(&1000u32.into()))
+			.ok_or(Error::<T>::Overflow)?;
+		let denominator = (reserve_out - amount_out)
+			.checked_mul(&995u32.into())
+			.ok_or(Error::<T>::Overflow)?;
+		let (amount_in, _) = Self::div_round(numerator, denominator)?;
+
+		Ok(amount_in)
+	}
+
+	/// Amount received for selling `amount_in`.
+	///
+	/// Rounds down, so a seller never receives more than the curve with fee allows.
 	fn get_amount_out(
+		curve: PoolCurve,
 		amount_in: T::TokenBalance,
 		reserve_in: T::TokenBalance,
 		reserve_out: T::TokenBalance,
@@ -367,33 +2832,782 @@ impl<T: Trait> Module<T> {
 		ensure!(amount_in > Zero::zero() , Error::<T>::InsufficientInputAmount);
 		ensure!(reserve_in > Zero::zero()  && reserve_out > Zero::zero() , Error::<T>::InsufficientLiquidity);
 
-		let amount_in_with_fee = amount_in * 995u32.into();
-		let numerator = amount_in_with_fee * reserve_out;
-		let denominator = (reserve_in * 1000u32.into()) + amount_in_with_fee;
+		let amount_in_with_fee = amount_in.checked_mul(&995u32.into()).ok_or(Error::<T>::Overflow)?;
+
+		if let PoolCurve::StableSwap { amplification } = curve {
+			let (x, y) = (Self::to_u256(reserve_in), Self::to_u256(reserve_out));
+			let d = Self::stable_invariant(amplification, x, y)?;
+			let new_reserve_out = Self::stable_reserve(amplification, x + Self::to_u256(amount_in_with_fee) / U256::from(1000u32), d)?;
+			// One less than the curve gives, so invariant rounding never favours the seller.
+			return Ok(Self::from_u256(y.saturating_sub(new_reserve_out).saturating_sub(U256::one())));
+		}
+
+		let numerator = amount_in_with_fee.checked_mul(&reserve_out).ok_or(Error::<T>::Overflow)?;
+		let denominator = reserve_in
+			.checked_mul(&1000u32.into())
+			.and_then(|d| d.checked_add(&amount_in_with_fee))
+			.ok_or(Error::<T>::Overflow)?;
 		let amount_out = numerator / denominator;
 
 		Ok(amount_out)
 	}
 
-	fn get_token_reserves(vault: &T::AccountId, token_ids: &Vec<T::TokenId>) -> Vec<T::TokenBalance> {
-		let n = token_ids.len();
+	/// StableSwap invariant `D` of reserves `x` and `y`, solving
+	/// `4A(x + y) + D = 4AD + D^3 / 4xy` by Newton's method.
+	fn stable_invariant(amplification: u32, x: U256, y: U256) -> Result<U256, DispatchError> {
+		if x.is_zero() || y.is_zero() {
+			return Ok(U256::zero());
+		}
+
+		let overflow = || DispatchError::from(Error::<T>::Overflow);
+		let ann = U256::from(amplification) * U256::from(4u32);
+		let sum = x + y;
+
+		let mut d = sum;
+		for _ in 0..255 {
+			let d_p = d.checked_mul(d).ok_or_else(overflow)? / (x * U256::from(2u32));
+			let d_p = d_p.checked_mul(d).ok_or_else(overflow)? / (y * U256::from(2u32));
+			let previous = d;
+
+			let numerator = d_p
+				.checked_mul(U256::from(2u32))
+				.and_then(|n| n.checked_add(ann * sum))
+				.and_then(|n| n.checked_mul(d))
+				.ok_or_else(overflow)?;
+			let denominator = d_p
+				.checked_mul(U256::from(3u32))
+				.and_then(|n| n.checked_add((ann - U256::one()).checked_mul(d)?))
+				.ok_or_else(overflow)?;
+			d = numerator / denominator;
+
+			let difference = if d > previous { d - previous } else { previous - d };
+			if difference <= U256::one() {
+				break;
+			}
+		}
+
+		Ok(d)
+	}
+
+	/// The reserve on one side of a StableSwap pool with invariant `d` when the other side
+	/// holds `reserve`.
+	fn stable_reserve(amplification: u32, reserve: U256, d: U256) -> Result<U256, DispatchError> {
+		ensure!(!reserve.is_zero(), Error::<T>::InsufficientLiquidity);
+
+		let overflow = || DispatchError::from(Error::<T>::Overflow);
+		let ann = U256::from(amplification) * U256::from(4u32);
+
+		let c = d.checked_mul(d).ok_or_else(overflow)? / (reserve * U256::from(2u32));
+		let c = c.checked_mul(d).ok_or_else(overflow)? / (ann * U256::from(2u32));
+		let b = reserve.checked_add(d / ann).ok_or_else(overflow)?;
+
+		let mut y = d;
+		for _ in 0..255 {
+			let previous = y;
+
+			let numerator = y.checked_mul(y).and_then(|n| n.checked_add(c)).ok_or_else(overflow)?;
+			let denominator = (y * U256::from(2u32))
+				.checked_add(b)
+				.and_then(|n| n.checked_sub(d))
+				.filter(|n| !n.is_zero())
+				.ok_or(Error::<T>::InsufficientLiquidity)?;
+			y = numerator / denominator;
+
+			let difference = if y > previous { y - previous } else { previous - y };
+			if difference <= U256::one() {
+				break;
+			}
+		}
+
+		Ok(y)
+	}
+
+	/// Part of `amount_in` kept by a pool as the swap fee, rounded down.
+	fn swap_fee(amount_in: T::TokenBalance) -> Result<T::TokenBalance, DispatchError> {
+		Self::mul_div(amount_in, 5u32.into(), 1000u32.into())
+	}
+
+	/// Part of a swap `fee` paid to the referrer.
+	fn referral_share(fee: T::TokenBalance) -> Result<T::TokenBalance, DispatchError> {
+		let share = T::ReferralFeeShare::get().min(10_000);
+		Self::mul_div(fee, share.into(), 10_000u32.into())
+	}
+
+	/// Referral paid on a swap of `amount_in`, if it was `referred`, so the price checks can
+	/// see the reserves it will leave behind.
+	fn referral_amount(referred: bool, amount_in: T::TokenBalance) -> Result<T::TokenBalance, DispatchError> {
+		if !referred {
+			return Ok(Zero::zero());
+		}
+		Self::referral_share(Self::swap_fee(amount_in)?)
+	}
+
+	/// Pays `referrer` its share of the `fee` the `token_id` pool charged a taker on `side`,
+	/// out of the vault in the asset the taker paid: currency for buys, tokens for sells.
+	fn pay_referral(
+		referrer: &T::AccountId,
+		exchange_id: ExchangeId,
+		exchange: &ExchangeInfo<T::AccountId>,
+		token_id: T::TokenId,
+		side: OrderSide,
+		fee: T::TokenBalance,
+	) -> DispatchResult {
+		let amount = Self::referral_share(fee)?;
+		if amount.is_zero() {
+			return Ok(());
+		}
+
+		let paid_in = match side {
+			OrderSide::Buy => {
+				Self::decrease_currency_reserve(exchange_id, token_id, amount)?;
+				currency::Module::<T>::get_currency_token(&exchange.currency)?
+			}
+			OrderSide::Sell => token_id,
+		};
+		token::Module::<T>::transfer_from(&exchange.vault, referrer, &paid_in, amount)?;
+		ReferralEarnings::<T>::mutate(referrer, paid_in, |earned| *earned = earned.saturating_add(amount));
+
+		Self::deposit_event(RawEvent::ReferralPaid(referrer.clone(), paid_in, amount));
 
-		if n == 1 {
-			let mut token_reserves = vec![T::TokenBalance::from(0u32); n];
-			token_reserves[0] = token::Module::<T>::balance_of(vault, &token_ids[0]);
-			token_reserves
+		Ok(())
+	}
+
+	/// Pays `referrer` its share of the fee on `amount_in` charged by the first pool of a route.
+	fn pay_route_referral(referrer: &T::AccountId, leg: &RouteLeg<T>, amount_in: T::TokenBalance) -> DispatchResult {
+		let (token_id, side) = if leg.token_in == leg.currency_token {
+			(leg.token_out, OrderSide::Buy)
 		} else {
-			let vaults = vec![vault.clone(); n];
-			let token_reserves = token::Module::<T>::balance_of_batch(&vaults, &token_ids).unwrap();
-			token_reserves
+			(leg.token_in, OrderSide::Sell)
+		};
+
+		Self::pay_referral(referrer, leg.exchange_id, &leg.exchange, token_id, side, Self::swap_fee(amount_in)?)
+	}
+
+	/// Volume and fees of the `(exchange_id, token_id)` pool over the last `window` blocks, in
+	/// the exchange currency. `None` if the window reaches past the observations kept.
+	pub fn pool_volume(exchange_id: ExchangeId, token_id: T::TokenId, window: T::BlockNumber) -> Option<(T::TokenBalance, T::TokenBalance)> {
+		let observations = Self::volume_observations(exchange_id, token_id);
+		let start = system::Module::<T>::block_number().saturating_sub(window);
+
+		let last = observations.last().cloned().unwrap_or_default();
+		let first = match observations.iter().rev().find(|o| o.block_number <= start) {
+			Some(first) => first.clone(),
+			// Nothing was dropped yet, so the counters started from zero.
+			None if observations.len() < T::MaxPriceObservations::get() as usize => Default::default(),
+			None => return None,
+		};
+
+		Some((last.volume.saturating_sub(first.volume), last.fees.saturating_sub(first.fees)))
+	}
+
+	/// Yearly return of the fees charged by a pool over the last `window` blocks, relative to
+	/// the value of its reserves, in basis points.
+	///
+	/// Blocks are assumed to follow each other every `2 * MinimumPeriod`.
+	pub fn fee_apr(exchange_id: ExchangeId, token_id: T::TokenId, window: T::BlockNumber) -> Option<u32> {
+		let exchange = Self::exchanges(exchange_id)?;
+		let (_, fees) = Self::pool_volume(exchange_id, token_id, window)?;
+
+		let currency_reserve = Self::currency_reserves(exchange_id, token_id);
+		let token_reserve = token::Module::<T>::balance_of(&exchange.vault, &token_id);
+		let (currency_per_token, _) = Self::spot_prices(Self::pool_curve(exchange_id, token_id), currency_reserve, token_reserve)?;
+		let tokens: u128 = token_reserve.unique_saturated_into();
+		let value = Self::to_u256(currency_reserve).saturating_add(U256::from(currency_per_token.saturating_mul_int(tokens)));
+
+		let block_time: u128 = <T as pallet_timestamp::Trait>::MinimumPeriod::get()
+			.saturating_mul(2u32.into())
+			.unique_saturated_into();
+		let window: u128 = window.unique_saturated_into();
+		let year = U256::from(365u64 * 24 * 60 * 60 * 1000);
+		let denominator = value.checked_mul(U256::from(window))?.checked_mul(U256::from(block_time))?;
+		if denominator.is_zero() {
+			return None;
+		}
+
+		let apr = Self::to_u256(fees).checked_mul(U256::from(10_000u32))?.checked_mul(year)? / denominator;
+		Some(if apr > U256::from(u32::max_value()) { u32::max_value() } else { apr.low_u32() })
+	}
+
+	/// Value of a liquidity share now against holding what it was worth when the pool was at
+	/// `entry`, `(currency_reserve, token_reserve, total_liquidity)`, both valued at the current
+	/// spot price. In basis points, negative when the share is worth less.
+	///
+	/// Fees earned since entry stay in the reserves, so they offset the loss.
+	pub fn impermanent_loss(
+		exchange_id: ExchangeId,
+		token_id: T::TokenId,
+		entry: (T::TokenBalance, T::TokenBalance, T::TokenBalance),
+	) -> Option<i32> {
+		let exchange = Self::exchanges(exchange_id)?;
+		let currency_reserve = Self::currency_reserves(exchange_id, token_id);
+		let token_reserve = token::Module::<T>::balance_of(&exchange.vault, &token_id);
+		let total_liquidity = Self::total_supplies(exchange_id, token_id);
+		let (entry_currency, entry_tokens, entry_liquidity) = entry;
+		if total_liquidity.is_zero() || entry_liquidity.is_zero() {
+			return None;
+		}
+
+		let (price, _) = Self::spot_prices(Self::pool_curve(exchange_id, token_id), currency_reserve, token_reserve)?;
+		let share_value = |currency: T::TokenBalance, tokens: T::TokenBalance, liquidity: T::TokenBalance| {
+			let (currency, tokens, liquidity): (u128, u128, u128) =
+				(currency.unique_saturated_into(), tokens.unique_saturated_into(), liquidity.unique_saturated_into());
+			FixedU128::saturating_from_rational(currency, liquidity)
+				.saturating_add(FixedU128::saturating_from_rational(tokens, liquidity).saturating_mul(price))
+		};
+
+		let held = share_value(entry_currency, entry_tokens, entry_liquidity);
+		let pooled = share_value(currency_reserve, token_reserve, total_liquidity);
+		if held.is_zero() {
+			return None;
+		}
+
+		let (held, pooled) = (U256::from(held.into_inner()), U256::from(pooled.into_inner()));
+		let change = |a: U256, b: U256| {
+			let bps = (a - b) * U256::from(10_000u32) / held;
+			if bps > U256::from(i32::max_value() as u32) { i32::max_value() } else { bps.low_u32() as i32 }
+		};
+		Some(if pooled >= held { change(pooled, held) } else { -change(held, pooled) })
+	}
+
+	/// Referral fees paid to `referrer` so far, by the token they were paid in.
+	pub fn referral_earnings_of(referrer: &T::AccountId) -> Vec<(T::TokenId, T::TokenBalance)> {
+		ReferralEarnings::<T>::iter_prefix(referrer).collect()
+	}
+
+	fn pool_state(exchange_id: ExchangeId, vault: &T::AccountId, token_id: T::TokenId) -> PoolState<T::TokenBalance> {
+		PoolState {
+			token_reserve: token::Module::<T>::balance_of(vault, &token_id),
+			currency_reserve: Self::currency_reserves(exchange_id, token_id),
+			total_liquidity: Self::total_supplies(exchange_id, token_id),
+		}
+	}
+
+	fn pool_states(exchange_id: ExchangeId, vault: &T::AccountId, token_ids: &Vec<T::TokenId>) -> Vec<PoolState<T::TokenBalance>> {
+		token_ids.iter().map(|id| Self::pool_state(exchange_id, vault, *id)).collect()
+	}
+
+	/// States of the pools a route traded through, in route order.
+	fn route_pool_states(route: &Vec<RouteLeg<T>>) -> Vec<(ExchangeId, T::TokenId, PoolState<T::TokenBalance>)> {
+		let mut pools = Vec::new();
+		for leg in route {
+			for token in &[leg.token_in, leg.token_out] {
+				if *token != leg.currency_token {
+					pools.push((leg.exchange_id, *token, Self::pool_state(leg.exchange_id, &leg.exchange.vault, *token)));
+				}
+			}
 		}
+		pools
+	}
+
+	fn get_token_reserves(vault: &T::AccountId, token_ids: &Vec<T::TokenId>) -> Result<Vec<T::TokenBalance>, DispatchError> {
+		let vaults = vec![vault.clone(); token_ids.len()];
+		token::Module::<T>::balance_of_batch(&vaults, token_ids)
 	}
 
-	fn div_round(a: T::TokenBalance, b: T::TokenBalance) -> (T::TokenBalance, bool) {
+	/// Divides `a` by `b` rounding up, returning whether rounding happened.
+	///
+	/// Used for amounts the pool charges, so the remainder is always paid to the pool.
+	fn div_round(a: T::TokenBalance, b: T::TokenBalance) -> Result<(T::TokenBalance, bool), DispatchError> {
+		ensure!(b > Zero::zero(), Error::<T>::InsufficientLiquidity);
+
 		if a % b > Zero::zero() {
-			(a / b, false)
+			Ok(((a / b) + 1u32.into(), true))
 		} else {
-			((a / b) + 1u32.into(), true)
+			Ok((a / b, false))
+		}
+	}
+
+	/// Computes `a * b / c` rounding down.
+	///
+	/// Used for amounts the pool pays out or mints, so the remainder always stays in the pool.
+	fn mul_div(a: T::TokenBalance, b: T::TokenBalance, c: T::TokenBalance) -> Result<T::TokenBalance, DispatchError> {
+		ensure!(c > Zero::zero(), Error::<T>::InsufficientLiquidity);
+
+		let product = a.checked_mul(&b).ok_or(Error::<T>::Overflow)?;
+		Ok(product / c)
+	}
+
+	fn increase_currency_reserve(exchange_id: ExchangeId, token_id: T::TokenId, amount: T::TokenBalance) -> DispatchResult {
+		CurrencyReserves::<T>::try_mutate(exchange_id, token_id, |reserve| -> DispatchResult {
+			*reserve = reserve.checked_add(&amount).ok_or(Error::<T>::Overflow)?;
+			Ok(())
+		})
+	}
+
+	fn decrease_currency_reserve(exchange_id: ExchangeId, token_id: T::TokenId, amount: T::TokenBalance) -> DispatchResult {
+		CurrencyReserves::<T>::try_mutate(exchange_id, token_id, |reserve| -> DispatchResult {
+			*reserve = reserve.checked_sub(&amount).ok_or(Error::<T>::InsufficientLiquidity)?;
+			Ok(())
+		})
+	}
+
+	/// Returns the token tracking liquidity shares of a pool, creating it when the pool is first funded.
+	fn get_or_create_liquidity_token(exchange_id: ExchangeId, vault: &T::AccountId, token_id: T::TokenId) -> Result<T::TokenId, DispatchError> {
+		if let Some(liquidity_token) = Self::liquidity_tokens(exchange_id, token_id) {
+			return Ok(liquidity_token);
+		}
+
+		let liquidity_token = token::Module::<T>::create_token(vault, false, &Vec::new())?;
+		LiquidityTokens::<T>::insert(exchange_id, token_id, liquidity_token);
+
+		Self::deposit_event(RawEvent::PoolCreated(exchange_id, token_id, liquidity_token));
+
+		Ok(liquidity_token)
+	}
+
+	/// Accumulates the pool price since the last observation, before the first reserve
+	/// change of a block. `currency_reserve` and `token_reserve` are the reserves held
+	/// since that observation.
+	fn update_price_observations(
+		exchange_id: ExchangeId,
+		token_id: T::TokenId,
+		currency_reserve: T::TokenBalance,
+		token_reserve: T::TokenBalance,
+	) {
+		let now = system::Module::<T>::block_number();
+
+		PriceObservations::<T>::mutate(exchange_id, token_id, |observations| {
+			let last = match observations.last() {
+				Some(last) if last.block_number == now => return,
+				Some(last) => last.clone(),
+				None => PriceObservation { block_number: now, ..Default::default() },
+			};
+
+			let elapsed: u128 = now.saturating_sub(last.block_number).unique_saturated_into();
+			let (currency_per_token, token_per_currency) = Self::spot_prices(Self::pool_curve(exchange_id, token_id), currency_reserve, token_reserve)
+				.unwrap_or((FixedU128::zero(), FixedU128::zero()));
+
+			observations.push(PriceObservation {
+				currency_per_token: last.currency_per_token
+					.wrapping_add(currency_per_token.into_inner().wrapping_mul(elapsed)),
+				token_per_currency: last.token_per_currency
+					.wrapping_add(token_per_currency.into_inner().wrapping_mul(elapsed)),
+				block_number: now,
+			});
+
+			let max = T::MaxPriceObservations::get() as usize;
+			if observations.len() > max {
+				let excess = observations.len() - max;
+				observations.drain(..excess);
+			}
+		});
+	}
+
+	/// Adds a pool trade with `currency_amount` on its currency side to the counters of the block.
+	fn record_volume(exchange_id: ExchangeId, token_id: T::TokenId, currency_amount: T::TokenBalance) -> DispatchResult {
+		if currency_amount.is_zero() {
+			return Ok(());
+		}
+
+		let fee = Self::swap_fee(currency_amount)?;
+		let now = system::Module::<T>::block_number();
+
+		VolumeObservations::<T>::mutate(exchange_id, token_id, |observations| {
+			if let Some(last) = observations.last_mut() {
+				if last.block_number == now {
+					last.volume = last.volume.saturating_add(currency_amount);
+					last.fees = last.fees.saturating_add(fee);
+					return;
+				}
+			}
+
+			let last = observations.last().cloned().unwrap_or_default();
+			observations.push(VolumeObservation {
+				block_number: now,
+				volume: last.volume.saturating_add(currency_amount),
+				fees: last.fees.saturating_add(fee),
+			});
+
+			let max = T::MaxPriceObservations::get() as usize;
+			if observations.len() > max {
+				let excess = observations.len() - max;
+				observations.drain(..excess);
+			}
+		});
+
+		Ok(())
+	}
+
+	/// Spot prices of a pool as `(currency_per_token, token_per_currency)`, if both reserves are non-zero.
+	///
+	/// On a StableSwap curve the marginal price is `(4A + D^3 / 4ct^2) / (4A + D^3 / 4c^2t)`
+	/// currency per token, `c` and `t` being the reserves.
+	fn spot_prices(curve: PoolCurve, currency_reserve: T::TokenBalance, token_reserve: T::TokenBalance) -> Option<(FixedU128, FixedU128)> {
+		let currency_reserve: u128 = currency_reserve.unique_saturated_into();
+		let token_reserve: u128 = token_reserve.unique_saturated_into();
+
+		if currency_reserve == 0 || token_reserve == 0 {
+			return None;
+		}
+
+		match curve {
+			PoolCurve::ConstantProduct => Some((
+				FixedU128::saturating_from_rational(currency_reserve, token_reserve),
+				FixedU128::saturating_from_rational(token_reserve, currency_reserve),
+			)),
+			PoolCurve::StableSwap { amplification } => {
+				let d = Self::stable_invariant(amplification, U256::from(currency_reserve), U256::from(token_reserve)).ok()?;
+				let d = if d > U256::from(u128::max_value()) { u128::max_value() } else { d.low_u128() };
+
+				let ann = FixedU128::saturating_from_integer(4 * amplification as u128);
+				let (per_currency, per_token) = (
+					FixedU128::saturating_from_rational(d, currency_reserve),
+					FixedU128::saturating_from_rational(d, token_reserve),
+				);
+				let four = FixedU128::saturating_from_integer(4u128);
+				let currency_side = ann.saturating_add(
+					per_currency.saturating_mul(per_currency).saturating_mul(per_token).checked_div(&four)?,
+				);
+				let token_side = ann.saturating_add(
+					per_currency.saturating_mul(per_token).saturating_mul(per_token).checked_div(&four)?,
+				);
+
+				Some((token_side.checked_div(&currency_side)?, currency_side.checked_div(&token_side)?))
+			}
+		}
+	}
+
+	/// Checks a pool's price move from `before` to `after`, both `(currency_reserve, token_reserve)`,
+	/// against `max_price_impact` and the exchange's circuit breaker.
+	fn ensure_price_move(
+		exchange_id: ExchangeId,
+		token_id: T::TokenId,
+		before: (T::TokenBalance, T::TokenBalance),
+		after: (T::TokenBalance, T::TokenBalance),
+		max_price_impact: Option<u32>,
+	) -> DispatchResult {
+		let curve = Self::pool_curve(exchange_id, token_id);
+
+		if let Some(max) = max_price_impact {
+			if let Some(moved) = Self::price_move(curve, before, after) {
+				ensure!(moved <= U256::from(max), Error::<T>::PriceImpactTooHigh);
+			}
+		}
+
+		if let Some(breaker) = Self::market_circuit_breaker(exchange_id) {
+			let now = system::Module::<T>::block_number();
+			let reference = match Self::price_references(exchange_id, token_id) {
+				Some(reference) if now < reference.start.saturating_add(breaker.window) => reference,
+				_ => {
+					let reference = PriceReference { start: now, currency_reserve: before.0, token_reserve: before.1 };
+					PriceReferences::<T>::insert(exchange_id, token_id, reference.clone());
+					reference
+				}
+			};
+
+			if let Some(moved) = Self::price_move(curve, (reference.currency_reserve, reference.token_reserve), after) {
+				ensure!(moved <= U256::from(breaker.max_move), Error::<T>::CircuitBreakerTripped);
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Relative change of the spot price between two `(currency_reserve, token_reserve)` pairs,
+	/// in basis points. `None` if either pair has an empty side.
+	fn price_move(
+		curve: PoolCurve,
+		before: (T::TokenBalance, T::TokenBalance),
+		after: (T::TokenBalance, T::TokenBalance),
+	) -> Option<U256> {
+		let (c0, t0, c1, t1) = (Self::to_u256(before.0), Self::to_u256(before.1), Self::to_u256(after.0), Self::to_u256(after.1));
+
+		if c0.is_zero() || t0.is_zero() || c1.is_zero() || t1.is_zero() {
+			return None;
+		}
+
+		// price_after / price_before = a / b
+		let (a, b) = match curve {
+			// (c1 * t0) / (c0 * t1)
+			PoolCurve::ConstantProduct => (c1 * t0, c0 * t1),
+			PoolCurve::StableSwap { .. } => {
+				let (after, _) = Self::spot_prices(curve, after.0, after.1)?;
+				let (before, _) = Self::spot_prices(curve, before.0, before.1)?;
+				(U256::from(after.into_inner()), U256::from(before.into_inner()))
+			}
+		};
+		if b.is_zero() {
+			return None;
+		}
+		let difference = if a > b { a - b } else { b - a };
+
+		Some(difference * U256::from(10_000u32) / b)
+	}
+
+	fn to_u256(amount: T::TokenBalance) -> U256 {
+		U256::from(UniqueSaturatedInto::<u128>::unique_saturated_into(amount))
+	}
+
+	/// Saturates amounts past `u128`, callers only convert amounts bounded by a balance.
+	fn from_u256(amount: U256) -> T::TokenBalance {
+		let amount = if amount > U256::from(u128::max_value()) { u128::max_value() } else { amount.low_u128() };
+		T::TokenBalance::unique_saturated_from(amount)
+	}
+
+	/// Invariant of a pool's reserves after deducting the swap fee on the amounts paid in,
+	/// computed on reserves scaled by `1000`.
+	fn invariant_with_fee(
+		curve: PoolCurve,
+		token_reserve: T::TokenBalance,
+		token_amount_in: T::TokenBalance,
+		currency_reserve: T::TokenBalance,
+		currency_amount_in: T::TokenBalance,
+	) -> Result<U256, DispatchError> {
+		let adjusted = |reserve: T::TokenBalance, amount_in: T::TokenBalance| {
+			let reserve: u128 = reserve.unique_saturated_into();
+			let amount_in: u128 = amount_in.unique_saturated_into();
+			(U256::from(reserve) * U256::from(1000u32)).saturating_sub(U256::from(amount_in) * U256::from(5u32))
+		};
+		let (token_reserve, currency_reserve) = (adjusted(token_reserve, token_amount_in), adjusted(currency_reserve, currency_amount_in));
+
+		match curve {
+			PoolCurve::ConstantProduct => Ok(token_reserve.saturating_mul(currency_reserve)),
+			PoolCurve::StableSwap { amplification } => Self::stable_invariant(amplification, token_reserve, currency_reserve),
+		}
+	}
+
+	/// The exchange holding the pools of `exchange_id` quoted in `currency_id`: the exchange
+	/// itself for its primary currency, otherwise one of its quote markets.
+	pub fn market_id(exchange_id: ExchangeId, currency_id: CurrencyId) -> Result<ExchangeId, DispatchError> {
+		let exchange = Self::exchanges(exchange_id).ok_or(Error::<T>::InvalidExchangeId)?;
+		if exchange.currency == currency_id {
+			return Ok(exchange_id);
+		}
+		Self::quote_markets(exchange_id, currency_id).ok_or_else(|| Error::<T>::InvalidQuoteCurrency.into())
+	}
+
+	/// Status of a market, pausing and closing its parent exchange applying to it too.
+	fn market_status(exchange_id: ExchangeId) -> ExchangeStatus {
+		let status = Self::exchange_status(exchange_id);
+		match Self::market_parents(exchange_id) {
+			Some(parent_id) => {
+				let parent = Self::exchange_status(parent_id);
+				ExchangeStatus {
+					trading_paused: status.trading_paused || parent.trading_paused,
+					deposits_paused: status.deposits_paused || parent.deposits_paused,
+					closed: status.closed || parent.closed,
+				}
+			}
+			None => status,
+		}
+	}
+
+	/// Whether a market trades through batch auctions, set on it or on its parent exchange.
+	fn market_batch_auction(exchange_id: ExchangeId) -> bool {
+		Self::batch_auctions(exchange_id)
+			|| Self::market_parents(exchange_id).map_or(false, |parent_id| Self::batch_auctions(parent_id))
+	}
+
+	/// Circuit breaker of a market, its own or else its parent exchange's.
+	fn market_circuit_breaker(exchange_id: ExchangeId) -> Option<CircuitBreaker<T::BlockNumber>> {
+		Self::circuit_breakers(exchange_id)
+			.or_else(|| Self::market_parents(exchange_id).and_then(|parent_id| Self::circuit_breakers(parent_id)))
+	}
+
+	/// Closes an exchange, refunding its resting orders and queued batch swaps.
+	fn do_close_exchange(exchange_id: ExchangeId) -> DispatchResult {
+		let exchange = Self::get_exchange(exchange_id)?;
+		ExchangeStatuses::try_mutate(exchange_id, |status| -> DispatchResult {
+			ensure!(!status.closed, Error::<T>::ExchangeClosed);
+			status.closed = true;
+			Ok(())
+		})?;
+
+		for (_, book) in Bids::<T>::drain_prefix(exchange_id).chain(Asks::<T>::drain_prefix(exchange_id)) {
+			for (_, order_id) in book {
+				if let Some(order) = Orders::<T>::take(order_id) {
+					Self::refund_order(&order, &exchange)?;
+					T::DepositCurrency::unreserve(&order.owner, order.deposit);
+					Self::deposit_event(RawEvent::OrderCancelled(order_id, order.owner));
+				}
+			}
+		}
+
+		for (token_id, swaps) in BatchSwaps::<T>::drain_prefix(exchange_id) {
+			for swap in swaps {
+				Self::refund_batch_swap(exchange_id, &exchange, token_id, &swap)?;
+			}
+		}
+
+		Self::deposit_event(RawEvent::ExchangeClosed(exchange_id));
+
+		Self::remove_exchange_if_empty(exchange_id)
+	}
+
+	fn get_exchange(exchange_id: ExchangeId) -> Result<ExchangeInfo<T::AccountId>, DispatchError> {
+		let exchange = Self::exchanges(exchange_id).ok_or(Error::<T>::InvalidExchangeId)?;
+		ensure!(!Self::flash_swap_locks(exchange_id), Error::<T>::ExchangeLocked);
+		Ok(exchange)
+	}
+
+	/// Like `get_exchange`, also checking that trading is open.
+	fn get_trading_exchange(exchange_id: ExchangeId) -> Result<ExchangeInfo<T::AccountId>, DispatchError> {
+		let exchange = Self::get_exchange(exchange_id)?;
+		let status = Self::market_status(exchange_id);
+		ensure!(!status.closed, Error::<T>::ExchangeClosed);
+		ensure!(!status.trading_paused, Error::<T>::TradingPaused);
+		ensure!(!Self::market_batch_auction(exchange_id), Error::<T>::BatchAuctionOnly);
+		Ok(exchange)
+	}
+
+	/// Like `get_exchange`, also checking that deposits are open.
+	fn get_deposit_exchange(exchange_id: ExchangeId) -> Result<ExchangeInfo<T::AccountId>, DispatchError> {
+		let exchange = Self::get_exchange(exchange_id)?;
+		let status = Self::market_status(exchange_id);
+		ensure!(!status.closed, Error::<T>::ExchangeClosed);
+		ensure!(!status.deposits_paused, Error::<T>::DepositsPaused);
+		Ok(exchange)
+	}
+
+	fn ensure_owner_or_root(origin: T::Origin, exchange_id: ExchangeId) -> DispatchResult {
+		let exchange = Self::exchanges(exchange_id).ok_or(Error::<T>::InvalidExchangeId)?;
+		let origin: Result<system::RawOrigin<T::AccountId>, T::Origin> = origin.into();
+		match origin {
+			Ok(system::RawOrigin::Root) => Ok(()),
+			Ok(system::RawOrigin::Signed(who)) if who == exchange.creator => Ok(()),
+			_ => Err(Error::<T>::NotExchangeOwner.into()),
+		}
+	}
+
+	/// Deletes a closed exchange once only locked liquidity is left in it and all its quote
+	/// markets are gone.
+	///
+	/// Locked shares are never paid out: the reserves backing them move to the locked
+	/// liquidity account for good, leaving the vault empty.
+	fn remove_exchange_if_empty(exchange_id: ExchangeId) -> DispatchResult {
+		if QuoteMarkets::iter_prefix_values(exchange_id).next().is_some() {
+			return Ok(());
+		}
+
+		let locked_account = Self::locked_liquidity_account();
+		let locked_shares = |token_id: &T::TokenId| {
+			Self::liquidity_tokens(exchange_id, token_id)
+				.map(|lp| token::Module::<T>::balance_of(&locked_account, &lp))
+				.unwrap_or_else(Zero::zero)
+		};
+		if TotalSupplies::<T>::iter_prefix(exchange_id).any(|(id, supply)| supply > locked_shares(&id)) {
+			return Ok(());
+		}
+
+		if let Some(exchange) = Exchanges::<T>::take(exchange_id) {
+			if Self::exchange_by_currency(exchange.currency) == Some(exchange_id) {
+				ExchangeByCurrency::remove(exchange.currency);
+			}
+			Self::sweep_locked_liquidity(exchange_id, &exchange)?;
+		}
+
+		TotalSupplies::<T>::remove_prefix(exchange_id);
+		CurrencyReserves::<T>::remove_prefix(exchange_id);
+		LiquidityTokens::<T>::remove_prefix(exchange_id);
+		PoolCurves::<T>::remove_prefix(exchange_id);
+		ExchangeStatuses::remove(exchange_id);
+		MinPoolSizes::<T>::remove(exchange_id);
+		BatchAuctions::remove(exchange_id);
+		LegacyLiquidity::<T>::remove_prefix(exchange_id);
+		PriceObservations::<T>::remove_prefix(exchange_id);
+		VolumeObservations::<T>::remove_prefix(exchange_id);
+		PriceReferences::<T>::remove_prefix(exchange_id);
+		CircuitBreakers::<T>::remove(exchange_id);
+
+		Self::deposit_event(RawEvent::ExchangeRemoved(exchange_id));
+
+		// The parent may have been waiting on this market only.
+		if let Some(parent_id) = MarketParents::take(exchange_id) {
+			let currency_id = QuoteMarkets::iter_prefix(parent_id)
+				.find(|(_, market_id)| *market_id == exchange_id)
+				.map(|(currency_id, _)| currency_id);
+			if let Some(currency_id) = currency_id {
+				QuoteMarkets::remove(parent_id, currency_id);
+			}
+			if Self::exchange_status(parent_id).closed {
+				Self::remove_exchange_if_empty(parent_id)?;
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Moves the reserves of every pool of a removed exchange to the locked liquidity account
+	/// and burns the locked shares they backed.
+	fn sweep_locked_liquidity(exchange_id: ExchangeId, exchange: &ExchangeInfo<T::AccountId>) -> DispatchResult {
+		let locked_account = Self::locked_liquidity_account();
+
+		for (token_id, supply) in TotalSupplies::<T>::iter_prefix(exchange_id) {
+			if supply.is_zero() {
+				continue;
+			}
+
+			let tokens = token::Module::<T>::balance_of(&exchange.vault, &token_id);
+			let currency = Self::currency_reserves(exchange_id, token_id);
+			token::Module::<T>::transfer_from(&exchange.vault, &locked_account, &token_id, tokens)?;
+			currency::Module::<T>::do_transfer_from(&exchange.vault, &locked_account, &exchange.currency, currency)?;
+			if let Some(lp_token) = Self::liquidity_tokens(exchange_id, token_id) {
+				token::Module::<T>::burn(&locked_account, &lp_token, supply)?;
+			}
+
+			Self::deposit_event(RawEvent::LockedLiquiditySwept(exchange_id, token_id, tokens, currency));
+		}
+
+		Ok(())
+	}
+
+	fn ensure_deadline(deadline: BlockNumber) -> DispatchResult {
+		let now = system::Module::<T>::block_number();
+		ensure!(now <= T::BlockNumber::from(deadline), Error::<T>::DeadlineExpired);
+		Ok(())
+	}
+
+	/// Checks that `token_ids` is non-empty and free of duplicates, and that every
+	/// per-token argument has a matching length.
+	fn ensure_token_ids(token_ids: &Vec<T::TokenId>, amounts: &[&Vec<T::TokenBalance>]) -> DispatchResult {
+		ensure!(!token_ids.is_empty(), Error::<T>::InvalidArrayLength);
+		ensure!(amounts.iter().all(|a| a.len() == token_ids.len()), Error::<T>::InvalidArrayLength);
+
+		let unique: BTreeSet<_> = token_ids.iter().collect();
+		ensure!(unique.len() == token_ids.len(), Error::<T>::DuplicateTokenId);
+
+		Ok(())
+	}
+
+	fn sum(amounts: &Vec<T::TokenBalance>) -> Result<T::TokenBalance, DispatchError> {
+		amounts.iter().try_fold(T::TokenBalance::from(0u32), |total, amount| {
+			total.checked_add(amount).ok_or(Error::<T>::Overflow.into())
+		})
+	}
+}
+
+impl<T: Trait> PriceOracle<T::TokenId, T::BlockNumber> for Module<T> {
+	fn twap(exchange_id: ExchangeId, token_id: T::TokenId, window: T::BlockNumber) -> Option<(FixedU128, FixedU128)> {
+		let exchange = Self::exchanges(exchange_id)?;
+		let currency_reserve = Self::currency_reserves(exchange_id, token_id);
+		let token_reserve = token::Module::<T>::balance_of(&exchange.vault, &token_id);
+
+		let observations = Self::price_observations(exchange_id, token_id);
+		let last = observations.last()?;
+
+		let curve = Self::pool_curve(exchange_id, token_id);
+
+		let now = system::Module::<T>::block_number();
+		if window.is_zero() {
+			return Self::spot_prices(curve, currency_reserve, token_reserve);
 		}
+		let start = now.checked_sub(&window)?;
+		let first = observations.iter().rev().find(|o| o.block_number <= start)?;
+
+		// Extend the latest accumulators with the price held since the last trade.
+		let (currency_per_token, token_per_currency) = Self::spot_prices(curve, currency_reserve, token_reserve)
+			.unwrap_or((FixedU128::zero(), FixedU128::zero()));
+		let since_last: u128 = now.saturating_sub(last.block_number).unique_saturated_into();
+		let current_currency_per_token = last.currency_per_token
+			.wrapping_add(currency_per_token.into_inner().wrapping_mul(since_last));
+		let current_token_per_currency = last.token_per_currency
+			.wrapping_add(token_per_currency.into_inner().wrapping_mul(since_last));
+
+		let elapsed: u128 = now.saturating_sub(first.block_number).unique_saturated_into();
+
+		Some((
+			FixedU128::from_inner(current_currency_per_token.wrapping_sub(first.currency_per_token) / elapsed),
+			FixedU128::from_inner(current_token_per_currency.wrapping_sub(first.token_per_currency) / elapsed),
+		))
 	}
 }
@@ -1,11 +1,12 @@
 use crate::{Module, Trait};
 
 use sp_core::H256;
-use frame_support::{impl_outer_origin, impl_outer_event ,parameter_types, weights::Weight, traits::OnFinalize, traits::OnInitialize};
+use frame_support::{impl_outer_origin, impl_outer_event, impl_outer_dispatch, parameter_types, weights::Weight, traits::{Get, OnFinalize, OnInitialize}};
 use sp_runtime::{
     traits::{BlakeTwo256, IdentityLookup}, testing::Header, Perbill, ModuleId,
 };
 use frame_system as system;
+use std::cell::RefCell;
 
 pub const MILLISECS_PER_BLOCK: u64 = 4000;
 
@@ -20,6 +21,14 @@ mod dex {
     pub use super::super::*;
 }
 
+impl_outer_dispatch! {
+    pub enum Call for Test where origin: Origin {
+        frame_system::System,
+        dex::Dex,
+        currency::Currency,
+    }
+}
+
 impl_outer_event! {
     pub enum TestEvent for Test {
         system<T>,
@@ -28,6 +37,7 @@ impl_outer_event! {
         token<T>,
         currency<T>,
         tao<T>,
+        nft<T>,
     }
 }
 
@@ -95,8 +105,13 @@ impl token::Trait for Test {
     type TokenId = u64;
 }
 
+parameter_types! {
+    pub const CurrencyModuleId: ModuleId = ModuleId(*b"spr/curr");
+}
+
 impl currency::Trait for Test {
     type Event = TestEvent;
+    type ModuleId = CurrencyModuleId;
 }
 
 impl tao::Trait for Test {
@@ -104,18 +119,59 @@ impl tao::Trait for Test {
     type TaoId = u64;
 }
 
+impl nft::Trait for Test {
+    type Event = TestEvent;
+}
+
 parameter_types! {
     pub const DexModuleId: ModuleId = ModuleId(*b"spr/dexm");
+    pub const MaxPriceObservations: u32 = 4;
+    pub const MaxOrdersPerPool: u32 = 4;
+    pub const OrderDeposit: u64 = 5;
+    pub const MaxBatchSwapsPerPool: u32 = 3;
+    pub const MaxBatchSwapsPerBlock: u32 = 4;
+    pub const DefaultMinPoolSize: u128 = 1000;
+    pub const RevealWindow: u64 = 5;
+    pub const ReferralFeeShare: u32 = 5_000;
 }
+
+thread_local! {
+    static MINIMUM_LIQUIDITY: RefCell<u128> = RefCell::new(0);
+}
+
+pub struct MinimumLiquidity;
+impl Get<u128> for MinimumLiquidity {
+    fn get() -> u128 {
+        MINIMUM_LIQUIDITY.with(|v| *v.borrow())
+    }
+}
+
+pub fn set_minimum_liquidity(amount: u128) {
+    MINIMUM_LIQUIDITY.with(|v| *v.borrow_mut() = amount);
+}
+
 impl Trait for Test {
     type Event = TestEvent;
     type ModuleId = DexModuleId;
+    type MaxPriceObservations = MaxPriceObservations;
+    type Call = Call;
+    type MaxOrdersPerPool = MaxOrdersPerPool;
+    type OrderDeposit = OrderDeposit;
+    type MaxBatchSwapsPerPool = MaxBatchSwapsPerPool;
+    type MaxBatchSwapsPerBlock = MaxBatchSwapsPerBlock;
+    type MinimumLiquidity = MinimumLiquidity;
+    type DefaultMinPoolSize = DefaultMinPoolSize;
+    type RevealWindow = RevealWindow;
+    type DepositCurrency = Balances;
+    type ReferralFeeShare = ReferralFeeShare;
 }
 
 pub type Dex = Module<Test>;
+pub type Balances = pallet_balances::Module<Test>;
 pub type Currency = currency::Module<Test>;
 pub type Token = token::Module<Test>;
 pub type Tao = tao::Module<Test>;
+pub type Nft = nft::Module<Test>;
 pub type System = frame_system::Module<Test>;
 
 pub fn new_test_ext() -> sp_io::TestExternalities {
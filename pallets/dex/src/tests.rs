@@ -1,15 +1,19 @@
 use crate::mock::*;
 use super::*;
-use frame_support::{assert_ok, assert_noop};
+use frame_support::{assert_ok, assert_noop, traits::OnRuntimeUpgrade};
 
 #[test]
 fn create_exchange_works() {
     new_test_ext().execute_with(|| {
 		run_to_block(10);
 		assert!(Dex::exchanges(0).is_none());
-		assert_ok!(Dex::create_exchange(Origin::signed(1), 1));
+		assert_noop!(Dex::create_exchange(Origin::signed(1), 0), currency::Error::<Test>::InvalidCurrencyId);
+
+		assert_ok!(Currency::create(Origin::signed(1), [0].to_vec()));
+		assert_ok!(Dex::create_exchange(Origin::signed(1), 0));
 
 		assert!(Dex::exchanges(0).is_some());
+		assert_eq!(Dex::exchange_by_currency(0), Some(0));
 
 		assert_eq!(
 			System::events()
@@ -32,6 +36,7 @@ fn create_exchange_works() {
 pub fn before_exchange() {
 	Tao::create_tao(Origin::signed(1), [0].to_vec());
 	Tao::create_tao_item(Origin::signed(1), 0, false, [0].to_vec());
+	Tao::mint(Origin::signed(1), 0, 0, 1000, 1);
 	assert!(Token::tokens(0).is_some());
 
 	Currency::create(Origin::signed(1), [0].to_vec());
@@ -51,11 +56,1506 @@ fn add_liquidity_works() {
 		assert_ok!(Dex::add_liquidity(
 			Origin::signed(1),
 			0,
+			0,
 			2,
 			[0].to_vec(),
 			[100].to_vec(),
 			[1000].to_vec(),
-			20
+			20,
+			false,
 		));
 	});
 }
+
+fn vault() -> u64 {
+	Dex::exchanges(0).unwrap().vault
+}
+
+fn price(currency_per_token: u128) -> FixedU128 {
+	FixedU128::saturating_from_integer(currency_per_token)
+}
+
+/// Seeds exchange 0 with a pool of 100 tokens against 1000 currency and funds account 2.
+pub fn before_swap() {
+	before_exchange();
+	run_to_block(10);
+
+	assert_ok!(Dex::add_liquidity(Origin::signed(1), 0, 0, 1, [0].to_vec(), [100].to_vec(), [1000].to_vec(), 20, false));
+
+	Currency::mint(Origin::signed(1), 0, 1000, 2);
+	Tao::mint(Origin::signed(1), 0, 0, 100, 2);
+}
+
+// Reference constant product formulas with a 0.5% fee on the input amount.
+fn reference_amount_out(amount_in: u128, reserve_in: u128, reserve_out: u128) -> u128 {
+	let amount_in_with_fee = amount_in * 995;
+	amount_in_with_fee * reserve_out / (reserve_in * 1000 + amount_in_with_fee)
+}
+
+fn reference_amount_in(amount_out: u128, reserve_in: u128, reserve_out: u128) -> u128 {
+	let numerator = reserve_in * amount_out * 1000;
+	let denominator = (reserve_out - amount_out) * 995;
+	(numerator + denominator - 1) / denominator
+}
+
+#[test]
+fn swap_exact_currency_for_tokens_works() {
+	new_test_ext().execute_with(|| {
+		before_swap();
+
+		let expected = reference_amount_out(100, 1000, 100);
+		assert_noop!(
+			Dex::swap_exact_currency_for_tokens(Origin::signed(2), 0, 0, [0].to_vec(), [100].to_vec(), [expected + 1].to_vec(), 2, 20, None, None),
+			Error::<Test>::InsufficientOutputAmount,
+		);
+		assert_ok!(Dex::swap_exact_currency_for_tokens(Origin::signed(2), 0, 0, [0].to_vec(), [100].to_vec(), [expected].to_vec(), 2, 20, None, None));
+
+		assert_eq!(Token::balance_of(&2, &0), 100 + expected);
+		assert_eq!(Token::balance_of(&2, &1), 900);
+		assert_eq!(Token::balance_of(&vault(), &0), 100 - expected);
+		assert_eq!(Dex::currency_reserves(0, 0), 1100);
+	});
+}
+
+#[test]
+fn swap_currency_for_exact_tokens_works() {
+	new_test_ext().execute_with(|| {
+		before_swap();
+
+		let expected = reference_amount_in(10, 1000, 100);
+		assert_noop!(
+			Dex::swap_currency_for_exact_tokens(Origin::signed(2), 0, 0, [0].to_vec(), [10].to_vec(), expected - 1, 2, 20, None, None),
+			Error::<Test>::MaxCurrencyAmountExceeded,
+		);
+		assert_ok!(Dex::swap_currency_for_exact_tokens(Origin::signed(2), 0, 0, [0].to_vec(), [10].to_vec(), expected, 2, 20, None, None));
+
+		assert_eq!(Token::balance_of(&2, &0), 110);
+		assert_eq!(Token::balance_of(&2, &1), 1000 - expected);
+		assert_eq!(Dex::currency_reserves(0, 0), 1000 + expected);
+	});
+}
+
+#[test]
+fn swap_exact_tokens_for_currency_works() {
+	new_test_ext().execute_with(|| {
+		before_swap();
+
+		let expected = reference_amount_out(10, 100, 1000);
+		assert_noop!(
+			Dex::swap_exact_tokens_for_currency(Origin::signed(2), 0, 0, [0].to_vec(), [10].to_vec(), expected + 1, 2, 20, None, None),
+			Error::<Test>::InsufficientCurrencyAmount,
+		);
+		assert_ok!(Dex::swap_exact_tokens_for_currency(Origin::signed(2), 0, 0, [0].to_vec(), [10].to_vec(), expected, 2, 20, None, None));
+
+		assert_eq!(Token::balance_of(&2, &0), 90);
+		assert_eq!(Token::balance_of(&2, &1), 1000 + expected);
+		assert_eq!(Token::balance_of(&vault(), &0), 110);
+		assert_eq!(Dex::currency_reserves(0, 0), 1000 - expected);
+	});
+}
+
+#[test]
+fn swap_tokens_for_exact_currency_works() {
+	new_test_ext().execute_with(|| {
+		before_swap();
+
+		let expected = reference_amount_in(50, 100, 1000);
+		assert_noop!(
+			Dex::swap_tokens_for_exact_currency(Origin::signed(2), 0, 0, [0].to_vec(), [50].to_vec(), [expected - 1].to_vec(), 2, 20, None, None),
+			Error::<Test>::MaxTokenAmountExceeded,
+		);
+		assert_ok!(Dex::swap_tokens_for_exact_currency(Origin::signed(2), 0, 0, [0].to_vec(), [50].to_vec(), [expected].to_vec(), 2, 20, None, None));
+
+		assert_eq!(Token::balance_of(&2, &0), 100 - expected);
+		assert_eq!(Token::balance_of(&2, &1), 1050);
+		assert_eq!(Dex::currency_reserves(0, 0), 950);
+	});
+}
+
+#[test]
+fn swap_rounding_matches_reference() {
+	new_test_ext().execute_with(|| {
+		for &(amount, reserve_in, reserve_out) in &[(1u128, 1000u128, 100u128), (7, 333, 999), (99, 100, 1000), (995, 1000, 1990)] {
+			assert_eq!(Dex::get_amount_out(PoolCurve::ConstantProduct, amount, reserve_in, reserve_out).unwrap(), reference_amount_out(amount, reserve_in, reserve_out));
+		}
+		// Both an inexact and an exact division.
+		for &(amount, reserve_in, reserve_out) in &[(10u128, 1000u128, 100u128), (1, 333, 999), (5, 995, 6)] {
+			assert_eq!(Dex::get_amount_in(PoolCurve::ConstantProduct, amount, reserve_in, reserve_out).unwrap(), reference_amount_in(amount, reserve_in, reserve_out));
+		}
+	});
+}
+
+#[test]
+fn swap_fails_after_deadline() {
+	new_test_ext().execute_with(|| {
+		before_swap();
+		run_to_block(21);
+
+		assert_noop!(
+			Dex::swap_exact_currency_for_tokens(Origin::signed(2), 0, 0, [0].to_vec(), [100].to_vec(), [1].to_vec(), 2, 20, None, None),
+			Error::<Test>::DeadlineExpired,
+		);
+	});
+}
+
+/// Adds a second token (id 3) to exchange 0 and opens exchange 1 on currency 1 (token 5),
+/// which also lists token 3. Tokens 2, 4 and 6 are the liquidity tokens of the pools.
+pub fn before_route() {
+	before_swap();
+
+	Tao::create_tao_item(Origin::signed(1), 0, false, [0].to_vec());
+	Tao::mint(Origin::signed(1), 0, 1, 1000, 1);
+	assert_ok!(Dex::add_liquidity(Origin::signed(1), 0, 0, 1, [3].to_vec(), [100].to_vec(), [1000].to_vec(), 20, false));
+
+	Currency::create(Origin::signed(1), [0].to_vec());
+	Currency::mint(Origin::signed(1), 1, 2000, 1);
+	assert_ok!(Dex::create_exchange(Origin::signed(1), 1));
+	assert_ok!(Dex::add_liquidity(Origin::signed(1), 1, 1, 1, [3].to_vec(), [100].to_vec(), [1000].to_vec(), 20, false));
+}
+
+#[test]
+fn swap_exact_tokens_for_tokens_works_across_exchanges() {
+	new_test_ext().execute_with(|| {
+		before_route();
+
+		let currency_0 = reference_amount_out(10, 100, 1000);
+		let token_3 = reference_amount_out(currency_0, 1000, 100);
+		let expected = reference_amount_out(token_3, 100, 1000);
+
+		assert_noop!(
+			Dex::swap_exact_tokens_for_tokens(Origin::signed(2), [(0, 0), (1, 1)].to_vec(), [0, 3, 5].to_vec(), 10, expected + 1, 2, 20, None, None),
+			Error::<Test>::InsufficientOutputAmount,
+		);
+		assert_ok!(Dex::swap_exact_tokens_for_tokens(Origin::signed(2), [(0, 0), (1, 1)].to_vec(), [0, 3, 5].to_vec(), 10, expected, 2, 20, None, None));
+
+		assert_eq!(Token::balance_of(&2, &0), 90);
+		assert_eq!(Token::balance_of(&2, &3), 0);
+		assert_eq!(Token::balance_of(&2, &5), expected);
+		assert_eq!(Dex::currency_reserves(0, 0), 1000 - currency_0);
+		assert_eq!(Dex::currency_reserves(0, 3), 1000 + currency_0);
+		assert_eq!(Dex::currency_reserves(1, 3), 1000 - expected);
+	});
+}
+
+#[test]
+fn swap_tokens_for_exact_tokens_works() {
+	new_test_ext().execute_with(|| {
+		before_route();
+
+		let currency_0 = reference_amount_in(5, 1000, 100);
+		let expected = reference_amount_in(currency_0, 100, 1000);
+
+		assert_noop!(
+			Dex::swap_tokens_for_exact_tokens(Origin::signed(2), [(0, 0)].to_vec(), [0, 3].to_vec(), 5, expected - 1, 2, 20, None, None),
+			Error::<Test>::MaxTokenAmountExceeded,
+		);
+		assert_ok!(Dex::swap_tokens_for_exact_tokens(Origin::signed(2), [(0, 0)].to_vec(), [0, 3].to_vec(), 5, expected, 2, 20, None, None));
+
+		assert_eq!(Token::balance_of(&2, &0), 100 - expected);
+		assert_eq!(Token::balance_of(&2, &3), 5);
+		assert_eq!(Dex::currency_reserves(0, 0), 1000 - currency_0);
+		assert_eq!(Dex::currency_reserves(0, 3), 1000 + currency_0);
+	});
+}
+
+#[test]
+fn swap_tokens_rejects_invalid_paths() {
+	new_test_ext().execute_with(|| {
+		before_route();
+
+		assert_noop!(
+			Dex::swap_exact_tokens_for_tokens(Origin::signed(2), [(0, 0), (0, 0)].to_vec(), [0, 3].to_vec(), 10, 1, 2, 20, None, None),
+			Error::<Test>::InvalidPath,
+		);
+		assert_noop!(
+			Dex::swap_exact_tokens_for_tokens(Origin::signed(2), [(0, 0), (0, 0)].to_vec(), [0, 3, 0].to_vec(), 10, 1, 2, 20, None, None),
+			Error::<Test>::InvalidPath,
+		);
+	});
+}
+
+#[test]
+fn twap_averages_prices_over_window() {
+	new_test_ext().execute_with(|| {
+		// Pool opens at block 10 with a price of 1000 / 100.
+		before_swap();
+
+		run_to_block(20);
+		assert_ok!(Dex::swap_exact_currency_for_tokens(Origin::signed(2), 0, 0, [0].to_vec(), [100].to_vec(), [1].to_vec(), 2, 30, None, None));
+		let token_reserve = Token::balance_of(&vault(), &0);
+
+		run_to_block(30);
+		let first = FixedU128::saturating_from_rational(1000, 100);
+		let second = FixedU128::saturating_from_rational(1100, token_reserve);
+		let expected = FixedU128::from_inner((first.into_inner() * 10 + second.into_inner() * 10) / 20);
+
+		let (currency_per_token, _) = <Dex as PriceOracle<_, _>>::twap(0, 0, 20).unwrap();
+		assert_eq!(currency_per_token, expected);
+
+		let (currency_per_token, _) = <Dex as PriceOracle<_, _>>::twap(0, 0, 5).unwrap();
+		assert_eq!(currency_per_token, second);
+
+		assert_eq!(<Dex as PriceOracle<_, _>>::twap(0, 0, 25), None);
+	});
+}
+
+#[test]
+fn price_observations_update_once_per_block() {
+	new_test_ext().execute_with(|| {
+		before_swap();
+
+		run_to_block(11);
+		assert_ok!(Dex::swap_exact_currency_for_tokens(Origin::signed(2), 0, 0, [0].to_vec(), [10].to_vec(), [1].to_vec(), 2, 30, None, None));
+		assert_ok!(Dex::swap_exact_currency_for_tokens(Origin::signed(2), 0, 0, [0].to_vec(), [10].to_vec(), [1].to_vec(), 2, 30, None, None));
+		assert_eq!(Dex::price_observations(0, 0).len(), 2);
+
+		for block in 12..16 {
+			run_to_block(block);
+			assert_ok!(Dex::swap_exact_currency_for_tokens(Origin::signed(2), 0, 0, [0].to_vec(), [10].to_vec(), [1].to_vec(), 2, 30, None, None));
+		}
+		let observations = Dex::price_observations(0, 0);
+		assert_eq!(observations.len(), 4);
+		assert_eq!(observations[0].block_number, 12);
+	});
+}
+
+mod fuzz {
+	use super::*;
+	use proptest::prelude::*;
+
+	const CURRENCY_TOKEN: u64 = 1;
+	// Pool tokens of exchange 0 and their liquidity tokens.
+	const POOLS: [(u64, u64); 2] = [(0, 2), (3, 4)];
+
+	#[derive(Clone, Debug)]
+	enum Op {
+		SwapExactCurrencyForTokens(usize, u128),
+		SwapCurrencyForExactTokens(usize, u128),
+		SwapExactTokensForCurrency(usize, u128),
+		SwapTokensForExactCurrency(usize, u128),
+		CurrencyToToken(usize, u128, u128),
+		TokenToCurrency(usize, u128),
+		SwapExactTokensForTokens(bool, u128),
+		SwapTokensForExactTokens(bool, u128, u128),
+		AddLiquidity(usize, u128, u128),
+		RemoveLiquidity(usize, u128),
+	}
+
+	fn amount() -> impl Strategy<Value = u128> {
+		prop_oneof![1u128..1_000_000, any::<u128>()]
+	}
+
+	fn op() -> impl Strategy<Value = Op> {
+		prop_oneof![
+			(0..2usize, amount()).prop_map(|(p, a)| Op::SwapExactCurrencyForTokens(p, a)),
+			(0..2usize, amount()).prop_map(|(p, a)| Op::SwapCurrencyForExactTokens(p, a)),
+			(0..2usize, amount()).prop_map(|(p, a)| Op::SwapExactTokensForCurrency(p, a)),
+			(0..2usize, amount()).prop_map(|(p, a)| Op::SwapTokensForExactCurrency(p, a)),
+			(0..2usize, amount(), amount()).prop_map(|(p, a, m)| Op::CurrencyToToken(p, a, m)),
+			(0..2usize, amount()).prop_map(|(p, a)| Op::TokenToCurrency(p, a)),
+			(any::<bool>(), amount()).prop_map(|(r, a)| Op::SwapExactTokensForTokens(r, a)),
+			(any::<bool>(), amount(), amount()).prop_map(|(r, a, m)| Op::SwapTokensForExactTokens(r, a, m)),
+			(0..2usize, amount(), amount()).prop_map(|(p, a, m)| Op::AddLiquidity(p, a, m)),
+			(0..2usize, amount()).prop_map(|(p, a)| Op::RemoveLiquidity(p, a)),
+		]
+	}
+
+	fn setup(token_reserves: [u128; 2], currency_reserves: [u128; 2]) {
+		Tao::create_tao(Origin::signed(1), [0].to_vec());
+		Tao::create_tao_item(Origin::signed(1), 0, false, [0].to_vec());
+		Currency::create(Origin::signed(1), [0].to_vec());
+		assert_ok!(Dex::create_exchange(Origin::signed(1), 0));
+		run_to_block(10);
+
+		Tao::mint(Origin::signed(1), 0, 0, token_reserves[0], 1);
+		Currency::mint(Origin::signed(1), 0, currency_reserves[0] + currency_reserves[1], 1);
+		assert_ok!(Dex::add_liquidity(Origin::signed(1), 0, 0, 1, [0].to_vec(), [token_reserves[0]].to_vec(), [currency_reserves[0]].to_vec(), 100, false));
+
+		Tao::create_tao_item(Origin::signed(1), 0, false, [0].to_vec());
+		Tao::mint(Origin::signed(1), 0, 1, token_reserves[1], 1);
+		assert_ok!(Dex::add_liquidity(Origin::signed(1), 0, 0, 1, [3].to_vec(), [token_reserves[1]].to_vec(), [currency_reserves[1]].to_vec(), 100, false));
+
+		Tao::mint(Origin::signed(1), 0, 0, 1_000_000_000_000, 2);
+		Tao::mint(Origin::signed(1), 0, 1, 1_000_000_000_000, 2);
+		Currency::mint(Origin::signed(1), 0, 1_000_000_000_000, 2);
+	}
+
+	fn apply(op: &Op) -> bool {
+		let token = |p: usize| [POOLS[p].0].to_vec();
+		let path = |reverse: bool| if reverse { [3, 0].to_vec() } else { [0, 3].to_vec() };
+		let who = Origin::signed(2);
+
+		let result = match *op {
+			Op::SwapExactCurrencyForTokens(p, a) =>
+				Dex::swap_exact_currency_for_tokens(who, 0, 0, token(p), [a].to_vec(), [1].to_vec(), 2, 100, None, None),
+			Op::SwapCurrencyForExactTokens(p, a) =>
+				Dex::swap_currency_for_exact_tokens(who, 0, 0, token(p), [a].to_vec(), u128::max_value(), 2, 100, None, None),
+			Op::SwapExactTokensForCurrency(p, a) =>
+				Dex::swap_exact_tokens_for_currency(who, 0, 0, token(p), [a].to_vec(), 0, 2, 100, None, None),
+			Op::SwapTokensForExactCurrency(p, a) =>
+				Dex::swap_tokens_for_exact_currency(who, 0, 0, token(p), [a].to_vec(), [u128::max_value()].to_vec(), 2, 100, None, None),
+			Op::CurrencyToToken(p, a, m) =>
+				Dex::currency_to_token(who, 0, token(p), [a].to_vec(), m, 2, 100),
+			Op::TokenToCurrency(p, a) =>
+				Dex::token_to_currency(who, 0, token(p), [a].to_vec(), 0, 2, 100),
+			Op::SwapExactTokensForTokens(r, a) =>
+				Dex::swap_exact_tokens_for_tokens(who, [(0, 0)].to_vec(), path(r), a, 0, 2, 100, None, None),
+			Op::SwapTokensForExactTokens(r, a, m) =>
+				Dex::swap_tokens_for_exact_tokens(who, [(0, 0)].to_vec(), path(r), a, m, 2, 100, None, None),
+			Op::AddLiquidity(p, a, m) =>
+				Dex::add_liquidity(who, 0, 0, 2, token(p), [a].to_vec(), [m].to_vec(), 100, false),
+			Op::RemoveLiquidity(p, a) =>
+				Dex::remove_liquidity(who, 0, 0, 2, token(p), [a].to_vec(), [0].to_vec(), [0].to_vec(), 100, vec![]),
+		};
+
+		result.is_ok()
+	}
+
+	fn product(pool: (u64, u64)) -> u128 {
+		Dex::currency_reserves(0, pool.0).saturating_mul(Token::balance_of(&vault(), &pool.0))
+	}
+
+	fn assert_vault_funds() {
+		let vault = vault();
+
+		let currency: u128 = POOLS.iter().map(|(token, _)| Dex::currency_reserves(0, token)).sum();
+		assert_eq!(Token::balance_of(&vault, &CURRENCY_TOKEN), currency);
+
+		for &(token, liquidity_token) in POOLS.iter() {
+			let supply = Dex::total_supplies(0, token);
+			assert_eq!(Token::balance_of(&1, &liquidity_token) + Token::balance_of(&2, &liquidity_token), supply);
+
+			if supply > 0 {
+				assert!(Dex::currency_reserves(0, token) > 0);
+				assert!(Token::balance_of(&vault, &token) > 0);
+			}
+		}
+	}
+
+	proptest! {
+		#![proptest_config(ProptestConfig::with_cases(64))]
+
+		#[test]
+		fn dispatchables_never_panic_or_lose_vault_funds(
+			token_reserves in [1u128..1_000_000_000, 1u128..1_000_000_000],
+			currency_reserves in [1000u128..1_000_000_000, 1000u128..1_000_000_000],
+			ops in prop::collection::vec(op(), 1..32),
+		) {
+			new_test_ext().execute_with(|| {
+				setup(token_reserves, currency_reserves);
+				assert_vault_funds();
+
+				for op in &ops {
+					let before = [product(POOLS[0]), product(POOLS[1])];
+					let ok = apply(op);
+					assert_vault_funds();
+
+					let is_swap = match op {
+						Op::AddLiquidity(..) | Op::RemoveLiquidity(..) => false,
+						_ => true,
+					};
+					if ok && is_swap {
+						assert!(product(POOLS[0]) >= before[0]);
+						assert!(product(POOLS[1]) >= before[1]);
+					}
+				}
+			});
+		}
+	}
+
+	fn balances_of_2() -> (u128, u128) {
+		(Token::balance_of(&2, &0), Token::balance_of(&2, &CURRENCY_TOKEN))
+	}
+
+	proptest! {
+		#![proptest_config(ProptestConfig::with_cases(64))]
+
+		#[test]
+		fn add_then_remove_liquidity_never_profits(
+			token_reserves in [1u128..1_000_000_000, 1u128..1_000_000_000],
+			currency_reserves in [1000u128..1_000_000_000, 1000u128..1_000_000_000],
+			amount in 1u128..1_000_000_000,
+		) {
+			new_test_ext().execute_with(|| {
+				setup(token_reserves, currency_reserves);
+				let before = balances_of_2();
+
+				if Dex::add_liquidity(Origin::signed(2), 0, 0, 2, [0].to_vec(), [amount].to_vec(), [u128::max_value()].to_vec(), 100, false).is_ok() {
+					let liquidity = Token::balance_of(&2, &2);
+					assert_ok!(Dex::remove_liquidity(Origin::signed(2), 0, 0, 2, [0].to_vec(), [liquidity].to_vec(), [0].to_vec(), [0].to_vec(), 100, vec![]));
+				}
+
+				let after = balances_of_2();
+				assert!(after.0 <= before.0);
+				assert!(after.1 <= before.1);
+			});
+		}
+
+		#[test]
+		fn buy_then_sell_never_profits(
+			token_reserves in [1u128..1_000_000_000, 1u128..1_000_000_000],
+			currency_reserves in [1000u128..1_000_000_000, 1000u128..1_000_000_000],
+			amount in 1u128..1_000_000_000,
+			exact_output in any::<bool>(),
+		) {
+			new_test_ext().execute_with(|| {
+				setup(token_reserves, currency_reserves);
+				let before = balances_of_2();
+
+				let bought = if exact_output {
+					Dex::swap_currency_for_exact_tokens(Origin::signed(2), 0, 0, [0].to_vec(), [amount].to_vec(), u128::max_value(), 2, 100, None, None)
+				} else {
+					Dex::swap_exact_currency_for_tokens(Origin::signed(2), 0, 0, [0].to_vec(), [amount].to_vec(), [1].to_vec(), 2, 100, None, None)
+				};
+				if bought.is_ok() {
+					let tokens = balances_of_2().0 - before.0;
+					if Dex::swap_exact_tokens_for_currency(Origin::signed(2), 0, 0, [0].to_vec(), [tokens].to_vec(), 0, 2, 100, None, None).is_ok() {
+						let after = balances_of_2();
+						assert_eq!(after.0, before.0);
+						assert!(after.1 <= before.1);
+					}
+				}
+			});
+		}
+
+		#[test]
+		fn sell_then_buy_never_profits(
+			token_reserves in [1u128..1_000_000_000, 1u128..1_000_000_000],
+			currency_reserves in [1000u128..1_000_000_000, 1000u128..1_000_000_000],
+			amount in 1u128..1_000_000_000,
+			exact_output in any::<bool>(),
+		) {
+			new_test_ext().execute_with(|| {
+				setup(token_reserves, currency_reserves);
+				let before = balances_of_2();
+
+				let sold = if exact_output {
+					Dex::swap_tokens_for_exact_currency(Origin::signed(2), 0, 0, [0].to_vec(), [amount].to_vec(), [u128::max_value()].to_vec(), 2, 100, None, None)
+				} else {
+					Dex::swap_exact_tokens_for_currency(Origin::signed(2), 0, 0, [0].to_vec(), [amount].to_vec(), 0, 2, 100, None, None)
+				};
+				if sold.is_ok() {
+					let currency = balances_of_2().1 - before.1;
+					if Dex::swap_exact_currency_for_tokens(Origin::signed(2), 0, 0, [0].to_vec(), [currency].to_vec(), [1].to_vec(), 2, 100, None, None).is_ok() {
+						let after = balances_of_2();
+						assert_eq!(after.1, before.1);
+						assert!(after.0 <= before.0);
+					}
+				}
+			});
+		}
+	}
+}
+
+#[test]
+fn flash_swap_works_when_repaid_with_fee() {
+	new_test_ext().execute_with(|| {
+		before_swap();
+
+		let call = Box::new(Call::System(frame_system::Call::remark(vec![])));
+		assert_ok!(Dex::flash_swap(Origin::signed(2), 0, 0, 0, 10, 0, 11, 0, call));
+
+		assert_eq!(Token::balance_of(&2, &0), 99);
+		assert_eq!(Token::balance_of(&vault(), &0), 101);
+		assert_eq!(Dex::currency_reserves(0, 0), 1000);
+		assert!(!Dex::flash_swap_locks(0));
+	});
+}
+
+#[test]
+fn flash_swap_fails_without_fee() {
+	new_test_ext().execute_with(|| {
+		before_swap();
+
+		let call = Box::new(Call::System(frame_system::Call::remark(vec![])));
+		assert_noop!(
+			Dex::flash_swap(Origin::signed(2), 0, 0, 0, 10, 0, 10, 0, call),
+			Error::<Test>::FlashSwapNotRepaid,
+		);
+
+		// Repaying a token loan in currency works as long as the product is restored.
+		let call = Box::new(Call::System(frame_system::Call::remark(vec![])));
+		assert_ok!(Dex::flash_swap(Origin::signed(2), 0, 0, 0, 10, 0, 0, 112, call));
+		assert_eq!(Dex::currency_reserves(0, 0), 1112);
+	});
+}
+
+#[test]
+fn flash_swap_locks_the_exchange() {
+	new_test_ext().execute_with(|| {
+		before_swap();
+
+		let call = Box::new(Call::Dex(dex::Call::swap_exact_currency_for_tokens(0, 0, vec![0], vec![100], vec![1], 2, 20, None, None)));
+		assert_noop!(
+			Dex::flash_swap(Origin::signed(2), 0, 0, 0, 10, 0, 11, 0, call),
+			Error::<Test>::ExchangeLocked,
+		);
+	});
+}
+
+#[test]
+fn place_order_escrows_and_sorts_the_book() {
+	new_test_ext().execute_with(|| {
+		before_swap();
+
+		assert_ok!(Dex::place_order(Origin::signed(1), 0, 0, 0, OrderSide::Sell, price(9), 5));
+		assert_ok!(Dex::place_order(Origin::signed(1), 0, 0, 0, OrderSide::Sell, price(8), 5));
+		assert_ok!(Dex::place_order(Origin::signed(2), 0, 0, 0, OrderSide::Sell, price(9), 5));
+		assert_eq!(Dex::asks(0, 0), vec![(price(8), 1), (price(9), 0), (price(9), 2)]);
+
+		assert_ok!(Dex::place_order(Origin::signed(2), 0, 0, 0, OrderSide::Buy, price(5), 10));
+		assert_ok!(Dex::place_order(Origin::signed(2), 0, 0, 0, OrderSide::Buy, price(7), 10));
+		assert_eq!(Dex::bids(0, 0), vec![(price(7), 4), (price(5), 3)]);
+
+		assert_eq!(Token::balance_of(&Dex::order_escrow(), &0), 15);
+		assert_eq!(Token::balance_of(&Dex::order_escrow(), &1), 120);
+		assert_eq!(Token::balance_of(&2, &1), 880);
+
+		assert_noop!(
+			Dex::place_order(Origin::signed(2), 0, 0, 1, OrderSide::Buy, price(5), 10),
+			Error::<Test>::SameCurrencyAndToken,
+		);
+		assert_noop!(
+			Dex::place_order(Origin::signed(2), 0, 0, 0, OrderSide::Buy, price(0), 10),
+			Error::<Test>::InvalidPrice,
+		);
+		assert_ok!(Dex::place_order(Origin::signed(2), 0, 0, 0, OrderSide::Sell, price(20), 1));
+		assert_noop!(
+			Dex::place_order(Origin::signed(2), 0, 0, 0, OrderSide::Sell, price(20), 1),
+			Error::<Test>::OrderBookFull,
+		);
+		assert_eq!(Balances::reserved_balance(2), 20);
+	});
+}
+
+#[test]
+fn non_fungible_tokens_have_no_pools_or_orders() {
+	new_test_ext().execute_with(|| {
+		before_swap();
+
+		let nft = Token::next_token_id();
+		assert_ok!(Tao::create_tao_item(Origin::signed(1), 0, true, [1].to_vec()));
+		assert_noop!(Tao::mint(Origin::signed(1), 0, 1, 2, 1), token::Error::<Test>::NonFungibleAmount);
+		assert_ok!(Tao::mint(Origin::signed(1), 0, 1, 1, 1));
+		assert_noop!(Tao::mint(Origin::signed(1), 0, 1, 1, 2), token::Error::<Test>::NonFungibleAmount);
+
+		assert_noop!(
+			Dex::add_liquidity(Origin::signed(1), 0, 0, 1, [nft].to_vec(), [1].to_vec(), [1000].to_vec(), 20, false),
+			Error::<Test>::NonFungibleToken,
+		);
+		assert_noop!(
+			Dex::place_order(Origin::signed(1), 0, 0, nft, OrderSide::Sell, price(9), 1),
+			Error::<Test>::NonFungibleToken,
+		);
+	});
+}
+
+#[test]
+fn cancel_order_refunds_escrow() {
+	new_test_ext().execute_with(|| {
+		before_swap();
+
+		assert_ok!(Dex::place_order(Origin::signed(2), 0, 0, 0, OrderSide::Buy, price(5), 10));
+		assert_eq!(Balances::reserved_balance(2), 5);
+		assert_noop!(Dex::cancel_order(Origin::signed(1), 0), Error::<Test>::NotOrderOwner);
+
+		assert_ok!(Dex::cancel_order(Origin::signed(2), 0));
+		assert!(Dex::orders(0).is_none());
+		assert!(Dex::bids(0, 0).is_empty());
+		assert_eq!(Token::balance_of(&2, &1), 1000);
+		assert_eq!(Balances::reserved_balance(2), 0);
+		assert_noop!(Dex::cancel_order(Origin::signed(2), 0), Error::<Test>::InvalidOrderId);
+	});
+}
+
+#[test]
+fn fill_order_trades_part_of_an_order() {
+	new_test_ext().execute_with(|| {
+		before_swap();
+
+		assert_ok!(Dex::place_order(Origin::signed(1), 0, 0, 0, OrderSide::Sell, price(20), 5));
+		assert_noop!(Dex::fill_order(Origin::signed(2), 0, 6, 2), Error::<Test>::InsufficientTokenAmount);
+
+		assert_ok!(Dex::fill_order(Origin::signed(2), 0, 2, 2));
+		assert_eq!(Dex::orders(0).unwrap().amount, 3);
+		assert_eq!(Token::balance_of(&2, &0), 102);
+		assert_eq!(Token::balance_of(&2, &1), 960);
+		assert_eq!(Token::balance_of(&Dex::order_escrow(), &0), 3);
+	});
+}
+
+#[test]
+fn fractional_prices_round_in_the_makers_favour() {
+	new_test_ext().execute_with(|| {
+		before_swap();
+		let half = FixedU128::saturating_from_rational(1, 2);
+		let currency_before = Token::balance_of(&1, &1);
+
+		// Taking 3 tokens at half a unit each costs 1.5, rounded up for the seller.
+		assert_ok!(Dex::place_order(Origin::signed(1), 0, 0, 0, OrderSide::Sell, half, 5));
+		assert_ok!(Dex::fill_order(Origin::signed(2), 0, 3, 2));
+		assert_eq!(Token::balance_of(&2, &0), 103);
+		assert_eq!(Token::balance_of(&2, &1), 998);
+
+		// A buy of 5 tokens escrows 2.5 rounded up, and fills pay the taker rounded down.
+		assert_ok!(Dex::place_order(Origin::signed(2), 0, 0, 0, OrderSide::Buy, half, 5));
+		assert_eq!(Dex::orders(1).unwrap().currency_escrowed, 3);
+		assert_eq!(Token::balance_of(&2, &1), 995);
+		assert_noop!(Dex::fill_order(Origin::signed(1), 1, 1, 1), Error::<Test>::InsufficientTokenAmount);
+
+		assert_ok!(Dex::fill_order(Origin::signed(1), 1, 2, 1));
+		assert_eq!(Dex::orders(1).unwrap().currency_escrowed, 2);
+		assert_ok!(Dex::fill_order(Origin::signed(1), 1, 3, 1));
+
+		// The unit left in escrow by rounding goes back to the buyer.
+		assert!(Dex::orders(1).is_none());
+		assert_eq!(Token::balance_of(&2, &1), 996);
+		assert_eq!(Token::balance_of(&2, &0), 108);
+		assert_eq!(Token::balance_of(&1, &1), currency_before + 4);
+		assert_eq!(Token::balance_of(&Dex::order_escrow(), &1), 0);
+		assert_eq!(Token::balance_of(&Dex::order_escrow(), &0), 2);
+
+		// The filled order's deposit is returned, the partly filled one keeps its own.
+		assert_eq!(Balances::reserved_balance(2), 0);
+		assert_eq!(Balances::reserved_balance(1), 5);
+	});
+}
+
+#[test]
+fn swaps_cross_orders_better_than_the_pool() {
+	new_test_ext().execute_with(|| {
+		before_swap();
+
+		// Buying from the pool costs about 10.05 per token, so only the ask at 8 is crossed.
+		assert_ok!(Dex::place_order(Origin::signed(1), 0, 0, 0, OrderSide::Sell, price(8), 5));
+		assert_ok!(Dex::place_order(Origin::signed(1), 0, 0, 0, OrderSide::Sell, price(12), 5));
+		let currency_before = Token::balance_of(&1, &1);
+
+		let pool_cost = reference_amount_in(5, 1000, 100);
+		assert_ok!(Dex::swap_currency_for_exact_tokens(Origin::signed(2), 0, 0, [0].to_vec(), [10].to_vec(), 40 + pool_cost, 2, 20, None, None));
+
+		assert_eq!(Token::balance_of(&2, &0), 110);
+		assert_eq!(Token::balance_of(&2, &1), 1000 - 40 - pool_cost);
+		assert_eq!(Token::balance_of(&1, &1), currency_before + 40);
+		assert_eq!(Token::balance_of(&vault(), &0), 95);
+		assert_eq!(Dex::currency_reserves(0, 0), 1000 + pool_cost);
+		assert!(Dex::orders(0).is_none());
+		assert_eq!(Dex::asks(0, 0), vec![(price(12), 1)]);
+	});
+}
+
+#[test]
+fn sells_partially_fill_bids() {
+	new_test_ext().execute_with(|| {
+		before_swap();
+
+		// Selling to the pool pays about 9.95 per token, so the bid at 10 is crossed first.
+		assert_ok!(Dex::place_order(Origin::signed(1), 0, 0, 0, OrderSide::Buy, price(10), 10));
+		assert_ok!(Dex::swap_exact_tokens_for_currency(Origin::signed(2), 0, 0, [0].to_vec(), [4].to_vec(), 40, 2, 20, None, None));
+
+		assert_eq!(Token::balance_of(&2, &0), 96);
+		assert_eq!(Token::balance_of(&2, &1), 1040);
+		assert_eq!(Token::balance_of(&1, &0), 904);
+		assert_eq!(Dex::orders(0).unwrap().amount, 6);
+		assert_eq!(Dex::currency_reserves(0, 0), 1000);
+		assert_eq!(Token::balance_of(&vault(), &0), 100);
+	});
+}
+
+#[test]
+fn owner_or_root_can_pause_an_exchange() {
+	new_test_ext().execute_with(|| {
+		before_swap();
+
+		assert_noop!(Dex::set_trading_paused(Origin::signed(2), 0, true), Error::<Test>::NotExchangeOwner);
+		assert_ok!(Dex::set_trading_paused(Origin::signed(1), 0, true));
+		assert_noop!(
+			Dex::swap_exact_currency_for_tokens(Origin::signed(2), 0, 0, [0].to_vec(), [100].to_vec(), [1].to_vec(), 2, 20, None, None),
+			Error::<Test>::TradingPaused,
+		);
+		assert_noop!(
+			Dex::place_order(Origin::signed(2), 0, 0, 0, OrderSide::Buy, price(5), 10),
+			Error::<Test>::TradingPaused,
+		);
+		assert_ok!(Dex::add_liquidity(Origin::signed(2), 0, 0, 2, [0].to_vec(), [10].to_vec(), [100].to_vec(), 20, false));
+
+		assert_ok!(Dex::set_deposits_paused(Origin::root(), 0, true));
+		assert_noop!(
+			Dex::add_liquidity(Origin::signed(2), 0, 0, 2, [0].to_vec(), [10].to_vec(), [100].to_vec(), 20, false),
+			Error::<Test>::DepositsPaused,
+		);
+		assert_ok!(Dex::remove_liquidity(Origin::signed(2), 0, 0, 2, [0].to_vec(), [100].to_vec(), [0].to_vec(), [0].to_vec(), 20, vec![]));
+
+		assert_ok!(Dex::set_trading_paused(Origin::root(), 0, false));
+		assert_ok!(Dex::swap_exact_currency_for_tokens(Origin::signed(2), 0, 0, [0].to_vec(), [100].to_vec(), [1].to_vec(), 2, 20, None, None));
+	});
+}
+
+#[test]
+fn transfer_ownership_moves_control() {
+	new_test_ext().execute_with(|| {
+		before_swap();
+
+		assert_noop!(Dex::transfer_ownership(Origin::signed(2), 0, 2), Error::<Test>::NotExchangeOwner);
+		assert_ok!(Dex::transfer_ownership(Origin::signed(1), 0, 2));
+		assert_eq!(Dex::exchanges(0).unwrap().creator, 2);
+
+		assert_noop!(Dex::set_trading_paused(Origin::signed(1), 0, true), Error::<Test>::NotExchangeOwner);
+		assert_ok!(Dex::set_trading_paused(Origin::signed(2), 0, true));
+	});
+}
+
+#[test]
+fn closed_exchange_only_allows_withdrawals_and_is_deleted_once_empty() {
+	new_test_ext().execute_with(|| {
+		before_swap();
+		let vault = vault();
+
+		assert_ok!(Dex::place_order(Origin::signed(2), 0, 0, 0, OrderSide::Buy, price(5), 10));
+		assert_eq!(Token::balance_of(&2, &1), 950);
+
+		assert_ok!(Dex::close_exchange(Origin::signed(1), 0));
+		assert!(Dex::orders(0).is_none());
+		assert!(Dex::bids(0, 0).is_empty());
+		assert_eq!(Token::balance_of(&2, &1), 1000);
+		assert_eq!(Balances::reserved_balance(2), 0);
+
+		assert_noop!(Dex::close_exchange(Origin::root(), 0), Error::<Test>::ExchangeClosed);
+		assert_noop!(Dex::set_trading_paused(Origin::signed(1), 0, false), Error::<Test>::ExchangeClosed);
+		assert_noop!(
+			Dex::swap_exact_currency_for_tokens(Origin::signed(2), 0, 0, [0].to_vec(), [100].to_vec(), [1].to_vec(), 2, 20, None, None),
+			Error::<Test>::ExchangeClosed,
+		);
+		assert_noop!(
+			Dex::add_liquidity(Origin::signed(2), 0, 0, 2, [0].to_vec(), [10].to_vec(), [100].to_vec(), 20, false),
+			Error::<Test>::ExchangeClosed,
+		);
+
+		assert_ok!(Dex::remove_liquidity(Origin::signed(1), 0, 0, 1, [0].to_vec(), [500].to_vec(), [0].to_vec(), [0].to_vec(), 20, vec![]));
+		assert!(Dex::exchanges(0).is_some());
+
+		assert_ok!(Dex::remove_liquidity(Origin::signed(1), 0, 0, 1, [0].to_vec(), [500].to_vec(), [0].to_vec(), [0].to_vec(), 20, vec![]));
+		assert!(Dex::exchanges(0).is_none());
+		assert_eq!(Dex::currency_reserves(0, 0), 0);
+		assert_eq!(Token::balance_of(&vault, &0), 0);
+		assert_eq!(
+			System::events().into_iter().map(|r| r.event).last().unwrap(),
+			TestEvent::dex(RawEvent::ExchangeRemoved(0)),
+		);
+	});
+}
+
+#[test]
+fn one_exchange_per_currency() {
+	new_test_ext().execute_with(|| {
+		before_route();
+
+		assert_noop!(Dex::create_exchange(Origin::signed(2), 0), Error::<Test>::ExchangeAlreadyExists);
+		assert_eq!(Dex::exchange_for_currency(0), Some((0, vec![0, 3])));
+		assert_eq!(Dex::exchange_for_currency(1), Some((1, vec![3])));
+		assert_eq!(Dex::exchange_for_currency(2), None);
+
+		// Draining a pool takes it off the list.
+		assert_ok!(Dex::remove_liquidity(Origin::signed(1), 1, 1, 1, [3].to_vec(), [1000].to_vec(), [0].to_vec(), [0].to_vec(), 20, vec![]));
+		assert_eq!(Dex::exchange_for_currency(1), Some((1, vec![])));
+
+		// The currency gets a new exchange once the old one is closed and deleted.
+		assert_ok!(Dex::close_exchange(Origin::signed(1), 1));
+		assert_eq!(Dex::exchange_for_currency(1), None);
+		assert_ok!(Dex::create_exchange(Origin::signed(2), 1));
+		assert_eq!(Dex::exchange_by_currency(1), Some(2));
+	});
+}
+
+#[test]
+fn migration_indexes_the_oldest_exchange() {
+	new_test_ext().execute_with(|| {
+		before_exchange();
+
+		let exchange = Dex::exchanges(0).unwrap();
+		Exchanges::<Test>::insert(1, exchange.clone());
+		Exchanges::<Test>::insert(2, ExchangeInfo { currency: 5, ..exchange });
+		ExchangeByCurrency::remove(0);
+
+		Dex::on_runtime_upgrade();
+		assert_eq!(Dex::exchange_by_currency(0), Some(0));
+		assert_eq!(Dex::exchange_by_currency(5), Some(2));
+
+		// Later upgrades leave the index alone.
+		ExchangeByCurrency::remove(5);
+		Dex::on_runtime_upgrade();
+		assert_eq!(Dex::exchange_by_currency(5), None);
+	});
+}
+
+#[test]
+fn migration_moves_pools_keyed_by_token() {
+	use frame_support::storage::migration::{get_storage_value, put_storage_value};
+
+	new_test_ext().execute_with(|| {
+		before_exchange();
+
+		// A pool of the old layout: reserves keyed by token, shares minted as the pool token.
+		assert_ok!(Token::transfer_from(&1, &vault(), &0, 100));
+		assert_ok!(Currency::do_transfer_from(&1, &vault(), &0, 1000));
+		assert_ok!(Token::mint(&1, &0, 1000));
+		let mut key = sp_io::hashing::blake2_128(&0u64.encode()).to_vec();
+		key.extend(0u64.encode());
+		put_storage_value(b"DexModule", b"TotalSupplies", &key, 1000u128);
+		put_storage_value(b"DexModule", b"CurrencyReserves", &key, 1000u128);
+
+		Dex::on_runtime_upgrade();
+		assert_eq!(get_storage_value::<u128>(b"DexModule", b"TotalSupplies", &key), None);
+		assert_eq!(get_storage_value::<u128>(b"DexModule", b"CurrencyReserves", &key), None);
+		assert_eq!(Dex::total_supplies(0, 0), 1000);
+		assert_eq!(Dex::currency_reserves(0, 0), 1000);
+		assert_eq!(Dex::legacy_liquidity(0, 0), 1000);
+		let liquidity_token = Dex::liquidity_tokens(0, 0).unwrap();
+
+		assert_noop!(
+			Dex::convert_legacy_liquidity(Origin::signed(1), 0, 0, vec![(1, 1000)]),
+			sp_runtime::DispatchError::BadOrigin,
+		);
+		assert_noop!(
+			Dex::convert_legacy_liquidity(Origin::root(), 0, 0, vec![(1, 600), (1, 401)]),
+			Error::<Test>::LegacyLiquidityExceeded,
+		);
+		assert_ok!(Dex::convert_legacy_liquidity(Origin::root(), 0, 0, vec![(1, 1000)]));
+		assert_eq!(Token::balance_of(&1, &0), 900);
+		assert_eq!(Token::balance_of(&1, &liquidity_token), 1000);
+		assert_eq!(Dex::legacy_liquidity(0, 0), 0);
+
+		assert_ok!(Dex::remove_liquidity(Origin::signed(1), 0, 0, 1, [0].to_vec(), [500].to_vec(), [0].to_vec(), [0].to_vec(), 20, vec![]));
+		assert_eq!(Token::balance_of(&1, &0), 950);
+		assert_eq!(Dex::currency_reserves(0, 0), 500);
+
+		// The migration runs once.
+		put_storage_value(b"DexModule", b"TotalSupplies", &key, 1000u128);
+		Dex::on_runtime_upgrade();
+		assert_eq!(get_storage_value::<u128>(b"DexModule", b"TotalSupplies", &key), Some(1000));
+	});
+}
+
+fn last_dex_event() -> RawEvent<u64, u64, u128, u64, sp_core::H256> {
+	System::events()
+		.into_iter()
+		.filter_map(|r| if let TestEvent::dex(inner) = r.event { Some(inner) } else { None })
+		.last()
+		.unwrap()
+}
+
+#[test]
+fn liquidity_events_report_pool_state() {
+	new_test_ext().execute_with(|| {
+		before_exchange();
+		run_to_block(10);
+
+		assert_ok!(Dex::add_liquidity(Origin::signed(1), 0, 0, 1, [0].to_vec(), [100].to_vec(), [1000].to_vec(), 20, false));
+		assert!(System::events().into_iter().any(|r| r.event == TestEvent::dex(RawEvent::PoolCreated(0, 0, 2))));
+
+		let state = PoolState { token_reserve: 100, currency_reserve: 1000, total_liquidity: 1000 };
+		assert_eq!(
+			last_dex_event(),
+			RawEvent::LiquidityAdded(0, 1, 1, vec![0], vec![100], vec![1000], vec![1000], vec![state]),
+		);
+
+		assert_ok!(Dex::remove_liquidity(Origin::signed(1), 0, 0, 1, [0].to_vec(), [400].to_vec(), [0].to_vec(), [0].to_vec(), 20, vec![]));
+		let state = PoolState { token_reserve: 60, currency_reserve: 600, total_liquidity: 600 };
+		assert_eq!(
+			last_dex_event(),
+			RawEvent::LiquidityRemoved(0, 1, 1, vec![0], vec![40], vec![400], vec![400], vec![state]),
+		);
+	});
+}
+
+#[test]
+fn swap_events_report_fees_and_reserves() {
+	new_test_ext().execute_with(|| {
+		before_swap();
+
+		let expected = reference_amount_out(200, 1000, 100);
+		assert_ok!(Dex::swap_exact_currency_for_tokens(Origin::signed(2), 0, 0, [0].to_vec(), [200].to_vec(), [expected].to_vec(), 2, 20, None, None));
+
+		// 0.5% of 200 currency.
+		let state = PoolState { token_reserve: 100 - expected, currency_reserve: 1200, total_liquidity: 1000 };
+		assert_eq!(
+			last_dex_event(),
+			RawEvent::CurrencyToToken(0, 2, 2, vec![0], vec![expected], vec![200], vec![1], vec![state]),
+		);
+
+		let currency_out = reference_amount_out(50, 100 - expected, 1200);
+		assert_ok!(Dex::swap_exact_tokens_for_currency(Origin::signed(2), 0, 0, [0].to_vec(), [50].to_vec(), currency_out, 2, 20, None, None));
+
+		let state = PoolState { token_reserve: 150 - expected, currency_reserve: 1200 - currency_out, total_liquidity: 1000 };
+		assert_eq!(
+			last_dex_event(),
+			RawEvent::TokenToCurrency(0, 2, 2, vec![0], vec![50], vec![currency_out], vec![0], vec![state]),
+		);
+	});
+}
+
+#[test]
+fn swaps_respect_max_price_impact() {
+	new_test_ext().execute_with(|| {
+		before_swap();
+
+		// 100 currency for ~9 tokens moves the price by ~20%.
+		assert_noop!(
+			Dex::swap_exact_currency_for_tokens(Origin::signed(2), 0, 0, [0].to_vec(), [100].to_vec(), [1].to_vec(), 2, 20, Some(1000), None),
+			Error::<Test>::PriceImpactTooHigh,
+		);
+		assert_noop!(
+			Dex::swap_exact_tokens_for_tokens(Origin::signed(2), [(0, 0)].to_vec(), [1, 0].to_vec(), 100, 1, 2, 20, Some(1000), None),
+			Error::<Test>::PriceImpactTooHigh,
+		);
+		assert_ok!(Dex::swap_exact_currency_for_tokens(Origin::signed(2), 0, 0, [0].to_vec(), [100].to_vec(), [1].to_vec(), 2, 20, Some(2500), None));
+	});
+}
+
+#[test]
+fn circuit_breaker_bounds_price_moves_within_a_window() {
+	new_test_ext().execute_with(|| {
+		before_swap();
+
+		let breaker = CircuitBreaker { max_move: 1500, window: 10 };
+		assert_noop!(Dex::set_circuit_breaker(Origin::signed(2), 0, Some(breaker.clone())), Error::<Test>::NotExchangeOwner);
+		assert_ok!(Dex::set_circuit_breaker(Origin::signed(1), 0, Some(breaker.clone())));
+		assert_eq!(last_dex_event(), RawEvent::CircuitBreakerSet(0, Some(breaker)));
+
+		// ~9% from the window's reference price, then ~20% in total.
+		assert_ok!(Dex::swap_exact_currency_for_tokens(Origin::signed(2), 0, 0, [0].to_vec(), [50].to_vec(), [1].to_vec(), 2, 20, None, None));
+		assert_noop!(
+			Dex::swap_exact_currency_for_tokens(Origin::signed(2), 0, 0, [0].to_vec(), [50].to_vec(), [1].to_vec(), 2, 20, None, None),
+			Error::<Test>::CircuitBreakerTripped,
+		);
+
+		// A new window starts from the current price.
+		run_to_block(20);
+		assert_ok!(Dex::swap_exact_currency_for_tokens(Origin::signed(2), 0, 0, [0].to_vec(), [50].to_vec(), [1].to_vec(), 2, 20, None, None));
+
+		assert_ok!(Dex::set_circuit_breaker(Origin::root(), 0, None));
+		assert!(Dex::circuit_breakers(0).is_none());
+		assert_ok!(Dex::swap_exact_currency_for_tokens(Origin::signed(2), 0, 0, [0].to_vec(), [500].to_vec(), [1].to_vec(), 2, 20, None, None));
+	});
+}
+
+#[test]
+fn zap_currency_swaps_part_and_adds_the_rest() {
+	new_test_ext().execute_with(|| {
+		before_swap();
+
+		assert_noop!(
+			Dex::zap_currency(Origin::signed(2), 0, 0, 0, 200, 87, 2, 20),
+			Error::<Test>::InsufficientLiquidityMinted,
+		);
+
+		// 95 currency buys 8 tokens, leaving a 1095 / 92 pool. The 8 tokens bound the deposit
+		// to 86 shares, paid with 95 currency and 8 tokens.
+		assert_ok!(Dex::zap_currency(Origin::signed(2), 0, 0, 0, 200, 86, 2, 20));
+		assert_eq!(Token::balance_of(&2, &2), 86);
+		assert_eq!(Token::balance_of(&2, &1), 1000 - 95 - 95);
+		assert_eq!(Token::balance_of(&2, &0), 100);
+		assert_eq!(Dex::currency_reserves(0, 0), 1190);
+		assert_eq!(Dex::total_supplies(0, 0), 1086);
+	});
+}
+
+#[test]
+fn zap_tokens_swaps_part_and_adds_the_rest() {
+	new_test_ext().execute_with(|| {
+		before_swap();
+
+		// 9 tokens sell for 82 currency, leaving a 918 / 109 pool. The 82 currency bound the
+		// deposit to 89 shares, paid with 82 currency and 10 tokens.
+		assert_ok!(Dex::zap_tokens(Origin::signed(2), 0, 0, 0, 20, 89, 3, 20));
+		assert_eq!(Token::balance_of(&3, &2), 89);
+		assert_eq!(Token::balance_of(&2, &0), 100 - 9 - 10);
+		assert_eq!(Token::balance_of(&2, &1), 1000);
+		assert_eq!(Dex::currency_reserves(0, 0), 1000);
+		assert_eq!(Dex::total_supplies(0, 0), 1089);
+
+		assert_noop!(Dex::zap_tokens(Origin::signed(2), 0, 0, 3, 20, 0, 2, 20), Error::<Test>::InsufficientLiquidity);
+	});
+}
+
+#[test]
+fn first_deposit_respects_the_min_pool_size() {
+	new_test_ext().execute_with(|| {
+		before_exchange();
+		run_to_block(10);
+
+		assert_noop!(
+			Dex::add_liquidity(Origin::signed(1), 0, 0, 1, [0].to_vec(), [100].to_vec(), [999].to_vec(), 20, false),
+			Error::<Test>::InvalidCurrencyAmount,
+		);
+
+		assert_noop!(Dex::set_min_pool_size(Origin::signed(2), 0, Some(1500)), Error::<Test>::NotExchangeOwner);
+		assert_ok!(Dex::set_min_pool_size(Origin::signed(1), 0, Some(1500)));
+		assert_eq!(Dex::min_pool_size(0), 1500);
+		assert_noop!(
+			Dex::add_liquidity(Origin::signed(1), 0, 0, 1, [0].to_vec(), [100].to_vec(), [1000].to_vec(), 20, false),
+			Error::<Test>::InvalidCurrencyAmount,
+		);
+		assert_ok!(Dex::add_liquidity(Origin::signed(1), 0, 0, 1, [0].to_vec(), [100].to_vec(), [1500].to_vec(), 20, false));
+
+		assert_ok!(Dex::set_min_pool_size(Origin::root(), 0, None));
+		assert_eq!(Dex::min_pool_size(0), 1000);
+	});
+}
+
+#[test]
+fn first_deposit_locks_minimum_liquidity() {
+	new_test_ext().execute_with(|| {
+		set_minimum_liquidity(100);
+		before_exchange();
+		run_to_block(10);
+		let vault = vault();
+		let locked = Dex::locked_liquidity_account();
+
+		assert_ok!(Dex::add_liquidity(Origin::signed(1), 0, 0, 1, [0].to_vec(), [100].to_vec(), [1000].to_vec(), 20, false));
+		assert_eq!(Token::balance_of(&1, &2), 900);
+		assert_eq!(Token::balance_of(&locked, &2), 100);
+		assert_eq!(Dex::total_supplies(0, 0), 1000);
+
+		// The provider can only take out its own shares, the locked ones keep the pool priced.
+		assert!(Dex::remove_liquidity(Origin::signed(1), 0, 0, 1, [0].to_vec(), [1000].to_vec(), [0].to_vec(), [0].to_vec(), 20, vec![]).is_err());
+		assert_ok!(Dex::remove_liquidity(Origin::signed(1), 0, 0, 1, [0].to_vec(), [900].to_vec(), [0].to_vec(), [0].to_vec(), 20, vec![]));
+		assert_eq!(Dex::currency_reserves(0, 0), 100);
+		assert_eq!(Token::balance_of(&vault, &0), 10);
+
+		// Closing the exchange deletes it and moves the dust backing the locked shares out of
+		// the vault to the locked account, where nobody can spend it.
+		assert_ok!(Dex::close_exchange(Origin::signed(1), 0));
+		assert!(Dex::exchanges(0).is_none());
+		assert!(System::events().into_iter().any(|r| r.event == TestEvent::dex(RawEvent::LockedLiquiditySwept(0, 0, 10, 100))));
+		assert_eq!(Token::balance_of(&locked, &2), 0);
+		assert_eq!(Dex::total_supplies(0, 0), 0);
+		assert_eq!(Dex::currency_reserves(0, 0), 0);
+		assert_eq!(Token::balance_of(&vault, &0), 0);
+		assert_eq!(Token::balance_of(&vault, &1), 0);
+		assert_eq!(Token::balance_of(&locked, &0), 10);
+		assert_eq!(Token::balance_of(&locked, &1), 100);
+		assert_eq!(Token::balance_of(&1, &0), 990);
+		assert_eq!(Token::balance_of(&1, &1), 1900);
+	});
+}
+
+#[test]
+fn liquidity_positions_as_nfts() {
+	new_test_ext().execute_with(|| {
+		before_swap();
+		let custody = Dex::position_custody();
+
+		assert_ok!(Dex::add_liquidity(Origin::signed(2), 0, 0, 2, [0].to_vec(), [10].to_vec(), [100].to_vec(), 20, true));
+		assert_eq!(Token::balance_of(&2, &2), 0);
+		assert_eq!(Token::balance_of(&custody, &2), 100);
+
+		let collection_id = Dex::position_collection().unwrap();
+		assert_eq!(Nft::nft_owner(collection_id, 0), 2);
+		let position = Dex::positions(0).unwrap();
+		assert_eq!((position.exchange_id, position.token_id, position.shares), (0, 0, 100));
+		assert_eq!(position.entry_prices.0, FixedU128::saturating_from_integer(10));
+		assert_eq!(last_dex_event(), RawEvent::PositionOpened(0, 2, 0, 0, 100));
+
+		// Positions move like any NFT, and only the holder can redeem them.
+		assert_ok!(Nft::transfer(Origin::signed(2), 3, collection_id, 0));
+		assert_noop!(
+			Dex::remove_liquidity(Origin::signed(2), 0, 0, 2, [0].to_vec(), [100].to_vec(), [0].to_vec(), [0].to_vec(), 20, vec![0]),
+			nft::Error::<Test>::NotAssetOwner,
+		);
+		assert_noop!(
+			Dex::remove_liquidity(Origin::signed(3), 0, 0, 3, [0].to_vec(), [100].to_vec(), [0].to_vec(), [0].to_vec(), 20, vec![1]),
+			Error::<Test>::InvalidPosition,
+		);
+
+		assert_ok!(Dex::remove_liquidity(Origin::signed(3), 0, 0, 3, [0].to_vec(), [100].to_vec(), [0].to_vec(), [0].to_vec(), 20, vec![0]));
+		assert!(Dex::positions(0).is_none());
+		assert!(Nft::nft_assets(collection_id, 0).is_none());
+		assert_eq!(Token::balance_of(&custody, &2), 0);
+		assert_eq!(Token::balance_of(&3, &0), 10);
+		assert_eq!(Token::balance_of(&3, &1), 100);
+	});
+}
+
+#[test]
+fn each_position_gets_its_own_nft() {
+	new_test_ext().execute_with(|| {
+		before_swap();
+
+		assert_ok!(Dex::add_liquidity(Origin::signed(2), 0, 0, 2, [0].to_vec(), [10].to_vec(), [100].to_vec(), 20, true));
+		assert_ok!(Dex::add_liquidity(Origin::signed(1), 0, 0, 1, [0].to_vec(), [10].to_vec(), [100].to_vec(), 20, true));
+
+		let collection_id = Dex::position_collection().unwrap();
+		assert_eq!(Nft::next_asset_id(collection_id), 2);
+		assert_eq!(Nft::nft_owner(collection_id, 0), 2);
+		assert_eq!(Nft::nft_owner(collection_id, 1), 1);
+		assert_eq!(Dex::positions(1).unwrap().shares, 100);
+
+		assert_noop!(Nft::transfer(Origin::signed(2), 3, collection_id, 1), nft::Error::<Test>::NotAssetOwner);
+		assert_eq!(Nft::nft_assets(collection_id, 1).unwrap().owner, 1);
+	});
+}
+
+#[test]
+fn committed_swaps_execute_on_reveal() {
+	new_test_ext().execute_with(|| {
+		before_swap();
+
+		let salt = sp_core::H256::repeat_byte(1);
+		let expected = reference_amount_out(100, 1000, 100);
+		let commitment = Dex::swap_commitment_hash(&2, 0, 0, 1, 100, 0, expected, &3, salt);
+
+		// A copy of a pending commitment does not block its owner, nor can it be revealed.
+		assert_ok!(Dex::commit_swap(Origin::signed(1), commitment, 1, 10));
+		assert_ok!(Dex::commit_swap(Origin::signed(2), commitment, 1, 100));
+		assert_noop!(Dex::commit_swap(Origin::signed(2), commitment, 1, 100), Error::<Test>::CommitmentAlreadyExists);
+		assert_eq!(last_dex_event(), RawEvent::SwapCommitted(commitment, 2, 1, 100));
+		assert_eq!(Token::balance_of(&2, &1), 900);
+		assert_eq!(Token::balance_of(&Dex::commitment_escrow(), &1), 110);
+		assert_noop!(
+			Dex::reveal_swap(Origin::signed(2), commitment, 0, 0, 0, expected, 3, salt),
+			Error::<Test>::RevealTooEarly,
+		);
+
+		run_to_block(11);
+		assert_noop!(
+			Dex::reveal_swap(Origin::signed(1), commitment, 0, 0, 0, expected, 3, salt),
+			Error::<Test>::InvalidReveal,
+		);
+		assert_noop!(
+			Dex::reveal_swap(Origin::signed(3), commitment, 0, 0, 0, expected, 3, salt),
+			Error::<Test>::InvalidCommitment,
+		);
+		assert_noop!(
+			Dex::reveal_swap(Origin::signed(2), commitment, 0, 0, 0, expected - 1, 3, salt),
+			Error::<Test>::InvalidReveal,
+		);
+
+		assert_ok!(Dex::reveal_swap(Origin::signed(2), commitment, 0, 0, 0, expected, 3, salt));
+		assert!(Dex::swap_commitments(2, commitment).is_none());
+		assert_eq!(Token::balance_of(&Dex::commitment_escrow(), &1), 10);
+		assert_eq!(Token::balance_of(&2, &1), 900);
+		assert_eq!(Token::balance_of(&3, &0), expected);
+		assert_eq!(Dex::currency_reserves(0, 0), 1100);
+	});
+}
+
+#[test]
+fn unrevealed_commitments_expire_and_refund() {
+	new_test_ext().execute_with(|| {
+		before_swap();
+
+		let salt = sp_core::H256::repeat_byte(2);
+		let commitment = Dex::swap_commitment_hash(&2, 0, 0, 0, 10, 1, 1, &2, salt);
+		assert_noop!(Dex::commit_swap(Origin::signed(2), commitment, 0, 0), Error::<Test>::InsufficientInputAmount);
+		assert_ok!(Dex::commit_swap(Origin::signed(2), commitment, 0, 10));
+		assert_eq!(Token::balance_of(&2, &0), 90);
+
+		run_to_block(15);
+		assert!(Dex::swap_commitments(2, commitment).is_some());
+
+		run_to_block(16);
+		assert!(Dex::swap_commitments(2, commitment).is_none());
+		assert_eq!(Token::balance_of(&2, &0), 100);
+		assert_eq!(Token::balance_of(&Dex::commitment_escrow(), &0), 0);
+		assert_eq!(last_dex_event(), RawEvent::SwapCommitmentExpired(commitment, 2));
+		assert_noop!(
+			Dex::reveal_swap(Origin::signed(2), commitment, 0, 0, 1, 1, 2, salt),
+			Error::<Test>::InvalidCommitment,
+		);
+	});
+}
+
+#[test]
+fn batch_auctions_clear_at_one_price() {
+	new_test_ext().execute_with(|| {
+		before_swap();
+		let vault = vault();
+
+		assert_noop!(
+			Dex::submit_batch_swap(Origin::signed(2), 0, 0, 0, OrderSide::Buy, 100, 1, 3),
+			Error::<Test>::NotBatchAuction,
+		);
+		assert_noop!(Dex::set_batch_auction(Origin::signed(2), 0, true), Error::<Test>::NotExchangeOwner);
+		assert_ok!(Dex::set_batch_auction(Origin::signed(1), 0, true));
+		assert_noop!(
+			Dex::swap_exact_currency_for_tokens(Origin::signed(2), 0, 0, [0].to_vec(), [100].to_vec(), [1].to_vec(), 2, 20, None, None),
+			Error::<Test>::BatchAuctionOnly,
+		);
+
+		assert_ok!(Dex::submit_batch_swap(Origin::signed(2), 0, 0, 0, OrderSide::Buy, 100, 9, 3));
+		assert_ok!(Dex::submit_batch_swap(Origin::signed(2), 0, 0, 0, OrderSide::Sell, 5, 45, 4));
+		assert_ok!(Dex::submit_batch_swap(Origin::signed(2), 0, 0, 0, OrderSide::Sell, 5, 50, 4));
+		assert_eq!(Token::balance_of(&2, &0), 90);
+		assert_eq!(Token::balance_of(&2, &1), 900);
+
+		// All three clear at about 9.95, paying each sell 49. Without the last sell, which misses
+		// its minimum, buyers are in excess and the price
+		// is (1000 * 1000 + 995 * 100) / (995 * (100 + 5)), about 10.52 currency per token.
+		run_to_block(11);
+		assert!(Dex::batch_swaps(0, 0).is_empty());
+		assert_eq!(Token::balance_of(&3, &0), 9);
+		assert_eq!(Token::balance_of(&4, &1), 52);
+		assert_eq!(Token::balance_of(&2, &0), 95);
+		assert_eq!(Token::balance_of(&2, &1), 900);
+		assert_eq!(Dex::currency_reserves(0, 0), 1048);
+		assert_eq!(Token::balance_of(&vault, &0), 96);
+		assert!(System::events().into_iter().any(|r| r.event == TestEvent::dex(RawEvent::BatchSwapRefunded(0, 0, 2))));
+
+		// Queued swaps are refunded when the exchange closes.
+		assert_ok!(Dex::submit_batch_swap(Origin::signed(2), 0, 0, 0, OrderSide::Buy, 100, 1, 3));
+		assert_ok!(Dex::close_exchange(Origin::signed(1), 0));
+		assert_eq!(Token::balance_of(&2, &1), 900);
+	});
+}
+
+#[test]
+fn batch_auctions_refund_paused_pools_and_tripped_breakers() {
+	new_test_ext().execute_with(|| {
+		before_swap();
+		assert_ok!(Dex::set_batch_auction(Origin::signed(1), 0, true));
+
+		assert_ok!(Dex::submit_batch_swap(Origin::signed(2), 0, 0, 0, OrderSide::Buy, 100, 1, 3));
+		assert_ok!(Dex::submit_batch_swap(Origin::signed(2), 0, 0, 0, OrderSide::Buy, 100, 1, 3));
+		assert_ok!(Dex::submit_batch_swap(Origin::signed(2), 0, 0, 0, OrderSide::Buy, 100, 1, 3));
+		assert_noop!(
+			Dex::submit_batch_swap(Origin::signed(2), 0, 0, 0, OrderSide::Buy, 100, 1, 3),
+			Error::<Test>::BatchQueueFull,
+		);
+
+		// Pausing the exchange before the batch clears refunds it.
+		assert_ok!(Dex::set_trading_paused(Origin::signed(1), 0, true));
+		run_to_block(11);
+		assert!(Dex::batch_swaps(0, 0).is_empty());
+		assert_eq!(Token::balance_of(&2, &1), 1000);
+		assert_eq!(Dex::currency_reserves(0, 0), 1000);
+
+		// So does a clearing price beyond the circuit breaker.
+		assert_ok!(Dex::set_trading_paused(Origin::signed(1), 0, false));
+		assert_ok!(Dex::set_circuit_breaker(Origin::signed(1), 0, Some(CircuitBreaker { max_move: 100, window: 10 })));
+		assert_ok!(Dex::submit_batch_swap(Origin::signed(2), 0, 0, 0, OrderSide::Buy, 100, 1, 3));
+		run_to_block(12);
+		assert_eq!(Token::balance_of(&2, &1), 1000);
+		assert_eq!(Token::balance_of(&3, &0), 0);
+		assert_eq!(Dex::currency_reserves(0, 0), 1000);
+	});
+}
+
+#[test]
+fn quote_markets_follow_the_parent_settings() {
+	new_test_ext().execute_with(|| {
+		before_swap();
+		assert_ok!(Currency::create(Origin::signed(1), [0].to_vec()));
+		assert_ok!(Currency::mint(Origin::signed(1), 1, 2000, 1));
+		assert_ok!(Currency::mint(Origin::signed(1), 1, 1000, 2));
+		assert_ok!(Dex::add_quote_currency(Origin::signed(1), 0, 1));
+
+		assert_ok!(Dex::set_min_pool_size(Origin::signed(1), 0, Some(2000)));
+		assert_eq!(Dex::min_pool_size(1), 2000);
+		assert_noop!(
+			Dex::add_liquidity(Origin::signed(1), 0, 1, 1, [0].to_vec(), [100].to_vec(), [1000].to_vec(), 20, false),
+			Error::<Test>::InvalidCurrencyAmount,
+		);
+		assert_ok!(Dex::set_min_pool_size(Origin::signed(1), 0, None));
+		assert_ok!(Dex::add_liquidity(Origin::signed(1), 0, 1, 1, [0].to_vec(), [100].to_vec(), [1000].to_vec(), 20, false));
+
+		assert_ok!(Dex::set_circuit_breaker(Origin::signed(1), 0, Some(CircuitBreaker { max_move: 100, window: 10 })));
+		assert_noop!(
+			Dex::swap_exact_currency_for_tokens(Origin::signed(2), 0, 1, [0].to_vec(), [100].to_vec(), [1].to_vec(), 2, 20, None, None),
+			Error::<Test>::CircuitBreakerTripped,
+		);
+		assert_ok!(Dex::set_circuit_breaker(Origin::signed(1), 0, None));
+
+		assert_ok!(Dex::set_batch_auction(Origin::signed(1), 0, true));
+		assert_noop!(
+			Dex::swap_exact_currency_for_tokens(Origin::signed(2), 0, 1, [0].to_vec(), [100].to_vec(), [1].to_vec(), 2, 20, None, None),
+			Error::<Test>::BatchAuctionOnly,
+		);
+		assert_ok!(Dex::submit_batch_swap(Origin::signed(2), 0, 1, 0, OrderSide::Buy, 100, 1, 2));
+
+		// The pools of the exchange and its market share the per block limit.
+		for _ in 0..3 {
+			assert_ok!(Dex::submit_batch_swap(Origin::signed(2), 0, 0, 0, OrderSide::Buy, 100, 1, 2));
+		}
+		assert_noop!(
+			Dex::submit_batch_swap(Origin::signed(2), 0, 1, 0, OrderSide::Buy, 100, 1, 2),
+			Error::<Test>::BatchQueueFull,
+		);
+		run_to_block(11);
+		assert!(Dex::batch_swaps(1, 0).is_empty());
+		assert_eq!(Dex::batch_swaps_queued(), 0);
+		assert_ok!(Dex::submit_batch_swap(Origin::signed(2), 0, 1, 0, OrderSide::Buy, 100, 1, 2));
+	});
+}
+
+#[test]
+fn exchanges_quote_tokens_in_several_currencies() {
+	new_test_ext().execute_with(|| {
+		before_swap();
+
+		// Currency 1 is token 3.
+		assert_ok!(Currency::create(Origin::signed(1), [0].to_vec()));
+		assert_ok!(Currency::mint(Origin::signed(1), 1, 2000, 1));
+		assert_ok!(Currency::mint(Origin::signed(1), 1, 1000, 2));
+
+		assert_noop!(Dex::add_quote_currency(Origin::signed(2), 0, 1), Error::<Test>::NotExchangeOwner);
+		assert_noop!(Dex::add_quote_currency(Origin::signed(1), 0, 2), currency::Error::<Test>::InvalidCurrencyId);
+		assert_noop!(Dex::add_quote_currency(Origin::signed(1), 0, 0), Error::<Test>::QuoteCurrencyAlreadyAdded);
+		assert_ok!(Dex::add_quote_currency(Origin::signed(1), 0, 1));
+		assert_eq!(last_dex_event(), RawEvent::QuoteCurrencyAdded(0, 1, 1));
+		assert_noop!(Dex::add_quote_currency(Origin::signed(1), 0, 1), Error::<Test>::QuoteCurrencyAlreadyAdded);
+		assert_eq!(Dex::market_id(0, 1), Ok(1));
+		assert_eq!(Dex::exchange_by_currency(1), None);
+
+		// Token 0 now has a pool priced in each currency.
+		assert_ok!(Dex::add_liquidity(Origin::signed(1), 0, 1, 1, [0].to_vec(), [100].to_vec(), [1000].to_vec(), 20, false));
+		assert_eq!(Dex::currency_reserves(1, 0), 1000);
+
+		let expected = reference_amount_out(100, 1000, 100);
+		assert_ok!(Dex::swap_exact_currency_for_tokens(Origin::signed(2), 0, 1, [0].to_vec(), [100].to_vec(), [expected].to_vec(), 2, 20, None, None));
+		assert_eq!(Token::balance_of(&2, &3), 900);
+		assert_eq!(Token::balance_of(&2, &1), 1000);
+		assert_eq!(Dex::currency_reserves(1, 0), 1100);
+		assert_eq!(Dex::currency_reserves(0, 0), 1000);
+		assert_noop!(
+			Dex::swap_exact_currency_for_tokens(Origin::signed(2), 0, 2, [0].to_vec(), [100].to_vec(), [1].to_vec(), 2, 20, None, None),
+			Error::<Test>::InvalidQuoteCurrency,
+		);
+
+		// Pausing or closing the exchange applies to its markets.
+		assert_ok!(Dex::set_trading_paused(Origin::signed(1), 0, true));
+		assert_noop!(
+			Dex::swap_exact_currency_for_tokens(Origin::signed(2), 0, 1, [0].to_vec(), [100].to_vec(), [1].to_vec(), 2, 20, None, None),
+			Error::<Test>::TradingPaused,
+		);
+		assert_ok!(Dex::close_exchange(Origin::signed(1), 0));
+		assert!(Dex::exchange_status(1).closed);
+
+		assert_ok!(Dex::remove_liquidity(Origin::signed(1), 0, 0, 1, [0].to_vec(), [1000].to_vec(), [0].to_vec(), [0].to_vec(), 20, vec![]));
+		assert!(Dex::exchanges(0).is_some());
+		assert_ok!(Dex::remove_liquidity(Origin::signed(1), 0, 1, 1, [0].to_vec(), [1000].to_vec(), [0].to_vec(), [0].to_vec(), 20, vec![]));
+		assert!(Dex::exchanges(1).is_none());
+		assert!(Dex::exchanges(0).is_none());
+		assert_eq!(Dex::quote_markets(0, 1), None);
+		assert_eq!(Dex::market_parents(1), None);
+	});
+}
+
+#[test]
+fn stable_swap_pools_trade_near_one_to_one() {
+	new_test_ext().execute_with(|| {
+		before_exchange();
+		run_to_block(10);
+
+		let curve = PoolCurve::StableSwap { amplification: 100 };
+		assert_noop!(Dex::set_pool_curve(Origin::signed(2), 0, 0, 0, curve), Error::<Test>::NotExchangeOwner);
+		assert_noop!(
+			Dex::set_pool_curve(Origin::signed(1), 0, 0, 0, PoolCurve::StableSwap { amplification: 0 }),
+			Error::<Test>::InvalidAmplification,
+		);
+		assert_ok!(Dex::set_pool_curve(Origin::signed(1), 0, 0, 0, curve));
+		assert_eq!(last_dex_event(), RawEvent::PoolCurveSet(0, 0, curve));
+		assert_noop!(Dex::set_batch_auction(Origin::signed(1), 0, true), Error::<Test>::CurveNotSupported);
+
+		assert_ok!(Dex::add_liquidity(Origin::signed(1), 0, 0, 1, [0].to_vec(), [1000].to_vec(), [1000].to_vec(), 20, false));
+		assert_noop!(Dex::set_pool_curve(Origin::signed(1), 0, 0, 0, PoolCurve::ConstantProduct), Error::<Test>::PoolNotEmpty);
+		assert_ok!(Currency::mint(Origin::signed(1), 0, 1000, 2));
+
+		// The constant product would pay 90 tokens for 100 currency and charge 53 for 50 tokens.
+		assert_eq!(Dex::quote_exact_in(vec![(0, 0)], vec![1, 0], 100), Some(98));
+		assert_eq!(Dex::quote_exact_out(vec![(0, 0)], vec![1, 0], 50), Some(52));
+
+		assert_noop!(
+			Dex::swap_exact_currency_for_tokens(Origin::signed(2), 0, 0, [0].to_vec(), [100].to_vec(), [99].to_vec(), 2, 20, None, None),
+			Error::<Test>::InsufficientOutputAmount,
+		);
+		assert_ok!(Dex::swap_exact_currency_for_tokens(Origin::signed(2), 0, 0, [0].to_vec(), [100].to_vec(), [98].to_vec(), 2, 20, None, None));
+		assert_eq!(Token::balance_of(&2, &0), 98);
+		assert_eq!(Dex::currency_reserves(0, 0), 1100);
+
+		// The price is still close to one.
+		let (currency_per_token, _) = <Dex as PriceOracle<_, _>>::twap(0, 0, 0).unwrap();
+		assert!(currency_per_token > FixedU128::saturating_from_rational(1, 1) && currency_per_token < FixedU128::saturating_from_rational(11, 10));
+	});
+}
+
+#[test]
+fn referrers_earn_a_share_of_swap_fees() {
+	new_test_ext().execute_with(|| {
+		before_swap();
+		let vault = vault();
+
+		assert_noop!(
+			Dex::swap_exact_currency_for_tokens(Origin::signed(2), 0, 0, [0].to_vec(), [1000].to_vec(), [1].to_vec(), 2, 20, None, Some(2)),
+			Error::<Test>::InvalidReferrer,
+		);
+
+		// Half of the 5 currency fee, rounded down, leaves the pool for the referrer.
+		let expected = reference_amount_out(1000, 1000, 100);
+		assert_ok!(Dex::swap_exact_currency_for_tokens(Origin::signed(2), 0, 0, [0].to_vec(), [1000].to_vec(), [1].to_vec(), 2, 20, None, Some(3)));
+		assert_eq!(Token::balance_of(&2, &0), 100 + expected);
+		assert_eq!(Token::balance_of(&3, &1), 2);
+		assert_eq!(Dex::referral_earnings(3, 1), 2);
+		assert_eq!(Dex::currency_reserves(0, 0), 1998);
+		assert!(System::events().into_iter().any(|r| r.event == TestEvent::dex(RawEvent::ReferralPaid(3, 1, 2))));
+
+		// Routes pay in the token sold to their first pool.
+		Tao::mint(Origin::signed(1), 0, 0, 400, 2);
+		assert_ok!(Dex::swap_exact_tokens_for_tokens(Origin::signed(2), [(0, 0)].to_vec(), [0, 1].to_vec(), 400, 1, 2, 20, None, Some(3)));
+		assert_eq!(Token::balance_of(&3, &0), 1);
+		assert_eq!(Token::balance_of(&vault, &0), 100 - expected + 400 - 1);
+
+		let mut earned = Dex::referral_earnings_of(&3);
+		earned.sort();
+		assert_eq!(earned, vec![(0, 1), (1, 2)]);
+	});
+}
+
+#[test]
+fn referral_payouts_count_towards_price_impact() {
+	new_test_ext().execute_with(|| {
+		before_swap();
+
+		// 1000 currency for 49 tokens moves the price by 292.15%, but the referral of 2
+		// currency leaves the pool with 1998 currency and 51 tokens, a move of 291.76%.
+		assert_noop!(
+			Dex::swap_exact_currency_for_tokens(Origin::signed(2), 0, 0, [0].to_vec(), [1000].to_vec(), [1].to_vec(), 2, 20, Some(29_200), None),
+			Error::<Test>::PriceImpactTooHigh,
+		);
+		assert_ok!(Dex::swap_exact_currency_for_tokens(Origin::signed(2), 0, 0, [0].to_vec(), [1000].to_vec(), [1].to_vec(), 2, 20, Some(29_200), Some(3)));
+		assert_eq!(Dex::currency_reserves(0, 0), 1998);
+		assert_eq!(Token::balance_of(&vault(), &0), 51);
+	});
+}
+
+#[test]
+fn pools_track_volume_fees_and_impermanent_loss() {
+	new_test_ext().execute_with(|| {
+		before_swap();
+
+		let tokens_bought = reference_amount_out(200, 1000, 100);
+		assert_ok!(Dex::swap_exact_currency_for_tokens(Origin::signed(2), 0, 0, [0].to_vec(), [200].to_vec(), [1].to_vec(), 2, 20, None, None));
+		run_to_block(15);
+		let currency_received = reference_amount_out(10, 100 - tokens_bought, 1200);
+		assert_ok!(Dex::swap_exact_tokens_for_currency(Origin::signed(2), 0, 0, [0].to_vec(), [10].to_vec(), 1, 2, 20, None, None));
+
+		// The buy paid a fee of 1, the sell one too small to count.
+		assert_eq!(Dex::pool_volume(0, 0, 3), Some((currency_received, 0)));
+		assert_eq!(Dex::pool_volume(0, 0, 20), Some((200 + currency_received, 1)));
+
+		// A fee of 1 on 2145 currency of reserves over 20 blocks of 4 seconds.
+		assert_eq!(Dex::fee_apr(0, 0, 20), Some(1_837_762));
+
+		// Against the 1000 currency and 100 tokens the first shares were minted for.
+		assert_eq!(Dex::impermanent_loss(0, 0, (1000, 100, 1000)), Some(21));
+		assert_eq!(Dex::impermanent_loss(0, 0, (1000, 100, 0)), None);
+
+		// Windows past the observations kept have no answer.
+		for block in 16..19 {
+			run_to_block(block);
+			assert_ok!(Dex::swap_exact_currency_for_tokens(Origin::signed(2), 0, 0, [0].to_vec(), [50].to_vec(), [1].to_vec(), 2, 20, None, None));
+		}
+		assert_eq!(Dex::pool_volume(0, 0, 3), Some((150, 0)));
+		assert_eq!(Dex::pool_volume(0, 0, 20), None);
+	});
+}
@@ -0,0 +1,321 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+//! Liquidity mining for DEX pools.
+//!
+//! Governance sets a per-block reward, paid in a currency, for each `(ExchangeId, TokenId)`
+//! pool. Liquidity providers stake the pool's LP token and earn the reward pro rata to their
+//! stake. Rewards are tracked MasterChef style: a farm accumulates the reward earned by one
+//! staked share, and each stake remembers the part of that accumulator already accounted for.
+
+use codec::{Encode, Decode};
+use frame_support::{
+	decl_module, decl_storage, decl_error, decl_event, ensure, StorageDoubleMap,
+	traits::{EnsureOrigin, Get}, transactional,
+};
+use frame_system::{self as system, ensure_signed};
+use sp_runtime::{
+	ModuleId, FixedPointNumber, FixedU128,
+	traits::{AccountIdConversion, CheckedAdd, CheckedSub, Saturating, UniqueSaturatedInto, Zero},
+	DispatchError, DispatchResult, RuntimeDebug,
+};
+use primitives::CurrencyId;
+use dex::ExchangeId;
+
+#[cfg(test)]
+mod mock;
+
+#[cfg(test)]
+mod tests;
+
+/// The pallet's configuration trait.
+pub trait Trait: system::Trait + dex::Trait {
+	type Event: From<Event<Self>> + Into<<Self as system::Trait>::Event>;
+	/// Account holding staked LP tokens and the reward pot.
+	type ModuleId: Get<ModuleId>;
+	/// Origin allowed to set reward rates.
+	type GovernanceOrigin: EnsureOrigin<Self::Origin>;
+}
+
+/// Reward schedule and accounting of a pool.
+#[derive(Encode, Decode, Clone, Default, Eq, PartialEq, RuntimeDebug)]
+pub struct FarmInfo<TokenBalance, BlockNumber> {
+	pub reward_currency: CurrencyId,
+	pub reward_per_block: TokenBalance,
+	pub total_staked: TokenBalance,
+	/// Reward earned by one staked LP token since the farm was created.
+	pub reward_per_share: FixedU128,
+	pub last_reward_block: BlockNumber,
+}
+
+#[derive(Encode, Decode, Clone, Default, Eq, PartialEq, RuntimeDebug)]
+pub struct StakeInfo<TokenBalance> {
+	pub amount: TokenBalance,
+	/// Reward already accounted for, `amount * reward_per_share` as of the last update.
+	pub reward_debt: u128,
+}
+
+pub type FarmInfoOf<T> = FarmInfo<<T as token::Trait>::TokenBalance, <T as system::Trait>::BlockNumber>;
+
+// This pallet's storage items.
+decl_storage! {
+	trait Store for Module<T: Trait> as FarmingModule {
+		pub Farms get(fn farms):
+			double_map hasher(blake2_128_concat) ExchangeId, hasher(blake2_128_concat) T::TokenId => Option<FarmInfoOf<T>>;
+		pub Stakes get(fn stakes):
+			double_map hasher(blake2_128_concat) (ExchangeId, T::TokenId), hasher(blake2_128_concat) T::AccountId
+			=> StakeInfo<T::TokenBalance>;
+	}
+}
+
+// The pallet's events
+decl_event!(
+	pub enum Event<T> where
+		AccountId = <T as system::Trait>::AccountId,
+		TokenId = <T as token::Trait>::TokenId,
+		TokenBalance = <T as token::Trait>::TokenBalance,
+	{
+		RewardRateSet(ExchangeId, TokenId, CurrencyId, TokenBalance),
+		RewardsFunded(AccountId, CurrencyId, TokenBalance),
+		Staked(AccountId, ExchangeId, TokenId, TokenBalance),
+		Unstaked(AccountId, ExchangeId, TokenId, TokenBalance),
+		RewardPaid(AccountId, ExchangeId, TokenId, TokenBalance),
+	}
+);
+
+// The pallet's errors
+decl_error! {
+	pub enum Error for Module<T: Trait> {
+		Overflow,
+		InvalidExchangeId,
+		InvalidFarm,
+		RewardCurrencyMismatch,
+		InvalidAmount,
+		InsufficientStake,
+	}
+}
+
+// The pallet's dispatchable functions.
+decl_module! {
+	pub struct Module<T: Trait> for enum Call where origin: T::Origin {
+		type Error = Error<T>;
+
+		fn deposit_event() = default;
+
+		/// Set the reward paid per block to the stakers of a pool, creating its farm if needed.
+		///
+		/// A farm keeps the reward currency it was created with.
+		#[weight = 0]
+		pub fn set_reward_rate(
+			origin,
+			exchange_id: ExchangeId,
+			token_id: T::TokenId,
+			reward_currency: CurrencyId,
+			reward_per_block: T::TokenBalance,
+		) -> DispatchResult {
+			T::GovernanceOrigin::ensure_origin(origin)?;
+
+			dex::Module::<T>::exchanges(exchange_id).ok_or(Error::<T>::InvalidExchangeId)?;
+			currency::Module::<T>::get_currency_token(&reward_currency)?;
+
+			let mut farm = match Self::farms(exchange_id, token_id) {
+				Some(farm) => {
+					ensure!(farm.reward_currency == reward_currency, Error::<T>::RewardCurrencyMismatch);
+					Self::accrue(farm)
+				}
+				None => FarmInfo {
+					reward_currency,
+					last_reward_block: system::Module::<T>::block_number(),
+					..Default::default()
+				},
+			};
+			farm.reward_per_block = reward_per_block;
+			Farms::<T>::insert(exchange_id, token_id, farm);
+
+			Self::deposit_event(RawEvent::RewardRateSet(exchange_id, token_id, reward_currency, reward_per_block));
+
+			Ok(())
+		}
+
+		/// Add `amount` of a currency to the reward pot.
+		#[weight = 0]
+		pub fn fund_rewards(origin, currency_id: CurrencyId, amount: T::TokenBalance) -> DispatchResult {
+			let who = ensure_signed(origin)?;
+
+			currency::Module::<T>::do_transfer_from(&who, &Self::account_id(), &currency_id, amount)?;
+
+			Self::deposit_event(RawEvent::RewardsFunded(who, currency_id, amount));
+
+			Ok(())
+		}
+
+		/// Stake LP tokens of a pool, paying out the reward accrued so far.
+		#[weight = 0]
+		#[transactional]
+		pub fn stake(origin, exchange_id: ExchangeId, token_id: T::TokenId, amount: T::TokenBalance) -> DispatchResult {
+			let who = ensure_signed(origin)?;
+			ensure!(amount > Zero::zero(), Error::<T>::InvalidAmount);
+
+			let farm = Self::farms(exchange_id, token_id).ok_or(Error::<T>::InvalidFarm)?;
+			let liquidity_token = dex::Module::<T>::liquidity_tokens(exchange_id, token_id).ok_or(Error::<T>::InvalidFarm)?;
+
+			let mut farm = Self::accrue(farm);
+			let mut stake = Self::stakes((exchange_id, token_id), &who);
+			Self::pay_reward(&who, exchange_id, token_id, &farm, &stake)?;
+
+			token::Module::<T>::transfer_from(&who, &Self::account_id(), &liquidity_token, amount)?;
+
+			stake.amount = stake.amount.checked_add(&amount).ok_or(Error::<T>::Overflow)?;
+			stake.reward_debt = Self::accumulated_reward(&farm, stake.amount);
+			farm.total_staked = farm.total_staked.checked_add(&amount).ok_or(Error::<T>::Overflow)?;
+
+			Stakes::<T>::insert((exchange_id, token_id), &who, stake);
+			Farms::<T>::insert(exchange_id, token_id, farm);
+
+			Self::deposit_event(RawEvent::Staked(who, exchange_id, token_id, amount));
+
+			Ok(())
+		}
+
+		/// Withdraw staked LP tokens of a pool, paying out the reward accrued so far.
+		#[weight = 0]
+		#[transactional]
+		pub fn unstake(origin, exchange_id: ExchangeId, token_id: T::TokenId, amount: T::TokenBalance) -> DispatchResult {
+			let who = ensure_signed(origin)?;
+			ensure!(amount > Zero::zero(), Error::<T>::InvalidAmount);
+
+			let farm = Self::farms(exchange_id, token_id).ok_or(Error::<T>::InvalidFarm)?;
+			let liquidity_token = dex::Module::<T>::liquidity_tokens(exchange_id, token_id).ok_or(Error::<T>::InvalidFarm)?;
+
+			let mut farm = Self::accrue(farm);
+			let mut stake = Self::stakes((exchange_id, token_id), &who);
+			Self::pay_reward(&who, exchange_id, token_id, &farm, &stake)?;
+
+			stake.amount = stake.amount.checked_sub(&amount).ok_or(Error::<T>::InsufficientStake)?;
+			stake.reward_debt = Self::accumulated_reward(&farm, stake.amount);
+			farm.total_staked = farm.total_staked.checked_sub(&amount).ok_or(Error::<T>::InsufficientStake)?;
+
+			token::Module::<T>::transfer_from(&Self::account_id(), &who, &liquidity_token, amount)?;
+
+			Self::store_stake(exchange_id, token_id, &who, stake);
+			Farms::<T>::insert(exchange_id, token_id, farm);
+
+			Self::deposit_event(RawEvent::Unstaked(who, exchange_id, token_id, amount));
+
+			Ok(())
+		}
+
+		/// Pay out the reward accrued on a stake.
+		#[weight = 0]
+		#[transactional]
+		pub fn claim(origin, exchange_id: ExchangeId, token_id: T::TokenId) -> DispatchResult {
+			let who = ensure_signed(origin)?;
+
+			let farm = Self::farms(exchange_id, token_id).ok_or(Error::<T>::InvalidFarm)?;
+
+			let farm = Self::accrue(farm);
+			let mut stake = Self::stakes((exchange_id, token_id), &who);
+			Self::pay_reward(&who, exchange_id, token_id, &farm, &stake)?;
+
+			stake.reward_debt = Self::accumulated_reward(&farm, stake.amount);
+
+			Self::store_stake(exchange_id, token_id, &who, stake);
+			Farms::<T>::insert(exchange_id, token_id, farm);
+
+			Ok(())
+		}
+
+		/// Withdraw the whole stake without paying out rewards, for when the pot runs dry.
+		#[weight = 0]
+		#[transactional]
+		pub fn emergency_unstake(origin, exchange_id: ExchangeId, token_id: T::TokenId) -> DispatchResult {
+			let who = ensure_signed(origin)?;
+
+			let farm = Self::farms(exchange_id, token_id).ok_or(Error::<T>::InvalidFarm)?;
+			let liquidity_token = dex::Module::<T>::liquidity_tokens(exchange_id, token_id).ok_or(Error::<T>::InvalidFarm)?;
+
+			let mut farm = Self::accrue(farm);
+			let stake = Stakes::<T>::take((exchange_id, token_id), &who);
+			ensure!(stake.amount > Zero::zero(), Error::<T>::InsufficientStake);
+
+			farm.total_staked = farm.total_staked.checked_sub(&stake.amount).ok_or(Error::<T>::InsufficientStake)?;
+			token::Module::<T>::transfer_from(&Self::account_id(), &who, &liquidity_token, stake.amount)?;
+
+			Farms::<T>::insert(exchange_id, token_id, farm);
+
+			Self::deposit_event(RawEvent::Unstaked(who, exchange_id, token_id, stake.amount));
+
+			Ok(())
+		}
+	}
+}
+
+impl<T: Trait> Module<T> {
+	pub fn account_id() -> T::AccountId {
+		<T as Trait>::ModuleId::get().into_account()
+	}
+
+	/// Reward accrued on the stake of `who` that has not been paid out yet.
+	pub fn pending_reward(exchange_id: ExchangeId, token_id: T::TokenId, who: &T::AccountId) -> T::TokenBalance {
+		let farm = match Self::farms(exchange_id, token_id) {
+			Some(farm) => Self::accrue(farm),
+			None => return Zero::zero(),
+		};
+		let stake = Self::stakes((exchange_id, token_id), who);
+
+		Self::accumulated_reward(&farm, stake.amount).saturating_sub(stake.reward_debt).into()
+	}
+
+	/// Brings `reward_per_share` of a farm up to the current block.
+	fn accrue(mut farm: FarmInfoOf<T>) -> FarmInfoOf<T> {
+		let now = system::Module::<T>::block_number();
+		if now <= farm.last_reward_block {
+			return farm;
+		}
+
+		if !farm.total_staked.is_zero() {
+			let blocks: u128 = (now - farm.last_reward_block).unique_saturated_into();
+			let reward_per_block: u128 = farm.reward_per_block.unique_saturated_into();
+			let total_staked: u128 = farm.total_staked.unique_saturated_into();
+
+			let reward = reward_per_block.saturating_mul(blocks);
+			farm.reward_per_share = farm.reward_per_share
+				.saturating_add(FixedU128::saturating_from_rational(reward, total_staked));
+		}
+		farm.last_reward_block = now;
+
+		farm
+	}
+
+	/// Reward earned by `amount` staked LP tokens since the farm was created, rounded down.
+	fn accumulated_reward(farm: &FarmInfoOf<T>, amount: T::TokenBalance) -> u128 {
+		let amount: u128 = amount.unique_saturated_into();
+		farm.reward_per_share.saturating_mul_int(amount)
+	}
+
+	fn pay_reward(
+		who: &T::AccountId,
+		exchange_id: ExchangeId,
+		token_id: T::TokenId,
+		farm: &FarmInfoOf<T>,
+		stake: &StakeInfo<T::TokenBalance>,
+	) -> Result<T::TokenBalance, DispatchError> {
+		let reward: T::TokenBalance = Self::accumulated_reward(farm, stake.amount)
+			.saturating_sub(stake.reward_debt)
+			.into();
+
+		if !reward.is_zero() {
+			currency::Module::<T>::do_transfer_from(&Self::account_id(), who, &farm.reward_currency, reward)?;
+			Self::deposit_event(RawEvent::RewardPaid(who.clone(), exchange_id, token_id, reward));
+		}
+
+		Ok(reward)
+	}
+
+	fn store_stake(exchange_id: ExchangeId, token_id: T::TokenId, who: &T::AccountId, stake: StakeInfo<T::TokenBalance>) {
+		if stake.amount.is_zero() {
+			Stakes::<T>::remove((exchange_id, token_id), who);
+		} else {
+			Stakes::<T>::insert((exchange_id, token_id), who, stake);
+		}
+	}
+}
@@ -0,0 +1,181 @@
+use crate::{Module, Trait};
+
+use sp_core::H256;
+use frame_support::{impl_outer_origin, impl_outer_event, impl_outer_dispatch, parameter_types, weights::Weight, traits::OnFinalize, traits::OnInitialize};
+use sp_runtime::{
+    traits::{BlakeTwo256, IdentityLookup}, testing::Header, Perbill, ModuleId,
+};
+use frame_system::{self as system, EnsureRoot};
+
+impl_outer_origin! {
+    pub enum Origin for Test where system = frame_system {}
+}
+
+mod farming {
+    pub use super::super::*;
+}
+
+impl_outer_dispatch! {
+    pub enum Call for Test where origin: Origin {
+        frame_system::System,
+        dex::Dex,
+        farming::Farming,
+    }
+}
+
+impl_outer_event! {
+    pub enum TestEvent for Test {
+        system<T>,
+        pallet_balances<T>,
+        farming<T>,
+        dex<T>,
+        token<T>,
+        currency<T>,
+        tao<T>,
+        nft<T>,
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Test;
+parameter_types! {
+    pub const BlockHashCount: u64 = 250;
+    pub const MaximumBlockWeight: Weight = 1024;
+    pub const MaximumBlockLength: u32 = 2 * 1024;
+    pub const AvailableBlockRatio: Perbill = Perbill::from_percent(75);
+
+    pub const ExistentialDeposit: u64 = 1;
+}
+impl system::Trait for Test {
+    type Origin = Origin;
+    type Call = ();
+    type Index = u64;
+    type BlockNumber = u64;
+    type Hash = H256;
+    type Hashing = BlakeTwo256;
+    type AccountId = u64;
+    type Lookup = IdentityLookup<Self::AccountId>;
+    type Header = Header;
+    type Event = TestEvent;
+    type BlockHashCount = BlockHashCount;
+    type MaximumBlockWeight = MaximumBlockWeight;
+    type DbWeight = ();
+    type BlockExecutionWeight = ();
+    type ExtrinsicBaseWeight = ();
+    type MaximumExtrinsicWeight = MaximumBlockWeight;
+    type MaximumBlockLength = MaximumBlockLength;
+    type AvailableBlockRatio = AvailableBlockRatio;
+    type Version = ();
+    type SystemWeightInfo = ();
+    type PalletInfo = ();
+    type AccountData = pallet_balances::AccountData<u64>;
+    type OnNewAccount = ();
+    type OnKilledAccount = ();
+    type BaseCallFilter = ();
+}
+
+impl pallet_balances::Trait for Test {
+    type Balance = u64;
+    type MaxLocks = ();
+    type Event = TestEvent;
+    type DustRemoval = ();
+    type ExistentialDeposit = ExistentialDeposit;
+    type AccountStore = system::Module<Test>;
+    type WeightInfo = ();
+}
+
+parameter_types! {
+    pub const MinimumPeriod: u64 = 2000;
+}
+
+impl pallet_timestamp::Trait for Test {
+    type Moment = u64;
+    type OnTimestampSet = ();
+    type MinimumPeriod = MinimumPeriod;
+    type WeightInfo = ();
+}
+
+impl token::Trait for Test {
+    type Event = TestEvent;
+    type TokenBalance = u128;
+    type TokenId = u64;
+}
+
+parameter_types! {
+    pub const CurrencyModuleId: ModuleId = ModuleId(*b"spr/curr");
+}
+
+impl currency::Trait for Test {
+    type Event = TestEvent;
+    type ModuleId = CurrencyModuleId;
+}
+
+impl tao::Trait for Test {
+    type Event = TestEvent;
+    type TaoId = u64;
+}
+
+impl nft::Trait for Test {
+    type Event = TestEvent;
+}
+
+parameter_types! {
+    pub const DexModuleId: ModuleId = ModuleId(*b"spr/dexm");
+    pub const MaxPriceObservations: u32 = 4;
+    pub const MaxOrdersPerPool: u32 = 4;
+    pub const OrderDeposit: u64 = 5;
+    pub const MaxBatchSwapsPerPool: u32 = 3;
+    pub const MaxBatchSwapsPerBlock: u32 = 4;
+    pub const MinimumLiquidity: u128 = 0;
+    pub const DefaultMinPoolSize: u128 = 1000;
+    pub const RevealWindow: u64 = 5;
+    pub const ReferralFeeShare: u32 = 5_000;
+}
+impl dex::Trait for Test {
+    type Event = TestEvent;
+    type ModuleId = DexModuleId;
+    type MaxPriceObservations = MaxPriceObservations;
+    type Call = Call;
+    type MaxOrdersPerPool = MaxOrdersPerPool;
+    type OrderDeposit = OrderDeposit;
+    type MaxBatchSwapsPerPool = MaxBatchSwapsPerPool;
+    type MaxBatchSwapsPerBlock = MaxBatchSwapsPerBlock;
+    type MinimumLiquidity = MinimumLiquidity;
+    type DefaultMinPoolSize = DefaultMinPoolSize;
+    type RevealWindow = RevealWindow;
+    type DepositCurrency = pallet_balances::Module<Test>;
+    type ReferralFeeShare = ReferralFeeShare;
+}
+
+parameter_types! {
+    pub const FarmingModuleId: ModuleId = ModuleId(*b"spr/farm");
+}
+impl Trait for Test {
+    type Event = TestEvent;
+    type ModuleId = FarmingModuleId;
+    type GovernanceOrigin = EnsureRoot<u64>;
+}
+
+pub type Farming = Module<Test>;
+pub type Dex = dex::Module<Test>;
+pub type Currency = currency::Module<Test>;
+pub type Token = token::Module<Test>;
+pub type Tao = tao::Module<Test>;
+pub type System = frame_system::Module<Test>;
+
+pub fn new_test_ext() -> sp_io::TestExternalities {
+    let t = system::GenesisConfig::default()
+        .build_storage::<Test>()
+        .unwrap();
+    let mut ext: sp_io::TestExternalities = t.into();
+    ext.execute_with(|| System::set_block_number(1));
+    ext
+}
+
+pub fn run_to_block(n: u64) {
+    while System::block_number() < n {
+        System::on_finalize(System::block_number());
+        System::set_block_number(System::block_number() + 1);
+        System::on_initialize(System::block_number());
+    }
+}
@@ -0,0 +1,106 @@
+use crate::mock::*;
+use super::*;
+use frame_support::{assert_ok, assert_noop};
+use sp_runtime::DispatchError;
+
+const LP_TOKEN: u64 = 2;
+const REWARD_TOKEN: u64 = 3;
+
+/// Funds a pool of token 0 in exchange 0 with 1000 LP tokens for each of accounts 1 and 2,
+/// and puts 100_000 of currency 1 in the reward pot.
+fn before_farm() {
+	Tao::create_tao(Origin::signed(1), [0].to_vec());
+	Tao::create_tao_item(Origin::signed(1), 0, false, [0].to_vec());
+	Tao::mint(Origin::signed(1), 0, 0, 1000, 1);
+
+	Currency::create(Origin::signed(1), [0].to_vec());
+	Currency::mint(Origin::signed(1), 0, 2000, 1);
+	assert_ok!(Dex::create_exchange(Origin::signed(1), 0));
+
+	run_to_block(10);
+	assert_ok!(Dex::add_liquidity(Origin::signed(1), 0, 0, 1, [0].to_vec(), [100].to_vec(), [1000].to_vec(), 20, false));
+	assert_ok!(Dex::add_liquidity(Origin::signed(1), 0, 0, 2, [0].to_vec(), [100].to_vec(), [1000].to_vec(), 20, false));
+	assert_eq!(Dex::liquidity_tokens(0, 0), Some(LP_TOKEN));
+
+	Currency::create(Origin::signed(1), [1].to_vec());
+	Currency::mint(Origin::signed(1), 1, 100_000, 1);
+	assert_ok!(Farming::fund_rewards(Origin::signed(1), 1, 100_000));
+	assert_eq!(Token::balance_of(&Farming::account_id(), &REWARD_TOKEN), 100_000);
+}
+
+#[test]
+fn set_reward_rate_requires_governance() {
+	new_test_ext().execute_with(|| {
+		before_farm();
+
+		assert_noop!(Farming::set_reward_rate(Origin::signed(1), 0, 0, 1, 10), DispatchError::BadOrigin);
+		assert_noop!(Farming::set_reward_rate(Origin::root(), 1, 0, 1, 10), Error::<Test>::InvalidExchangeId);
+
+		assert_ok!(Farming::set_reward_rate(Origin::root(), 0, 0, 1, 10));
+		assert_eq!(Farming::farms(0, 0).unwrap().reward_per_block, 10);
+		assert_noop!(Farming::set_reward_rate(Origin::root(), 0, 0, 0, 10), Error::<Test>::RewardCurrencyMismatch);
+	});
+}
+
+#[test]
+fn rewards_are_shared_pro_rata() {
+	new_test_ext().execute_with(|| {
+		before_farm();
+		assert_ok!(Farming::set_reward_rate(Origin::root(), 0, 0, 1, 10));
+
+		assert_ok!(Farming::stake(Origin::signed(1), 0, 0, 1000));
+		run_to_block(20);
+		assert_eq!(Farming::pending_reward(0, 0, &1), 100);
+
+		assert_ok!(Farming::stake(Origin::signed(2), 0, 0, 1000));
+		assert_eq!(Farming::pending_reward(0, 0, &2), 0);
+		run_to_block(30);
+		assert_eq!(Farming::pending_reward(0, 0, &1), 150);
+		assert_eq!(Farming::pending_reward(0, 0, &2), 50);
+
+		assert_ok!(Farming::unstake(Origin::signed(1), 0, 0, 1000));
+		assert_eq!(Token::balance_of(&1, &REWARD_TOKEN), 150);
+		assert_eq!(Token::balance_of(&1, &LP_TOKEN), 1000);
+		assert!(!Stakes::<Test>::contains_key((0u64, 0u64), 1u64));
+
+		run_to_block(40);
+		assert_ok!(Farming::claim(Origin::signed(2), 0, 0));
+		assert_eq!(Token::balance_of(&2, &REWARD_TOKEN), 150);
+		assert_eq!(Farming::pending_reward(0, 0, &2), 0);
+		assert_eq!(Farming::farms(0, 0).unwrap().total_staked, 1000);
+	});
+}
+
+#[test]
+fn rate_changes_apply_from_the_current_block() {
+	new_test_ext().execute_with(|| {
+		before_farm();
+		assert_ok!(Farming::set_reward_rate(Origin::root(), 0, 0, 1, 10));
+		assert_ok!(Farming::stake(Origin::signed(1), 0, 0, 500));
+
+		run_to_block(20);
+		assert_ok!(Farming::set_reward_rate(Origin::root(), 0, 0, 1, 20));
+		run_to_block(25);
+		assert_eq!(Farming::pending_reward(0, 0, &1), 100 + 100);
+
+		assert_noop!(Farming::unstake(Origin::signed(1), 0, 0, 501), Error::<Test>::InsufficientStake);
+	});
+}
+
+#[test]
+fn emergency_unstake_forfeits_rewards() {
+	new_test_ext().execute_with(|| {
+		before_farm();
+		assert_ok!(Farming::set_reward_rate(Origin::root(), 0, 0, 1, 100_000));
+		assert_ok!(Farming::stake(Origin::signed(1), 0, 0, 1000));
+
+		// The pot cannot cover two blocks of rewards.
+		run_to_block(12);
+		assert!(Farming::claim(Origin::signed(1), 0, 0).is_err());
+
+		assert_ok!(Farming::emergency_unstake(Origin::signed(1), 0, 0));
+		assert_eq!(Token::balance_of(&1, &LP_TOKEN), 1000);
+		assert_eq!(Token::balance_of(&1, &REWARD_TOKEN), 0);
+		assert_eq!(Farming::farms(0, 0).unwrap().total_staked, 0);
+	});
+}
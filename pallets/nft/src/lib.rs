@@ -3,7 +3,7 @@
 use codec::{Encode, Decode};
 use frame_support::{
 	decl_module, decl_storage, decl_error, decl_event, ensure,
-	StorageValue, StorageMap, Parameter,
+	StorageValue, StorageMap, StorageDoubleMap, Parameter, transactional,
 };
 use frame_system::ensure_signed;
 use sp_runtime::{
@@ -77,6 +77,12 @@ decl_storage! {
 decl_event!(
 	pub enum Event<T> where AccountId = <T as frame_system::Trait>::AccountId {
 		CollectionCreated(CollectionId, AccountId),
+		/// Collection, asset, owner
+		Minted(CollectionId, AssetId, AccountId),
+		/// Collection, asset, from, to
+		Transferred(CollectionId, AssetId, AccountId, AccountId),
+		/// Collection, asset, owner
+		Burned(CollectionId, AssetId, AccountId),
 	}
 );
 
@@ -87,6 +93,8 @@ decl_error! {
 		InvalidCollectionId,
 		CollectionNotFound,
 		NumOverflow,
+		AssetNotFound,
+		NotAssetOwner,
 	}
 }
 
@@ -105,21 +113,7 @@ decl_module! {
 		) -> DispatchResult {
 			let who = ensure_signed(origin)?;
 
-			let collection_id = Self::next_collection_id();
-			NextCollectionId::mutate(|id| *id += <CollectionId as One>::one());
-	
-			let token_id = token::Module::<T>::create_token(&who, false, &token_uri)?;
-	
-			let collection_info = CollectionInfo {
-				owner: who.clone(),
-				token: token_id,
-				total_supply: Default::default(),
-				properties,
-			};
-	
-			Collections::<T>::insert(collection_id, collection_info);
-
-			Self::deposit_event(RawEvent::CollectionCreated(collection_id, who));
+			Self::do_create_collection(&who, &token_uri, properties)?;
 			Ok(())
 		}
 
@@ -132,46 +126,141 @@ decl_module! {
 			properties: Vec<u8>,
 		) -> DispatchResult {
 			let who = ensure_signed(origin)?;
-			
-			let collection = Self::collections(collection_id).ok_or(Error::<T>::InvalidCollectionId)?;
-	
-			let asset_id = Self::next_asset_id(collection_id);
-	
-			let new_nft_data = NftAssetData {
+
+			let data = NftAssetData {
 				name: name,
 				description: description,
 				properties: properties,
 			};
-			let new_nft_data = Into::<NftAssetData>::into(new_nft_data);
-	
-			let new_asset_info = AssetInfo {
-				owner: who.clone(),
-				data: new_nft_data,
-			};
-	
-			token::Module::<T>::mint(&who, &collection.token, One::one())?;
-	
-			Collections::<T>::try_mutate(collection_id, |collection_info| -> DispatchResult {
-				let info = collection_info
-					.as_mut()
-					.ok_or(Error::<T>::CollectionNotFound)?;
-				info.total_supply = info
-					.total_supply
-					.checked_add(One::one())
-					.ok_or(Error::<T>::NumOverflow)?;
-				Ok(())
-			})?;
-	
-			NftAssets::<T>::insert(collection_id, asset_id, new_asset_info);
-			NftOwner::<T>::insert(collection_id, asset_id, who);
+			Self::do_mint(&who, collection_id, data)?;
 
 			Ok(())
 		}
+
+		#[weight = 0]
+		#[transactional]
+		pub fn transfer(
+			origin,
+			to: T::AccountId,
+			collection_id: CollectionId,
+			asset_id: AssetId,
+		) -> DispatchResult {
+			let who = ensure_signed(origin)?;
+
+			Self::do_transfer(&who, &to, collection_id, asset_id)
+		}
 	}
 }
 
 impl<T: Trait> Module<T> {
+	pub fn do_create_collection(
+		who: &T::AccountId,
+		token_uri: &Vec<u8>,
+		properties: Vec<u8>,
+	) -> Result<CollectionId, DispatchError> {
+		let collection_id = Self::next_collection_id();
+		NextCollectionId::mutate(|id| *id += <CollectionId as One>::one());
+
+		let token_id = token::Module::<T>::create_token(who, false, token_uri)?;
+
+		let collection_info = CollectionInfo {
+			owner: who.clone(),
+			token: token_id,
+			total_supply: Default::default(),
+			properties,
+		};
+
+		Collections::<T>::insert(collection_id, collection_info);
+
+		Self::deposit_event(RawEvent::CollectionCreated(collection_id, who.clone()));
+		Ok(collection_id)
+	}
+
+	pub fn do_mint(
+		who: &T::AccountId,
+		collection_id: CollectionId,
+		data: NftAssetData,
+	) -> Result<AssetId, DispatchError> {
+		let collection = Self::collections(collection_id).ok_or(Error::<T>::InvalidCollectionId)?;
+
+		let asset_id = Self::next_asset_id(collection_id);
+		let next_asset_id = asset_id.checked_add(One::one()).ok_or(Error::<T>::NumOverflow)?;
+
+		let new_asset_info = AssetInfo {
+			owner: who.clone(),
+			data,
+		};
+
+		token::Module::<T>::mint(who, &collection.token, One::one())?;
+
+		Collections::<T>::try_mutate(collection_id, |collection_info| -> DispatchResult {
+			let info = collection_info
+				.as_mut()
+				.ok_or(Error::<T>::CollectionNotFound)?;
+			info.total_supply = info
+				.total_supply
+				.checked_add(One::one())
+				.ok_or(Error::<T>::NumOverflow)?;
+			Ok(())
+		})?;
+
+		NextAssetId::insert(collection_id, next_asset_id);
+		NftAssets::<T>::insert(collection_id, asset_id, new_asset_info);
+		NftOwner::<T>::insert(collection_id, asset_id, who.clone());
+
+		Self::deposit_event(RawEvent::Minted(collection_id, asset_id, who.clone()));
+		Ok(asset_id)
+	}
+
+	/// Moves an asset and its unit of the collection token from `from` to `to`.
+	pub fn do_transfer(
+		from: &T::AccountId,
+		to: &T::AccountId,
+		collection_id: CollectionId,
+		asset_id: AssetId,
+	) -> DispatchResult {
+		let collection = Self::collections(collection_id).ok_or(Error::<T>::InvalidCollectionId)?;
+
+		let asset = Self::nft_assets(collection_id, asset_id).ok_or(Error::<T>::AssetNotFound)?;
+		ensure!(asset.owner == *from, Error::<T>::NotAssetOwner);
+
+		token::Module::<T>::transfer_from(from, to, &collection.token, One::one())?;
 
+		NftAssets::<T>::insert(collection_id, asset_id, AssetInfo { owner: to.clone(), ..asset });
+		NftOwner::<T>::insert(collection_id, asset_id, to.clone());
+
+		Self::deposit_event(RawEvent::Transferred(collection_id, asset_id, from.clone(), to.clone()));
+		Ok(())
+	}
+
+	pub fn do_burn(
+		who: &T::AccountId,
+		collection_id: CollectionId,
+		asset_id: AssetId,
+	) -> DispatchResult {
+		let collection = Self::collections(collection_id).ok_or(Error::<T>::InvalidCollectionId)?;
+		let asset = Self::nft_assets(collection_id, asset_id).ok_or(Error::<T>::AssetNotFound)?;
+		ensure!(asset.owner == *who, Error::<T>::NotAssetOwner);
+
+		token::Module::<T>::burn(who, &collection.token, One::one())?;
+
+		Collections::<T>::try_mutate(collection_id, |collection_info| -> DispatchResult {
+			let info = collection_info
+				.as_mut()
+				.ok_or(Error::<T>::CollectionNotFound)?;
+			info.total_supply = info
+				.total_supply
+				.checked_sub(One::one())
+				.ok_or(Error::<T>::NumOverflow)?;
+			Ok(())
+		})?;
+
+		NftAssets::<T>::remove(collection_id, asset_id);
+		NftOwner::<T>::remove(collection_id, asset_id);
+
+		Self::deposit_event(RawEvent::Burned(collection_id, asset_id, who.clone()));
+		Ok(())
+	}
 }
 
 // pub trait FungibleAsset<AssetId, AccountId> {
@@ -46,3 +46,6 @@ pub type BlockId = generic::BlockId<Block>;
 
 ///Currency ID
 pub type CurrencyId = u64;
+
+///Exchange ID
+pub type ExchangeId = u64;
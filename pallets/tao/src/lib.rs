@@ -117,7 +117,7 @@ decl_module! {
 
 			let tao_item = Self::tao_items(tao_id, item_id).ok_or(Error::<T>::InvalidTaoId)?;
 
-			token::Module::<T>::mint(&to, &tao_item.token, amount);
+			token::Module::<T>::mint(&to, &tao_item.token, amount)?;
 
 			Self::deposit_event(RawEvent::Mint(tao_id, item_id, amount, to));
 
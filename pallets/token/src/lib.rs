@@ -3,13 +3,13 @@
 use codec::{Codec, Encode, Decode};
 use frame_support::{
 	decl_module, decl_storage, decl_error, decl_event, ensure, StorageValue, StorageMap, Parameter,
-	weights::Weight, 
-	dispatch::{DispatchResult, DispatchError},
+	IterableStorageDoubleMap, weights::Weight, traits::Get,
+	dispatch::{DispatchResult, DispatchError}, transactional,
 };
 use sp_runtime::{
 	traits::{
 		AtLeast32Bit, MaybeSerializeDeserialize, Bounded, Member,
-		One, AtLeast32BitUnsigned, CheckedAdd, CheckedSub,
+		One, AtLeast32BitUnsigned, CheckedAdd, CheckedSub, Saturating,
 	},
 	RuntimeDebug,
 };
@@ -17,6 +17,7 @@ use sp_runtime::{
 // use sp_std::prelude::*;
 use sp_std::vec::Vec;
 use sp_std::vec;
+use sp_std::collections::btree_map::BTreeMap;
 
 /// The module's configuration trait.
 pub trait Trait: frame_system::Trait {
@@ -46,6 +47,10 @@ decl_storage! {
 
 		pub Balances get(fn balances):
 			double_map hasher(twox_64_concat) T::TokenId, hasher(twox_64_concat) T::AccountId => T::TokenBalance;
+		/// The amount of each token in circulation.
+		pub TotalSupply get(fn total_supply): map hasher(blake2_128_concat) T::TokenId => T::TokenBalance;
+		/// Whether the supply of tokens minted before it was tracked has been counted.
+		SupplyMigrated: bool;
 	}
 }
 
@@ -55,6 +60,8 @@ decl_error! {
 		InsufficientBalance,
 		NumOverflow,
 		InvalidArrayLength,
+		/// A non-fungible token balance or supply may not exceed one.
+		NonFungibleAmount,
 	}
 }
 
@@ -80,6 +87,10 @@ decl_module! {
 		type Error = Error<T>;
 
 		fn deposit_event() = default;
+
+		fn on_runtime_upgrade() -> Weight {
+			Self::migrate_supply()
+		}
 	}
 }
 
@@ -102,16 +113,75 @@ impl<T: Trait> Module<T> {
 		Ok(token_id)
 	}
 
+	/// Whether `id` is a fungible token. Unknown tokens are treated as fungible.
+	pub fn is_fungible(id: &T::TokenId) -> bool {
+		Self::tokens(id).map_or(true, |token| !token.is_nf)
+	}
+
+	/// Reclassify a token as fungible or non-fungible.
+	pub fn set_fungible(id: &T::TokenId, fungible: bool) -> DispatchResult {
+		Tokens::<T>::try_mutate(id, |token| -> DispatchResult {
+			let token = token.as_mut().ok_or(Error::<T>::InvalidTokenId)?;
+			token.is_nf = !fungible;
+			Ok(())
+		})
+	}
+
+	fn ensure_holdable(id: &T::TokenId, balance: T::TokenBalance) -> DispatchResult {
+		ensure!(balance <= One::one() || Self::is_fungible(id), Error::<T>::NonFungibleAmount);
+		Ok(())
+	}
+
+	/// Adds `amount` to the supply of `id`, so at most one of a non-fungible token exists.
+	fn increase_supply(id: &T::TokenId, amount: T::TokenBalance) -> DispatchResult {
+		TotalSupply::<T>::try_mutate(id, |supply| -> DispatchResult {
+			*supply = supply
+				.checked_add(&amount)
+				.ok_or(Error::<T>::NumOverflow)?;
+			Self::ensure_holdable(id, *supply)
+		})
+	}
+
+	fn decrease_supply(id: &T::TokenId, amount: T::TokenBalance) {
+		TotalSupply::<T>::mutate(id, |supply| *supply = supply.saturating_sub(amount));
+	}
+
+	/// Counts the supply of every token from its holders' balances, once.
+	fn migrate_supply() -> Weight {
+		if SupplyMigrated::get() {
+			return 0;
+		}
+
+		let mut count: Weight = 0;
+		let mut supplies = BTreeMap::<T::TokenId, T::TokenBalance>::new();
+		for (id, _, balance) in Balances::<T>::iter() {
+			count += 1;
+			let supply = supplies.entry(id).or_insert_with(Default::default);
+			*supply = supply.saturating_add(balance);
+		}
+
+		let tokens = supplies.len() as Weight;
+		for (id, supply) in supplies {
+			TotalSupply::<T>::insert(id, supply);
+		}
+		SupplyMigrated::put(true);
+
+		T::DbWeight::get().reads_writes(count + 1, tokens + 1)
+	}
+
+	/// Rolls back if the balance or the supply would overflow.
+	#[transactional]
 	pub fn mint(
 		to: &T::AccountId,
 		id: &T::TokenId,
 		amount: T::TokenBalance
 	) -> DispatchResult {
+		Self::increase_supply(id, amount)?;
 		Balances::<T>::try_mutate(id, to, |balance| -> DispatchResult {
 			*balance = balance
 				.checked_add(&amount)
 				.ok_or(Error::<T>::NumOverflow)?;
-			Ok(())
+			Self::ensure_holdable(id, *balance)
 		})?;
 
 		Self::deposit_event(RawEvent::Mint(to.clone(), id.clone(), amount));
@@ -119,6 +189,8 @@ impl<T: Trait> Module<T> {
 		Ok(())
 	}
 
+	/// Rolls back every mint of the batch if one of them fails.
+	#[transactional]
 	pub fn batch_mint(
 		to: &T::AccountId,
 		ids: &Vec<T::TokenId>,
@@ -132,11 +204,12 @@ impl<T: Trait> Module<T> {
 			let id = ids[i];
 			let amount = amounts[i];
 
+			Self::increase_supply(&id, amount)?;
 			Balances::<T>::try_mutate(id, to, |balance| -> DispatchResult {
 				*balance = balance
 					.checked_add(&amount)
 					.ok_or(Error::<T>::NumOverflow)?;
-				Ok(())
+				Self::ensure_holdable(&id, *balance)
 			})?;
 		}
 
@@ -156,6 +229,7 @@ impl<T: Trait> Module<T> {
 				.ok_or(Error::<T>::NumOverflow)?;
 			Ok(())
 		})?;
+		Self::decrease_supply(id, amount);
 
 		Self::deposit_event(RawEvent::Burn(from.clone(), id.clone(), amount));
 
@@ -181,6 +255,7 @@ impl<T: Trait> Module<T> {
 					.ok_or(Error::<T>::NumOverflow)?;
 				Ok(())
 			})?;
+			Self::decrease_supply(&id, amount);
 		}
 
 		Self::deposit_event(RawEvent::BatchBurn(from.clone(), ids.clone(), amounts));
@@ -198,25 +273,25 @@ impl<T: Trait> Module<T> {
 			return Ok(());
 		}
 
-		Balances::<T>::try_mutate(id, from, |balance| -> DispatchResult {
-			*balance = balance
-				.checked_sub(&amount)
-				.ok_or(Error::<T>::NumOverflow)?;
-			Ok(())
-		})?;
+		// Both balances are checked before either is written, callers need not be transactional.
+		let from_balance = Self::balances(id, from)
+			.checked_sub(&amount)
+			.ok_or(Error::<T>::NumOverflow)?;
+		let to_balance = Self::balances(id, to)
+			.checked_add(&amount)
+			.ok_or(Error::<T>::NumOverflow)?;
+		Self::ensure_holdable(id, to_balance)?;
 
-		Balances::<T>::try_mutate(id, to, |balance| -> DispatchResult {
-			*balance = balance
-				.checked_add(&amount)
-				.ok_or(Error::<T>::NumOverflow)?;
-			Ok(())
-		})?;
+		Balances::<T>::insert(id, from, from_balance);
+		Balances::<T>::insert(id, to, to_balance);
 
 		Self::deposit_event(RawEvent::Transferred(from.clone(), to.clone(), id.clone(), amount));
 
 		Ok(())
 	}
 
+	/// Rolls back every transfer of the batch if one of them fails.
+	#[transactional]
 	pub fn batch_transfer_from(
 		from: &T::AccountId,
 		to: &T::AccountId,
@@ -246,7 +321,7 @@ impl<T: Trait> Module<T> {
 				*balance = balance
 					.checked_add(&amount)
 					.ok_or(Error::<T>::NumOverflow)?;
-				Ok(())
+				Self::ensure_holdable(id, *balance)
 			})?;
 		}
 
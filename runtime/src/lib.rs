@@ -95,10 +95,10 @@ pub const VERSION: RuntimeVersion = RuntimeVersion {
 	spec_name: create_runtime_str!("node-template"),
 	impl_name: create_runtime_str!("node-template"),
 	authoring_version: 1,
-	spec_version: 1,
+	spec_version: 3,
 	impl_version: 1,
 	apis: RUNTIME_API_VERSIONS,
-	transaction_version: 1,
+	transaction_version: 3,
 };
 
 pub const MILLISECS_PER_BLOCK: u64 = 4000;
@@ -277,15 +277,40 @@ impl pallet_token::Trait for Runtime {
 parameter_types! {
 	pub const DexModuleId: ModuleId = ModuleId(*b"spr/dexm");
 	pub const CommodityModuleId: ModuleId = ModuleId(*b"spr/comd");
+	pub const MaxPriceObservations: u32 = 256;
+	pub const MaxOrdersPerPool: u32 = 64;
+	pub const OrderDeposit: Balance = 1_000;
+	pub const MaxBatchSwapsPerPool: u32 = 64;
+	pub const MaxBatchSwapsPerBlock: u32 = 512;
+	pub const MinimumLiquidity: u128 = 1_000;
+	pub const DexMinPoolSize: u128 = 10_000;
+	pub const RevealWindow: BlockNumber = 10;
+	pub const ReferralFeeShare: u32 = 2_000;
 }
 
 impl pallet_dex::Trait for Runtime {
 	type Event = Event;
 	type ModuleId = DexModuleId;
+	type MaxPriceObservations = MaxPriceObservations;
+	type Call = Call;
+	type MaxOrdersPerPool = MaxOrdersPerPool;
+	type OrderDeposit = OrderDeposit;
+	type MaxBatchSwapsPerPool = MaxBatchSwapsPerPool;
+	type MaxBatchSwapsPerBlock = MaxBatchSwapsPerBlock;
+	type MinimumLiquidity = MinimumLiquidity;
+	type DefaultMinPoolSize = DexMinPoolSize;
+	type RevealWindow = RevealWindow;
+	type DepositCurrency = Balances;
+	type ReferralFeeShare = ReferralFeeShare;
+}
+
+parameter_types! {
+	pub const CurrencyModuleId: ModuleId = ModuleId(*b"spr/curr");
 }
 
 impl pallet_currency::Trait for Runtime {
 	type Event = Event;
+	type ModuleId = CurrencyModuleId;
 }
 
 impl pallet_nft::Trait for Runtime {
@@ -304,6 +329,16 @@ impl pallet_tao::Trait for Runtime {
 	type TaoId = u64;
 }
 
+parameter_types! {
+	pub const FarmingModuleId: ModuleId = ModuleId(*b"spr/farm");
+}
+
+impl pallet_farming::Trait for Runtime {
+	type Event = Event;
+	type ModuleId = FarmingModuleId;
+	type GovernanceOrigin = frame_system::EnsureRoot<AccountId>;
+}
+
 // Create the runtime by composing the FRAME pallets that were previously configured.
 construct_runtime!(
 	pub enum Runtime where
@@ -327,6 +362,7 @@ construct_runtime!(
 		NftModule: pallet_nft::{Module, Call, Storage, Event<T>},
 		CommodityModule: pallet_commodity::{Module, Call, Storage, Event<T>},
 		TaoModule: pallet_tao::{Module, Call, Storage, Event<T>},
+		FarmingModule: pallet_farming::{Module, Call, Storage, Event<T>},
 	}
 );
 
@@ -478,6 +514,44 @@ impl_runtime_apis! {
 		}
 	}
 
+	impl pallet_dex_runtime_api::DexApi<Block, AccountId, u64, u128> for Runtime {
+		fn exchange_for_currency(currency_id: pallet_dex::CurrencyId) -> Option<(pallet_dex::ExchangeId, Vec<u64>)> {
+			DexModule::exchange_for_currency(currency_id)
+		}
+
+		fn quote_exact_in(
+			markets: Vec<(pallet_dex::ExchangeId, pallet_dex::CurrencyId)>,
+			path: Vec<u64>,
+			amount_in: u128,
+		) -> Option<u128> {
+			DexModule::quote_exact_in(markets, path, amount_in)
+		}
+
+		fn quote_exact_out(
+			markets: Vec<(pallet_dex::ExchangeId, pallet_dex::CurrencyId)>,
+			path: Vec<u64>,
+			amount_out: u128,
+		) -> Option<u128> {
+			DexModule::quote_exact_out(markets, path, amount_out)
+		}
+
+		fn referral_earnings(referrer: AccountId) -> Vec<(u64, u128)> {
+			DexModule::referral_earnings_of(&referrer)
+		}
+
+		fn pool_volume(exchange_id: pallet_dex::ExchangeId, token_id: u64, window: BlockNumber) -> Option<(u128, u128)> {
+			DexModule::pool_volume(exchange_id, token_id, window)
+		}
+
+		fn fee_apr(exchange_id: pallet_dex::ExchangeId, token_id: u64, window: BlockNumber) -> Option<u32> {
+			DexModule::fee_apr(exchange_id, token_id, window)
+		}
+
+		fn impermanent_loss(exchange_id: pallet_dex::ExchangeId, token_id: u64, entry: (u128, u128, u128)) -> Option<i32> {
+			DexModule::impermanent_loss(exchange_id, token_id, entry)
+		}
+	}
+
 	impl pallet_transaction_payment_rpc_runtime_api::TransactionPaymentApi<Block, Balance> for Runtime {
 		fn query_info(
 			uxt: <Block as BlockT>::Extrinsic,